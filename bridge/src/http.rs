@@ -0,0 +1,85 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use relay_cli::{Conversation, RelayClient};
+use serde::Deserialize;
+use serde_json::json;
+use warp::http::StatusCode;
+use warp::Filter;
+
+// Maximum accepted request body, in bytes
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+// `text` is accepted as well, since that is what most CI and monitoring
+// tools send to chat webhooks
+#[derive(Deserialize, Debug)]
+pub struct IncomingPayload {
+    #[serde(alias = "text")]
+    pub content: String,
+}
+
+// Serves `POST /messages`, turning each JSON payload into a `create_message`
+// call on the bridged conversation's cell
+pub async fn serve(
+    client: Arc<RelayClient>,
+    conversation: Conversation,
+    token: Option<String>,
+    listen: SocketAddr,
+) {
+    let route = warp::post()
+        .and(warp::path("messages"))
+        .and(warp::path::end())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json())
+        .and_then(move |authorization: Option<String>, payload: IncomingPayload| {
+            let client = client.clone();
+            let conversation = conversation.clone();
+            let token = token.clone();
+            async move {
+                Ok::<_, Infallible>(
+                    post_message(&client, &conversation, token, authorization, payload).await,
+                )
+            }
+        });
+
+    warp::serve(route).run(listen).await;
+}
+
+async fn post_message(
+    client: &RelayClient,
+    conversation: &Conversation,
+    token: Option<String>,
+    authorization: Option<String>,
+    payload: IncomingPayload,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    if let Some(token) = token {
+        if authorization != Some(format!("Bearer {token}")) {
+            return warp::reply::with_status(
+                warp::reply::json(&json!({ "error": "unauthorized" })),
+                StatusCode::UNAUTHORIZED,
+            );
+        }
+    }
+    if payload.content.trim().is_empty() {
+        return warp::reply::with_status(
+            warp::reply::json(&json!({ "error": "content must not be empty" })),
+            StatusCode::BAD_REQUEST,
+        );
+    }
+
+    match client.send_message(conversation, payload.content).await {
+        Ok(record) => warp::reply::with_status(
+            warp::reply::json(&json!({ "action_hash": record.action_address().to_string() })),
+            StatusCode::CREATED,
+        ),
+        Err(err) => {
+            log::error!("Failed to create message: {err:?}");
+            warp::reply::with_status(
+                warp::reply::json(&json!({ "error": err.to_string() })),
+                StatusCode::BAD_GATEWAY,
+            )
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use clap::Parser;
+use relay_cli::{RelayClient, APP_ID};
+
+mod http;
+mod webhook;
+
+#[derive(Parser)]
+#[command(
+    name = "relay-bridge",
+    version,
+    about = "Post into a Volla Messages conversation over HTTP and forward its messages to a webhook"
+)]
+struct Args {
+    /// Admin websocket port of the running conductor
+    #[arg(long, env = "RELAY_ADMIN_PORT")]
+    admin_port: u16,
+
+    /// App websocket port; a new app interface is attached if omitted
+    #[arg(long, env = "RELAY_APP_PORT")]
+    app_port: Option<u16>,
+
+    #[arg(long, env = "RELAY_APP_ID", default_value = APP_ID)]
+    app_id: String,
+
+    /// Network seed or title of the conversation to bridge
+    #[arg(long, env = "RELAY_BRIDGE_CONVERSATION")]
+    conversation: String,
+
+    /// Address the HTTP endpoint listens on
+    #[arg(long, env = "RELAY_BRIDGE_LISTEN", default_value = "127.0.0.1:8787")]
+    listen: SocketAddr,
+
+    /// Bearer token required on incoming requests
+    #[arg(long, env = "RELAY_BRIDGE_TOKEN")]
+    token: Option<String>,
+
+    /// URL that messages received in the conversation are posted to
+    #[arg(long, env = "RELAY_BRIDGE_WEBHOOK_URL")]
+    webhook_url: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let args = Args::parse();
+
+    let client = RelayClient::connect(args.admin_port, args.app_port, &args.app_id).await?;
+    let conversation = client.find_conversation(&args.conversation)?;
+    log::info!("Bridging conversation {} ({})", conversation.title, conversation.id);
+
+    let client = Arc::new(client);
+
+    if let Some(url) = args.webhook_url {
+        webhook::forward_messages(client.clone(), conversation.clone(), url).await;
+    }
+
+    log::info!("Listening on http://{}", args.listen);
+    http::serve(client, conversation, args.token, args.listen).await;
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use holochain_types::prelude::*;
+use relay_cli::{Conversation, RelayClient};
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+#[derive(Serialize, Debug)]
+struct OutgoingPayload {
+    conversation_id: String,
+    conversation_title: String,
+    action_hash: String,
+    from: String,
+    timestamp: String,
+    content: String,
+    mentioned: bool,
+}
+
+// Posts every `Signal::Message` and `Signal::Mentioned` received in `conversation` to `url`.
+// Deliveries happen one at a time, in the order the signals arrived.
+pub async fn forward_messages(client: Arc<RelayClient>, conversation: Conversation, url: String) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<OutgoingPayload>();
+
+    let cell_id = conversation.cell_id.clone();
+    client
+        .on_message(move |incoming| {
+            if incoming.cell_id != cell_id {
+                return;
+            }
+            let payload = OutgoingPayload {
+                conversation_id: conversation.id.clone(),
+                conversation_title: conversation.title.clone(),
+                action_hash: incoming.action.as_hash().to_string(),
+                from: incoming.from.to_string(),
+                timestamp: incoming.action.action().timestamp().to_string(),
+                content: incoming.message.content,
+                mentioned: incoming.mentioned,
+            };
+            let _ = tx.send(payload);
+        })
+        .await;
+
+    tokio::spawn(async move {
+        let http = reqwest::Client::new();
+        while let Some(payload) = rx.recv().await {
+            match http.post(&url).json(&payload).send().await {
+                Ok(response) if !response.status().is_success() => {
+                    log::warn!("Webhook responded with {}", response.status());
+                }
+                Ok(_) => {}
+                Err(err) => log::error!("Failed to deliver message to webhook: {err:?}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,321 @@
+use std::net::Ipv4Addr;
+
+use anyhow::anyhow;
+use holochain_client::{
+    AdminWebsocket, AppInfo, AppWebsocket, AuthorizeSigningCredentialsPayload, CellInfo,
+    ClientAgentSigner, ClonedCell, IssueAppAuthenticationTokenPayload, ZomeCallTarget,
+};
+use holochain_types::prelude::*;
+use holochain_types::signal::Signal;
+use holochain_types::websocket::AllowedOrigins;
+use relay_integrity::{Contact, ContactRecord, Message, MessageRecord};
+
+use crate::{IncomingMessage, RelaySignal, BUCKET_RANGE_MS, ROLE_NAME, ZOME_NAME};
+
+// Mirrors `SendMessageInput` in the relay coordinator zome
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SendMessageInput {
+    message: Message,
+    agents: Vec<AgentPubKey>,
+}
+
+// The DNA properties the UI sets when it clones a conversation cell.
+// `privacy` and `progenitor` are also present but not needed here.
+#[derive(Serialize, Deserialize, Debug, SerializedBytes)]
+struct ConversationProperties {
+    created: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    pub id: String, // the network seed
+    pub title: String,
+    pub cell_id: CellId,
+    pub created: i64, // milliseconds since the unix epoch
+    pub enabled: bool,
+}
+
+impl Conversation {
+    fn from_cloned_cell(cell: &ClonedCell) -> Self {
+        let created = ConversationProperties::try_from(cell.dna_modifiers.properties.clone())
+            .map(|p| p.created)
+            .unwrap_or_default();
+        Self {
+            id: cell.dna_modifiers.network_seed.clone(),
+            title: cell.name.clone(),
+            cell_id: cell.cell_id.clone(),
+            created,
+            enabled: cell.enabled,
+        }
+    }
+
+    // Same bucketing as ConversationStore.bucketFromTimestamp in the UI
+    pub fn bucket_for(&self, timestamp: Timestamp) -> u32 {
+        let diff = timestamp.as_micros() / 1000 - self.created;
+        (diff as f64 / BUCKET_RANGE_MS as f64).round().max(0.0) as u32
+    }
+
+    pub fn current_bucket(&self) -> u32 {
+        self.bucket_for(Timestamp::now())
+    }
+}
+
+pub fn api_err(e: impl std::fmt::Debug) -> anyhow::Error {
+    anyhow!("Conductor API error: {e:?}")
+}
+
+pub struct RelayClient {
+    admin_ws: AdminWebsocket,
+    app_ws: AppWebsocket,
+    signer: ClientAgentSigner,
+    app_info: AppInfo,
+}
+
+impl RelayClient {
+    // Connects to a running conductor through its admin websocket.
+    // If no app port is given, a new app interface is attached for `app_id`.
+    pub async fn connect(
+        admin_port: u16,
+        app_port: Option<u16>,
+        app_id: &str,
+    ) -> anyhow::Result<Self> {
+        let admin_ws = AdminWebsocket::connect((Ipv4Addr::LOCALHOST, admin_port))
+            .await
+            .map_err(api_err)?;
+
+        let app_port = match app_port {
+            Some(port) => port,
+            None => admin_ws
+                .attach_app_interface(0, AllowedOrigins::Any, Some(app_id.to_string()))
+                .await
+                .map_err(api_err)?,
+        };
+
+        let issued = admin_ws
+            .issue_app_auth_token(IssueAppAuthenticationTokenPayload::for_installed_app_id(
+                app_id.to_string(),
+            ))
+            .await
+            .map_err(api_err)?;
+
+        let signer = ClientAgentSigner::default();
+        let app_ws = AppWebsocket::connect(
+            (Ipv4Addr::LOCALHOST, app_port),
+            issued.token,
+            signer.clone().into(),
+        )
+        .await
+        .map_err(api_err)?;
+
+        let app_info = app_ws
+            .app_info()
+            .await
+            .map_err(api_err)?
+            .ok_or(anyhow!("App {app_id} is not installed"))?;
+
+        let client = Self {
+            admin_ws,
+            app_ws,
+            signer,
+            app_info,
+        };
+        for cell_id in client.cell_ids() {
+            client.authorize(cell_id).await?;
+        }
+        Ok(client)
+    }
+
+    async fn authorize(&self, cell_id: CellId) -> anyhow::Result<()> {
+        let credentials = self
+            .admin_ws
+            .authorize_signing_credentials(AuthorizeSigningCredentialsPayload {
+                cell_id: cell_id.clone(),
+                functions: None,
+            })
+            .await
+            .map_err(api_err)?;
+        self.signer.add_credentials(cell_id, credentials);
+        Ok(())
+    }
+
+    fn cell_infos(&self) -> &[CellInfo] {
+        self.app_info
+            .cell_info
+            .get(ROLE_NAME)
+            .map(|cells| cells.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn cell_ids(&self) -> Vec<CellId> {
+        self.cell_infos()
+            .iter()
+            .filter_map(|c| match c {
+                CellInfo::Provisioned(cell) => Some(cell.cell_id.clone()),
+                CellInfo::Cloned(cell) if cell.enabled => Some(cell.cell_id.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn my_pub_key(&self) -> &AgentPubKey {
+        &self.app_info.agent_pub_key
+    }
+
+    // The provisioned cell, which holds the contacts
+    pub fn main_cell_id(&self) -> anyhow::Result<CellId> {
+        self.cell_infos()
+            .iter()
+            .find_map(|c| match c {
+                CellInfo::Provisioned(cell) => Some(cell.cell_id.clone()),
+                _ => None,
+            })
+            .ok_or(anyhow!("No provisioned {ROLE_NAME} cell found"))
+    }
+
+    /********* Zome calls **********/
+
+    pub async fn call_zome<I, O>(
+        &self,
+        cell_id: &CellId,
+        zome_name: &str,
+        fn_name: &str,
+        payload: I,
+    ) -> anyhow::Result<O>
+    where
+        I: serde::Serialize + std::fmt::Debug,
+        O: serde::de::DeserializeOwned + std::fmt::Debug,
+    {
+        let response = self
+            .app_ws
+            .call_zome(
+                ZomeCallTarget::CellId(cell_id.clone()),
+                zome_name.into(),
+                fn_name.into(),
+                ExternIO::encode(payload)?,
+            )
+            .await
+            .map_err(api_err)?;
+        Ok(response.decode()?)
+    }
+
+    /********* Conversations **********/
+
+    // Each conversation is a clone of the relay cell
+    pub fn conversations(&self) -> Vec<Conversation> {
+        self.cell_infos()
+            .iter()
+            .filter_map(|c| match c {
+                CellInfo::Cloned(cell) => Some(Conversation::from_cloned_cell(cell)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Looks a conversation up by network seed first, then by title
+    pub fn find_conversation(&self, id_or_title: &str) -> anyhow::Result<Conversation> {
+        let conversations = self.conversations();
+        conversations
+            .iter()
+            .find(|c| c.id == id_or_title)
+            .or_else(|| conversations.iter().find(|c| c.title == id_or_title))
+            .cloned()
+            .ok_or(anyhow!("Conversation {id_or_title} not found"))
+    }
+
+    pub async fn get_messages_for_buckets(
+        &self,
+        conversation: &Conversation,
+        buckets: Vec<u32>,
+    ) -> anyhow::Result<Vec<MessageRecord>> {
+        // Zomes from before blocks also take the buckets alone, which leaves out blocked agents
+        self.call_zome(
+            &conversation.cell_id,
+            ZOME_NAME,
+            "get_messages_for_buckets",
+            buckets,
+        )
+        .await
+    }
+
+    pub async fn get_agents(&self, conversation: &Conversation) -> anyhow::Result<Vec<AgentPubKey>> {
+        self.call_zome(&conversation.cell_id, "profiles", "get_agents_with_profile", ())
+            .await
+    }
+
+    // Sends a text message into the conversation's current bucket and
+    // notifies every other agent with a profile in the conversation
+    pub async fn send_message(
+        &self,
+        conversation: &Conversation,
+        content: String,
+    ) -> anyhow::Result<Record> {
+        let agents = self
+            .get_agents(conversation)
+            .await?
+            .into_iter()
+            .filter(|agent| agent != self.my_pub_key())
+            .collect();
+        let input = SendMessageInput {
+            message: Message {
+                content,
+                bucket: conversation.current_bucket(),
+                attachments: vec![],
+                forwarded_from: None,
+                mentions: vec![],
+                link_preview: None,
+            },
+            agents,
+        };
+        self.call_zome(&conversation.cell_id, ZOME_NAME, "create_message", input)
+            .await
+    }
+
+    // Calls `handler` for every `Signal::Message` and `Signal::Mentioned` received by any of
+    // the app's cells
+    pub async fn on_message<F>(&self, handler: F)
+    where
+        F: Fn(IncomingMessage) + 'static + Sync + Send,
+    {
+        self.app_ws
+            .on_signal(move |signal| {
+                let Signal::App { cell_id, signal, .. } = signal else {
+                    return;
+                };
+                let (action, message, from, mentioned) =
+                    match signal.into_inner().decode::<RelaySignal>() {
+                        Ok(RelaySignal::Message {
+                            action,
+                            message,
+                            from,
+                        }) => (action, message, from, false),
+                        Ok(RelaySignal::Mentioned {
+                            action,
+                            message,
+                            from,
+                        }) => (action, message, from, true),
+                        _ => return,
+                    };
+                handler(IncomingMessage {
+                    cell_id,
+                    action,
+                    message,
+                    from,
+                    mentioned,
+                });
+            })
+            .await;
+    }
+
+    /********* Contacts **********/
+
+    pub async fn get_all_contacts(&self) -> anyhow::Result<Vec<ContactRecord>> {
+        self.call_zome(&self.main_cell_id()?, ZOME_NAME, "get_all_contact_entries", ())
+            .await
+    }
+
+    pub async fn create_contact(&self, contact: Contact) -> anyhow::Result<Record> {
+        self.call_zome(&self.main_cell_id()?, ZOME_NAME, "create_contact", contact)
+            .await
+    }
+}
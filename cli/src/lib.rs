@@ -0,0 +1,12 @@
+pub mod client;
+pub use client::*;
+pub mod signal;
+pub use signal::*;
+
+pub const APP_ID: &str = "volla-messages";
+pub const ROLE_NAME: &str = "relay";
+pub const ZOME_NAME: &str = "relay";
+
+// Timestamp range of messages contained within a single bucket, in milliseconds.
+// Must match BUCKET_RANGE_MS in ui/src/config.ts
+pub const BUCKET_RANGE_MS: i64 = 1000 * 60 * 60 * 24;
@@ -0,0 +1,182 @@
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
+use holochain_types::prelude::*;
+use relay_cli::{Conversation, RelayClient, APP_ID};
+use relay_integrity::{Contact, MessageRecord};
+
+#[derive(Parser)]
+#[command(name = "relay-cli", version, about = "Send and read Volla Messages from a terminal")]
+struct Cli {
+    /// Admin websocket port of the running conductor
+    #[arg(long, env = "RELAY_ADMIN_PORT")]
+    admin_port: u16,
+
+    /// App websocket port; a new app interface is attached if omitted
+    #[arg(long, env = "RELAY_APP_PORT")]
+    app_port: Option<u16>,
+
+    #[arg(long, env = "RELAY_APP_ID", default_value = APP_ID)]
+    app_id: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List all conversations
+    Conversations,
+    /// Print the messages of a conversation
+    Messages {
+        /// Network seed or title of the conversation
+        conversation: String,
+        /// Buckets to read, defaults to the current one
+        #[arg(long, value_delimiter = ',')]
+        buckets: Vec<u32>,
+    },
+    /// Send a text message to a conversation
+    Send {
+        /// Network seed or title of the conversation
+        conversation: String,
+        content: String,
+    },
+    /// Print incoming messages until interrupted
+    Listen {
+        /// Only print messages of this conversation
+        conversation: Option<String>,
+    },
+    /// Manage contacts
+    Contacts {
+        #[command(subcommand)]
+        command: ContactsCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ContactsCommand {
+    /// List all contacts
+    List,
+    /// Add a contact
+    Add {
+        /// Base64 encoded agent public key
+        public_key: String,
+        first_name: String,
+        #[arg(default_value = "")]
+        last_name: String,
+        #[arg(long, default_value = "")]
+        avatar: String,
+    },
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let client = RelayClient::connect(cli.admin_port, cli.app_port, &cli.app_id).await?;
+
+    match cli.command {
+        Command::Conversations => {
+            for conversation in client.conversations() {
+                println!(
+                    "{}\t{}{}",
+                    conversation.id,
+                    conversation.title,
+                    if conversation.enabled { "" } else { " (disabled)" }
+                );
+            }
+        }
+        Command::Messages {
+            conversation,
+            buckets,
+        } => {
+            let conversation = client.find_conversation(&conversation)?;
+            let buckets = if buckets.is_empty() {
+                vec![conversation.current_bucket()]
+            } else {
+                buckets
+            };
+            let mut messages = client
+                .get_messages_for_buckets(&conversation, buckets)
+                .await?;
+            messages.sort_by_key(|m| m.signed_action.action().timestamp());
+            for message in messages {
+                print_message(&conversation, &message);
+            }
+        }
+        Command::Send {
+            conversation,
+            content,
+        } => {
+            let conversation = client.find_conversation(&conversation)?;
+            let record = client.send_message(&conversation, content).await?;
+            println!("{}", record.action_address());
+        }
+        Command::Listen { conversation } => {
+            let conversations = match conversation {
+                Some(c) => vec![client.find_conversation(&c)?],
+                None => client.conversations(),
+            };
+            client
+                .on_message(move |incoming| {
+                    if let Some(conversation) =
+                        conversations.iter().find(|c| c.cell_id == incoming.cell_id)
+                    {
+                        println!(
+                            "{}\t{}\t{}\t{}",
+                            incoming.action.action().timestamp(),
+                            conversation.title,
+                            incoming.from,
+                            incoming.message.content
+                        );
+                    }
+                })
+                .await;
+            tokio::signal::ctrl_c().await?;
+        }
+        Command::Contacts { command } => match command {
+            ContactsCommand::List => {
+                for record in client.get_all_contacts().await? {
+                    if let Some(contact) = record.contact {
+                        println!(
+                            "{}\t{} {}",
+                            contact.public_key, contact.first_name, contact.last_name
+                        );
+                    }
+                }
+            }
+            ContactsCommand::Add {
+                public_key,
+                first_name,
+                last_name,
+                avatar,
+            } => {
+                let public_key: AgentPubKey = AgentPubKeyB64::from_b64_str(&public_key)
+                    .map_err(|e| anyhow!("Invalid agent public key: {e:?}"))?
+                    .into();
+                let record = client
+                    .create_contact(Contact {
+                        public_key,
+                        first_name,
+                        last_name,
+                        avatar,
+                        verification: None,
+                    })
+                    .await?;
+                println!("{}", record.action_address());
+            }
+        },
+    }
+    Ok(())
+}
+
+fn print_message(conversation: &Conversation, record: &MessageRecord) {
+    let Some(message) = &record.message else {
+        return;
+    };
+    println!(
+        "{}\t{}\t{}\t{}",
+        record.signed_action.action().timestamp(),
+        conversation.title,
+        record.signed_action.action().author(),
+        message.content
+    );
+}
@@ -0,0 +1,23 @@
+use holochain_types::prelude::*;
+use relay_integrity::Message;
+
+// Mirrors the relay coordinator zome's `Signal` enum.
+// Only the variants a client acts on are spelled out, everything else is `Other`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum RelaySignal {
+    Message { action: SignedActionHashed, message: Message, from: AgentPubKey },
+    // A message that mentions this agent
+    Mentioned { action: SignedActionHashed, message: Message, from: AgentPubKey },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub struct IncomingMessage {
+    pub cell_id: CellId,
+    pub action: SignedActionHashed,
+    pub message: Message,
+    pub from: AgentPubKey,
+    pub mentioned: bool,
+}
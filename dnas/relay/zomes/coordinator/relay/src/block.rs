@@ -0,0 +1,59 @@
+use hdk::prelude::*;
+use relay_integrity::*;
+
+use crate::contact::latest_entries;
+
+// Blocks are private entries kept with the contacts in the base cell,
+// which conversation cells ask for the blocked agents
+
+fn blocks() -> ExternResult<Vec<(ActionHash, Block)>> {
+    let mut blocks = vec![];
+    for (original, record) in latest_entries(UnitEntryTypes::Block)? {
+        if let Some(block) = record
+            .entry()
+            .to_app_option::<Block>()
+            .map_err(|e| wasm_error!(e))?
+        {
+            blocks.push((original, block));
+        }
+    }
+    Ok(blocks)
+}
+
+#[hdk_extern]
+pub fn block_agent(agent: AgentPubKey) -> ExternResult<ActionHash> {
+    if let Some((block_hash, _)) = blocks()?.into_iter().find(|(_, block)| block.agent == agent) {
+        return Ok(block_hash);
+    }
+    create_entry(&EntryTypes::Block(Block { agent }))
+}
+
+#[hdk_extern]
+pub fn unblock_agent(agent: AgentPubKey) -> ExternResult<()> {
+    for (block_hash, block) in blocks()? {
+        if block.agent == agent {
+            delete_entry(block_hash)?;
+        }
+    }
+    Ok(())
+}
+
+#[hdk_extern]
+pub fn get_blocked_agents(_: ()) -> ExternResult<Vec<AgentPubKey>> {
+    if is_base_cell()? {
+        return Ok(blocks()?.into_iter().map(|(_, block)| block.agent).collect());
+    }
+    let response = call(
+        CallTargetCell::OtherRole("relay".into()),
+        zome_info()?.name,
+        "get_blocked_agents".into(),
+        None,
+        (),
+    )?;
+    match response {
+        ZomeCallResponse::Ok(result) => result.decode().map_err(|e| wasm_error!(e)),
+        _ => Err(wasm_error!(WasmErrorInner::Guest(format!(
+            "Failed to get the blocked agents from the base cell: {response:?}"
+        )))),
+    }
+}
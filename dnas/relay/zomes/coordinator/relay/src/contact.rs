@@ -1,69 +1,114 @@
+use std::collections::{BTreeMap, HashSet};
+
 use hdk::prelude::*;
 use relay_integrity::*;
 
+use crate::Signal;
+
+// Contacts are private entries, so they are only ever read from this agent's own chain
+
+fn entries_filter(entry_type: UnitEntryTypes) -> ExternResult<ChainQueryFilter> {
+    Ok(ChainQueryFilter::new().entry_type(entry_type.try_into()?).include_entries(true))
+}
+
+// Every revision of every entry of a type on the chain, in chain order,
+// with the action that created the entry
+pub(crate) fn entry_revisions(
+    entry_type: UnitEntryTypes,
+) -> ExternResult<Vec<(ActionHash, Record)>> {
+    let mut originals: BTreeMap<ActionHash, ActionHash> = BTreeMap::new();
+    let mut revisions = vec![];
+    for record in query(entries_filter(entry_type)?)? {
+        let original = match record.action() {
+            Action::Update(update) => {
+                originals
+                    .get(&update.original_action_address)
+                    .cloned()
+                    .unwrap_or(update.original_action_address.clone())
+            }
+            _ => record.action_address().clone(),
+        };
+        originals.insert(record.action_address().clone(), original.clone());
+        revisions.push((original, record));
+    }
+    Ok(revisions)
+}
+
+pub(crate) fn deleted_actions() -> ExternResult<HashSet<ActionHash>> {
+    Ok(
+        query(ChainQueryFilter::new().action_type(ActionType::Delete))?
+            .into_iter()
+            .filter_map(|record| match record.action() {
+                Action::Delete(delete) => Some(delete.deletes_address.clone()),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+// The latest revision of every entry of a type that wasn't deleted, by the action that created it
+pub(crate) fn latest_entries(
+    entry_type: UnitEntryTypes,
+) -> ExternResult<BTreeMap<ActionHash, Record>> {
+    let deleted = deleted_actions()?;
+    let mut entries = BTreeMap::new();
+    for (original, record) in entry_revisions(entry_type)? {
+        entries.insert(original, record);
+    }
+    entries.retain(|original, _| !deleted.contains(original));
+    Ok(entries)
+}
+
+pub(crate) fn get_chain_record(
+    entry_type: UnitEntryTypes,
+    action_hash: &ActionHash,
+) -> ExternResult<Option<Record>> {
+    Ok(
+        query(entries_filter(entry_type)?)?
+            .into_iter()
+            .find(|record| record.action_address() == action_hash),
+    )
+}
+
+fn get_contact_record(action_hash: &ActionHash) -> ExternResult<Record> {
+    get_chain_record(UnitEntryTypes::PrivateContact, action_hash)?
+        .ok_or(wasm_error!(WasmErrorInner::Guest("Contact not found".to_string())))
+}
+
+fn get_contact(action_hash: &ActionHash) -> ExternResult<Contact> {
+    get_contact_record(action_hash)?
+        .entry()
+        .to_app_option()
+        .map_err(|e| wasm_error!(e))?
+        .ok_or(
+            wasm_error!(WasmErrorInner::Guest("Contact record has no entry".to_string())),
+        )
+}
+
+pub(crate) fn contact_record(
+    original_action: ActionHash,
+    record: Record,
+) -> ExternResult<ContactRecord> {
+    Ok(ContactRecord {
+        original_action,
+        signed_action: record.signed_action().clone(),
+        contact: record.entry().to_app_option().map_err(|e| wasm_error!(e))?,
+    })
+}
+
 #[hdk_extern]
 pub fn create_contact(contact: Contact) -> ExternResult<Record> {
-    let contact_hash = create_entry(&EntryTypes::Contact(contact.clone()))?;
-    create_link(
-        contact.public_key.clone(),
-        contact_hash.clone(),
-        LinkTypes::ContactToContacts,
-        (),
-    )?;
-    let record = get(contact_hash.clone(), GetOptions::default())?
-        .ok_or(
-            wasm_error!(
-                WasmErrorInner::Guest("Could not find the newly created Contact"
-                .to_string())
-            ),
-        )?;
-    let path = Path::from("all_contacts");
-    create_link(
-        path.path_entry_hash()?,
-        contact_hash.clone(),
-        LinkTypes::AllContacts,
-        (),
-    )?;
-    Ok(record)
+    let contact_hash = create_entry(&EntryTypes::PrivateContact(contact))?;
+    get_contact_record(&contact_hash)
 }
 
 #[hdk_extern]
 pub fn get_latest_contact(
     original_contact_hash: ActionHash,
 ) -> ExternResult<Option<ContactRecord>> {
-    let links = get_links(
-        GetLinksInputBuilder::try_new(
-                original_contact_hash.clone(),
-                LinkTypes::ContactUpdates,
-            )?
-            .build(),
-    )?;
-    let latest_link = links
-        .into_iter()
-        .max_by(|link_a, link_b| link_a.timestamp.cmp(&link_b.timestamp));
-    let latest_contact_hash = match latest_link {
-        Some(link) => {
-            link.target
-                .clone()
-                .into_action_hash()
-                .ok_or(
-                    wasm_error!(
-                        WasmErrorInner::Guest("No action hash associated with link"
-                        .to_string())
-                    ),
-                )?
-        }
-        None => original_contact_hash.clone(),
-    };
-    match get(latest_contact_hash, GetOptions::default())? {
-        Some(record) => {
-            Ok(Some(ContactRecord {
-                original_action: original_contact_hash,
-                signed_action: record.signed_action().clone(),
-                contact: record.entry().to_app_option().map_err(|e| wasm_error!(e))?,
-            }))
-        },
-        None => Ok(None)
+    match latest_entries(UnitEntryTypes::PrivateContact)?.remove(&original_contact_hash) {
+        Some(record) => Ok(Some(contact_record(original_contact_hash, record)?)),
+        None => Ok(None),
     }
 }
 
@@ -71,81 +116,33 @@ pub fn get_latest_contact(
 pub fn get_original_contact(
     original_contact_hash: ActionHash,
 ) -> ExternResult<Option<Record>> {
-    let Some(details) = get_details(original_contact_hash, GetOptions::default())? else {
-        return Ok(None);
-    };
-    match details {
-        Details::Record(details) => Ok(Some(details.record)),
-        _ => {
-            Err(
-                wasm_error!(
-                    WasmErrorInner::Guest("Malformed get details response".to_string())
-                ),
-            )
-        }
-    }
+    Ok(
+        entry_revisions(UnitEntryTypes::PrivateContact)?
+            .into_iter()
+            .map(|(_, record)| record)
+            .find(|record| record.action_address() == &original_contact_hash),
+    )
 }
 
 #[hdk_extern]
 pub fn get_all_revisions_for_contact(
     original_contact_hash: ActionHash,
 ) -> ExternResult<Vec<Record>> {
-    let Some(original_record) = get_original_contact(original_contact_hash.clone())?
-    else {
-        return Ok(vec![]);
-    };
-    let links = get_links(
-        GetLinksInputBuilder::try_new(
-                original_contact_hash.clone(),
-                LinkTypes::ContactUpdates,
-            )?
-            .build(),
-    )?;
-    let get_input: Vec<GetInput> = links
-        .into_iter()
-        .map(|link| Ok(
-            GetInput::new(
-                link
-                    .target
-                    .into_action_hash()
-                    .ok_or(
-                        wasm_error!(
-                            WasmErrorInner::Guest("No action hash associated with link"
-                            .to_string())
-                        ),
-                    )?
-                    .into(),
-                GetOptions::default(),
-            ),
-        ))
-        .collect::<ExternResult<Vec<GetInput>>>()?;
-    let records = HDK.with(|hdk| hdk.borrow().get(get_input))?;
-    let mut records: Vec<Record> = records.into_iter().flatten().collect();
-    records.insert(0, original_record);
-    Ok(records)
-}
-
-#[hdk_extern]
-pub fn get_all_contacts() -> ExternResult<Vec<Link>> {
-    let path = Path::from("all_contacts");
-    get_links(
-        GetLinksInputBuilder::try_new(path.path_entry_hash()?, LinkTypes::AllContacts)?
-            .build(),
+    Ok(
+        entry_revisions(UnitEntryTypes::PrivateContact)?
+            .into_iter()
+            .filter(|(original, _)| original == &original_contact_hash)
+            .map(|(_, record)| record)
+            .collect(),
     )
 }
 
 #[hdk_extern]
 pub fn get_all_contact_entries(_: ()) -> ExternResult<Vec<ContactRecord>> {
-    let links = get_all_contacts(())?;
-    let mut results: Vec<ContactRecord> = Vec::new();
-    for l in links {
-        let hash  = ActionHash::try_from(l.target).map_err(|e|wasm_error!(e))?;
-        if let Some(r) = get_latest_contact(hash)? {
-            results.push (r);
-        }
-    }
-
-    Ok(results)
+    latest_entries(UnitEntryTypes::PrivateContact)?
+        .into_iter()
+        .map(|(original, record)| contact_record(original, record))
+        .collect()
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -157,73 +154,26 @@ pub struct UpdateContactInput {
 
 #[hdk_extern]
 pub fn update_contact(input: UpdateContactInput) -> ExternResult<Record> {
-    let updated_contact_hash = update_entry(
-        input.previous_contact_hash.clone(),
-        &input.updated_contact,
-    )?;
-    create_link(
-        input.original_contact_hash.clone(),
-        updated_contact_hash.clone(),
-        LinkTypes::ContactUpdates,
-        (),
-    )?;
-    let record = get(updated_contact_hash.clone(), GetOptions::default())?
-        .ok_or(
-            wasm_error!(
-                WasmErrorInner::Guest("Could not find the newly updated Contact"
-                .to_string())
-            ),
-        )?;
-    Ok(record)
+    let previous_contact = get_contact(&input.previous_contact_hash)?;
+    let mut updated_contact = input.updated_contact;
+    if previous_contact.public_key == updated_contact.public_key {
+        updated_contact.verification = previous_contact.verification;
+    } else {
+        // The verification was for the previous key, so it has to be done again
+        updated_contact.verification = None;
+        emit_signal(Signal::ContactKeyChanged {
+            original_contact_hash: input.original_contact_hash,
+            previous_key: previous_contact.public_key,
+            new_key: updated_contact.public_key.clone(),
+        })?;
+    }
+    let updated_contact_hash = update_entry(input.previous_contact_hash, &updated_contact)?;
+    get_contact_record(&updated_contact_hash)
 }
 
 #[hdk_extern]
 pub fn delete_contact(original_contact_hash: ActionHash) -> ExternResult<ActionHash> {
-    let details = get_details(original_contact_hash.clone(), GetOptions::default())?
-        .ok_or(wasm_error!(WasmErrorInner::Guest("Contact not found".to_string())))?;
-    let record = match details {
-        Details::Record(details) => Ok(details.record),
-        _ => {
-            Err(
-                wasm_error!(
-                    WasmErrorInner::Guest("Malformed get details response".to_string())
-                ),
-            )
-        }
-    }?;
-    let entry = record
-        .entry()
-        .as_option()
-        .ok_or(
-            wasm_error!(WasmErrorInner::Guest("Contact record has no entry".to_string())),
-        )?;
-    let contact = <Contact>::try_from(entry)?;
-    let links = get_links(
-        GetLinksInputBuilder::try_new(
-                contact.public_key.clone(),
-                LinkTypes::ContactToContacts,
-            )?
-            .build(),
-    )?;
-    for link in links {
-        if let Some(action_hash) = link.target.into_action_hash() {
-            if action_hash == original_contact_hash {
-                delete_link(link.create_link_hash)?;
-            }
-        }
-    }
-    let path = Path::from("all_contacts");
-    let links = get_links(
-        GetLinksInputBuilder::try_new(path.path_entry_hash()?, LinkTypes::AllContacts)?
-            .build(),
-    )?;
-    for link in links {
-        if let Some(hash) = link.target.into_action_hash() {
-            if hash == original_contact_hash {
-                delete_link(link.create_link_hash)?;
-            }
-        }
-    }
+    get_contact(&original_contact_hash)?;
     delete_entry(original_contact_hash)
 }
 
@@ -231,15 +181,21 @@ pub fn delete_contact(original_contact_hash: ActionHash) -> ExternResult<ActionH
 pub fn get_all_deletes_for_contact(
     original_contact_hash: ActionHash,
 ) -> ExternResult<Option<Vec<SignedActionHashed>>> {
-    let Some(details) = get_details(original_contact_hash, GetOptions::default())? else {
+    if get_original_contact(original_contact_hash.clone())?.is_none() {
         return Ok(None);
-    };
-    match details {
-        Details::Entry(_) => {
-            Err(wasm_error!(WasmErrorInner::Guest("Malformed details".into())))
-        }
-        Details::Record(record_details) => Ok(Some(record_details.deletes)),
     }
+    Ok(
+        Some(
+            query(ChainQueryFilter::new().action_type(ActionType::Delete))?
+                .into_iter()
+                .filter(|record| match record.action() {
+                    Action::Delete(delete) => delete.deletes_address == original_contact_hash,
+                    _ => false,
+                })
+                .map(|record| record.signed_action().clone())
+                .collect(),
+        ),
+    )
 }
 
 #[hdk_extern]
@@ -256,28 +212,173 @@ pub fn get_oldest_delete_for_contact(
     Ok(deletes.first().cloned())
 }
 
+// Copies contacts published by earlier versions into private entries, and removes every public
+// revision along with the links that revealed who they belong to. Returns how many were migrated.
 #[hdk_extern]
-pub fn get_contacts_for_contact(contact: AgentPubKey) -> ExternResult<Vec<Link>> {
-    get_links(
-        GetLinksInputBuilder::try_new(contact, LinkTypes::ContactToContacts)?.build(),
-    )
+pub fn migrate_public_contacts(_: ()) -> ExternResult<u32> {
+    let deleted = deleted_actions()?;
+    let mut public_contacts: BTreeMap<ActionHash, Vec<Record>> = BTreeMap::new();
+    for (original, record) in entry_revisions(UnitEntryTypes::Contact)? {
+        public_contacts.entry(original).or_default().push(record);
+    }
+    // Earlier revisions stay public until they are deleted too, even if the original was
+    public_contacts.retain(|_, revisions| {
+        revisions.iter().any(|record| !deleted.contains(record.action_address()))
+    });
+    if public_contacts.is_empty() {
+        return Ok(0);
+    }
+    let me = agent_info()?.agent_latest_pubkey;
+    let all_contacts = get_links(
+        GetLinksInputBuilder::try_new(
+                Path::from("all_contacts").path_entry_hash()?,
+                LinkTypes::AllContacts,
+            )?
+            .build(),
+    )?;
+    let mut migrated = 0;
+    for (original, revisions) in public_contacts {
+        let mut contacts = vec![];
+        for record in &revisions {
+            if let Some(contact) = record
+                .entry()
+                .to_app_option::<Contact>()
+                .map_err(|e| wasm_error!(e))?
+            {
+                contacts.push(contact);
+            }
+        }
+        let revision_hashes: Vec<ActionHash> = revisions
+            .iter()
+            .map(|record| record.action_address().clone())
+            .collect();
+
+        let mut links = all_contacts.clone();
+        links.extend(
+            get_links(
+                GetLinksInputBuilder::try_new(original.clone(), LinkTypes::ContactUpdates)?
+                    .build(),
+            )?,
+        );
+        // The key may have changed between revisions
+        let mut public_keys: Vec<&AgentPubKey> = contacts.iter().map(|c| &c.public_key).collect();
+        public_keys.dedup();
+        for public_key in public_keys {
+            links.extend(
+                get_links(
+                    GetLinksInputBuilder::try_new(
+                            public_key.clone(),
+                            LinkTypes::ContactToContacts,
+                        )?
+                        .build(),
+                )?,
+            );
+        }
+        for link in links {
+            let target = link.target.into_action_hash();
+            if link.author == me && target.is_some_and(|hash| revision_hashes.contains(&hash)) {
+                delete_link(link.create_link_hash)?;
+            }
+        }
+
+        if !deleted.contains(&original) {
+            if let Some(contact) = contacts.pop() {
+                create_entry(&EntryTypes::PrivateContact(contact))?;
+                migrated += 1;
+            }
+        }
+        for hash in revision_hashes {
+            if !deleted.contains(&hash) {
+                delete_entry(hash)?;
+            }
+        }
+    }
+    Ok(migrated)
 }
 
+// Kept from when contacts were published, and now read from the private entries
+
 #[hdk_extern]
-pub fn get_deleted_contacts_for_contact(
-    contact: AgentPubKey,
-) -> ExternResult<Vec<(SignedActionHashed, Vec<SignedActionHashed>)>> {
-    let details = get_link_details(
-        contact,
-        LinkTypes::ContactToContacts,
-        None,
-        GetOptions::default(),
-    )?;
+pub fn get_contacts_for_contact(contact: AgentPubKey) -> ExternResult<Vec<ContactRecord>> {
     Ok(
-        details
-            .into_inner()
+        get_all_contact_entries(())?
             .into_iter()
-            .filter(|(_link, deletes)| !deletes.is_empty())
+            .filter(|record| {
+                record.contact.as_ref().is_some_and(|c| c.public_key == contact)
+            })
             .collect(),
     )
 }
+
+#[hdk_extern]
+pub fn get_all_contacts(_: ()) -> ExternResult<Vec<ContactRecord>> {
+    get_all_contact_entries(())
+}
+
+#[hdk_extern]
+pub fn get_safety_number(agent: AgentPubKey) -> ExternResult<String> {
+    safety_number(&agent_info()?.agent_latest_pubkey, &agent)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VerifyContactInput {
+    pub original_contact_hash: ActionHash,
+    pub previous_contact_hash: ActionHash,
+}
+
+// Records that the safety numbers were compared with the contact, signed by this agent
+#[hdk_extern]
+pub fn verify_contact(input: VerifyContactInput) -> ExternResult<Record> {
+    let me = agent_info()?.agent_latest_pubkey;
+    let mut contact = get_contact(&input.previous_contact_hash)?;
+    let safety_number = safety_number(&me, &contact.public_key)?;
+    let data = ContactVerificationData {
+        public_key: contact.public_key.clone(),
+        safety_number: safety_number.clone(),
+    };
+    contact.verification = Some(ContactVerification {
+        safety_number,
+        signature: sign(me, data)?,
+    });
+    let updated_contact_hash = update_entry(input.previous_contact_hash, &contact)?;
+    get_contact_record(&updated_contact_hash)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, SerializedBytes)]
+pub struct ContactCodeData {
+    pub public_key: AgentPubKey,
+    pub first_name: String,
+    pub last_name: String,
+}
+
+// What is shared to be added as a contact. The signature proves the code was made by the key's owner.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContactCode {
+    pub data: ContactCodeData,
+    pub signature: Signature,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateContactCodeInput {
+    pub first_name: String,
+    pub last_name: String,
+}
+
+#[hdk_extern]
+pub fn create_contact_code(input: CreateContactCodeInput) -> ExternResult<ContactCode> {
+    let me = agent_info()?.agent_latest_pubkey;
+    let data = ContactCodeData {
+        public_key: me.clone(),
+        first_name: input.first_name,
+        last_name: input.last_name,
+    };
+    Ok(ContactCode {
+        signature: sign(me, data.clone())?,
+        data,
+    })
+}
+
+#[hdk_extern]
+pub fn verify_contact_code(code: ContactCode) -> ExternResult<bool> {
+    verify_signature(code.data.public_key.clone(), code.signature, code.data)
+}
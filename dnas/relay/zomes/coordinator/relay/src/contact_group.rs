@@ -0,0 +1,81 @@
+use hdk::prelude::*;
+use relay_integrity::*;
+
+use crate::contact::{contact_record, get_chain_record, latest_entries};
+
+// Groups are private entries like contacts, kept on this agent's own chain
+
+fn get_contact_group_record(action_hash: &ActionHash) -> ExternResult<Record> {
+    get_chain_record(UnitEntryTypes::ContactGroup, action_hash)?
+        .ok_or(wasm_error!(WasmErrorInner::Guest("Contact group not found".to_string())))
+}
+
+fn contact_group_record(
+    original_action: ActionHash,
+    record: Record,
+) -> ExternResult<ContactGroupRecord> {
+    Ok(ContactGroupRecord {
+        original_action,
+        signed_action: record.signed_action().clone(),
+        group: record.entry().to_app_option().map_err(|e| wasm_error!(e))?,
+    })
+}
+
+#[hdk_extern]
+pub fn create_contact_group(group: ContactGroup) -> ExternResult<Record> {
+    let group_hash = create_entry(&EntryTypes::ContactGroup(group))?;
+    get_contact_group_record(&group_hash)
+}
+
+#[hdk_extern]
+pub fn get_latest_contact_group(
+    original_group_hash: ActionHash,
+) -> ExternResult<Option<ContactGroupRecord>> {
+    match latest_entries(UnitEntryTypes::ContactGroup)?.remove(&original_group_hash) {
+        Some(record) => Ok(Some(contact_group_record(original_group_hash, record)?)),
+        None => Ok(None),
+    }
+}
+
+#[hdk_extern]
+pub fn get_all_contact_groups(_: ()) -> ExternResult<Vec<ContactGroupRecord>> {
+    latest_entries(UnitEntryTypes::ContactGroup)?
+        .into_iter()
+        .map(|(original, record)| contact_group_record(original, record))
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateContactGroupInput {
+    pub previous_group_hash: ActionHash,
+    pub updated_group: ContactGroup,
+}
+
+#[hdk_extern]
+pub fn update_contact_group(input: UpdateContactGroupInput) -> ExternResult<Record> {
+    get_contact_group_record(&input.previous_group_hash)?;
+    let updated_group_hash = update_entry(input.previous_group_hash, &input.updated_group)?;
+    get_contact_group_record(&updated_group_hash)
+}
+
+#[hdk_extern]
+pub fn delete_contact_group(original_group_hash: ActionHash) -> ExternResult<ActionHash> {
+    get_contact_group_record(&original_group_hash)?;
+    delete_entry(original_group_hash)
+}
+
+// The latest version of each member that is still a contact
+#[hdk_extern]
+pub fn get_contacts_in_group(original_group_hash: ActionHash) -> ExternResult<Vec<ContactRecord>> {
+    let Some(group) = get_latest_contact_group(original_group_hash)?.and_then(|r| r.group)
+    else {
+        return Ok(vec![]);
+    };
+    let mut contacts = latest_entries(UnitEntryTypes::PrivateContact)?;
+    group
+        .members
+        .into_iter()
+        .filter_map(|member| contacts.remove(&member).map(|record| (member, record)))
+        .map(|(member, record)| contact_record(member, record))
+        .collect()
+}
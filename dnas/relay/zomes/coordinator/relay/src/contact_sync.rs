@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use hdk::prelude::*;
+use relay_integrity::*;
+
+use crate::contact::{latest_entries, update_contact, UpdateContactInput};
+use crate::Signal;
+
+// Mirrors `Profile` of the profiles zome, whose integrity crate can't be linked into this one
+#[derive(Serialize, Deserialize, Debug, Clone, SerializedBytes)]
+struct Profile {
+    nickname: String,
+    fields: BTreeMap<String, String>,
+}
+
+// A contact whose name or avatar differs from the latest profile of its agent
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContactProfileChange {
+    pub original_contact_hash: ActionHash,
+    pub previous_contact_hash: ActionHash,
+    pub updated_contact: Contact,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SyncContactsInput {
+    // Updates the contacts instead of only proposing the changes
+    pub apply: bool,
+}
+
+fn get_agent_profile(agent: AgentPubKey) -> ExternResult<Option<Profile>> {
+    let response = call(
+        CallTargetCell::Local,
+        ZomeName::from("profiles"),
+        "get_agent_profile".into(),
+        None,
+        agent,
+    )?;
+    let record: Option<Record> = match response {
+        ZomeCallResponse::Ok(result) => result.decode().map_err(|e| wasm_error!(e))?,
+        _ => {
+            return Err(wasm_error!(WasmErrorInner::Guest(format!(
+                "Failed to get the agent's profile: {response:?}"
+            ))));
+        }
+    };
+    match record {
+        Some(record) => record.entry().to_app_option().map_err(|e| wasm_error!(e)),
+        None => Ok(None),
+    }
+}
+
+fn non_empty_field(profile: &Profile, name: &str) -> Option<String> {
+    profile
+        .fields
+        .get(name)
+        .filter(|value| !value.trim().is_empty())
+        .cloned()
+}
+
+// The first and last name of the profile. Profiles without a first name, e.g. from other
+// clients, only have a nickname, which the UI writes as the first and last name joined by a space.
+fn profile_names(profile: &Profile) -> (Option<String>, Option<String>) {
+    if let Some(first_name) = non_empty_field(profile, "firstName") {
+        return (Some(first_name), non_empty_field(profile, "lastName"));
+    }
+    let nickname = profile.nickname.trim();
+    match nickname.split_once(' ') {
+        Some((first_name, last_name)) => (
+            Some(first_name.to_string()),
+            Some(last_name.trim().to_string()).filter(|last_name| !last_name.is_empty()),
+        ),
+        None => (Some(nickname.to_string()).filter(|nickname| !nickname.is_empty()), None),
+    }
+}
+
+// Takes the name and avatar from the profile. Fields the profile leaves missing or empty keep
+// what the contact has, so a profile without a last name doesn't clear the one given to it.
+fn contact_from_profile(contact: &Contact, profile: &Profile) -> Contact {
+    let (first_name, last_name) = profile_names(profile);
+    Contact {
+        first_name: first_name.unwrap_or(contact.first_name.clone()),
+        last_name: last_name.unwrap_or(contact.last_name.clone()),
+        avatar: non_empty_field(profile, "avatar").unwrap_or(contact.avatar.clone()),
+        ..contact.clone()
+    }
+}
+
+// Compares every contact with its agent's latest profile, signaling the ones that drifted.
+// With `apply` they are also updated, otherwise the changes are only returned as proposals.
+#[hdk_extern]
+pub fn sync_contacts_from_profiles(
+    input: SyncContactsInput,
+) -> ExternResult<Vec<ContactProfileChange>> {
+    let mut changes = vec![];
+    for (original_contact_hash, record) in latest_entries(UnitEntryTypes::PrivateContact)? {
+        let Some(contact) = record
+            .entry()
+            .to_app_option::<Contact>()
+            .map_err(|e| wasm_error!(e))? else {
+            continue;
+        };
+        let Some(profile) = get_agent_profile(contact.public_key.clone())? else {
+            continue;
+        };
+        let updated_contact = contact_from_profile(&contact, &profile);
+        if updated_contact == contact {
+            continue;
+        }
+        emit_signal(Signal::ContactProfileChanged {
+            original_contact_hash: original_contact_hash.clone(),
+            updated_contact: updated_contact.clone(),
+        })?;
+        let change = ContactProfileChange {
+            original_contact_hash,
+            previous_contact_hash: record.action_address().clone(),
+            updated_contact,
+        };
+        if input.apply {
+            update_contact(UpdateContactInput {
+                original_contact_hash: change.original_contact_hash.clone(),
+                previous_contact_hash: change.previous_contact_hash.clone(),
+                updated_contact: change.updated_contact.clone(),
+            })?;
+        }
+        changes.push(change);
+    }
+    Ok(changes)
+}
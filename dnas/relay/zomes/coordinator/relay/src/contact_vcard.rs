@@ -0,0 +1,319 @@
+use hdk::prelude::*;
+use relay_integrity::*;
+
+use crate::contact::{create_contact, latest_entries};
+
+// The custom vCard property the contact's public key is kept in
+const PUBLIC_KEY_PROPERTY: &str = "X-RELAY-PUBLIC-KEY";
+// Lines are folded after this many bytes, as RFC 6350 recommends
+const MAX_LINE_LENGTH: usize = 75;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportContactsOutput {
+    pub imported: u32,
+    // Cards without a public key, and ones for keys that are already contacts
+    pub skipped: u32,
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+        .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => {}
+        }
+    }
+    result
+}
+
+// Splits a structured value like `N` at the separators that aren't escaped
+fn split_components(value: &str) -> Vec<String> {
+    let mut components = vec![String::new()];
+    let mut escaped = false;
+    for c in value.chars() {
+        if escaped {
+            components.last_mut().unwrap().push('\\');
+            components.last_mut().unwrap().push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == ';' {
+            components.push(String::new());
+        } else {
+            components.last_mut().unwrap().push(c);
+        }
+    }
+    components.into_iter().map(|component| unescape(&component)).collect()
+}
+
+fn fold(line: String) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
+
+fn contact_to_vcard(contact: &Contact) -> String {
+    let mut lines = vec![
+        String::from("BEGIN:VCARD"),
+        String::from("VERSION:4.0"),
+        format!("N:{};{};;;", escape(&contact.last_name), escape(&contact.first_name)),
+        format!(
+            "FN:{}",
+            escape(format!("{} {}", contact.first_name, contact.last_name).trim())
+        ),
+    ];
+    if !contact.avatar.is_empty() {
+        // Avatars are data URLs already, which vCard 4 allows as a URI
+        lines.push(format!("PHOTO:{}", contact.avatar));
+    }
+    lines.push(format!("{}:{}", PUBLIC_KEY_PROPERTY, contact.public_key));
+    lines.push(String::from("END:VCARD"));
+    lines.into_iter().map(|line| fold(line) + "\r\n").collect()
+}
+
+#[hdk_extern]
+pub fn export_contacts_vcard(_: ()) -> ExternResult<String> {
+    let mut vcard = String::new();
+    for (_, record) in latest_entries(UnitEntryTypes::PrivateContact)? {
+        if let Some(contact) = record
+            .entry()
+            .to_app_option::<Contact>()
+            .map_err(|e| wasm_error!(e))?
+        {
+            vcard.push_str(&contact_to_vcard(&contact));
+        }
+    }
+    Ok(vcard)
+}
+
+fn is_base64_param(param: &str) -> bool {
+    ["ENCODING=b", "ENCODING=BASE64", "BASE64"]
+        .iter()
+        .any(|p| param.eq_ignore_ascii_case(p))
+}
+
+#[derive(Default)]
+struct Card {
+    first_name: Option<String>,
+    last_name: Option<String>,
+    full_name: Option<String>,
+    avatar: Option<String>,
+    public_key: Option<String>,
+}
+
+impl Card {
+    fn set(&mut self, name: &str, params: &[&str], value: &str) {
+        match name {
+            "N" => {
+                let components = split_components(value);
+                self.last_name = components.first().cloned();
+                self.first_name = components.get(1).cloned();
+            }
+            "FN" => self.full_name = Some(unescape(value)),
+            "PHOTO" if value.starts_with("data:") => self.avatar = Some(value.to_string()),
+            // vCard 3 embeds the photo itself, with its type as a parameter
+            "PHOTO" if params.iter().any(|p| is_base64_param(p)) => {
+                let image_type = params
+                    .iter()
+                    .find_map(|p| {
+                        p.to_ascii_lowercase().strip_prefix("type=").map(String::from)
+                    })
+                    .unwrap_or(String::from("jpeg"));
+                self.avatar = Some(format!("data:image/{image_type};base64,{value}"));
+            }
+            PUBLIC_KEY_PROPERTY => self.public_key = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+
+    fn into_contact(self) -> Option<Contact> {
+        let public_key = AgentPubKey::try_from(self.public_key?).ok()?;
+        let (first_name, last_name) = match self.first_name.filter(|n| !n.trim().is_empty()) {
+            Some(first_name) => (first_name, self.last_name.unwrap_or_default()),
+            None => (self.full_name?, String::new()),
+        };
+        Some(Contact {
+            public_key,
+            first_name,
+            last_name,
+            avatar: self.avatar.unwrap_or_default(),
+            verification: None,
+        })
+    }
+}
+
+fn parse_vcards(vcard: &str) -> Vec<Card> {
+    // Continuation lines start with a space or a tab
+    let unfolded = vcard.replace("\r\n", "\n").replace("\n ", "").replace("\n\t", "");
+    let mut cards = vec![];
+    let mut card: Option<Card> = None;
+    for line in unfolded.lines() {
+        let Some((property, value)) = line.split_once(':') else {
+            continue;
+        };
+        let mut params = property.split(';');
+        let name = params.next().unwrap_or_default();
+        // Properties can be grouped, as in `item1.PHOTO`
+        let name = name.rsplit('.').next().unwrap_or_default().to_ascii_uppercase();
+        let params: Vec<&str> = params.collect();
+        match (name.as_str(), value.to_ascii_uppercase().as_str()) {
+            ("BEGIN", "VCARD") => card = Some(Card::default()),
+            ("END", "VCARD") => cards.extend(card.take()),
+            _ => {
+                if let Some(card) = card.as_mut() {
+                    card.set(&name, &params, value);
+                }
+            }
+        }
+    }
+    cards
+}
+
+// The contacts of the cards with a public key that isn't in `known_keys` yet, and how many
+// cards were skipped
+fn new_contacts(vcard: &str, mut known_keys: Vec<AgentPubKey>) -> (Vec<Contact>, u32) {
+    let mut contacts = vec![];
+    let mut skipped = 0;
+    for card in parse_vcards(vcard) {
+        match card.into_contact() {
+            Some(contact) if !known_keys.contains(&contact.public_key) => {
+                known_keys.push(contact.public_key.clone());
+                contacts.push(contact);
+            }
+            _ => skipped += 1,
+        }
+    }
+    (contacts, skipped)
+}
+
+// Creates a contact for every card with a public key that isn't a contact yet
+#[hdk_extern]
+pub fn import_contacts_vcard(vcard: String) -> ExternResult<ImportContactsOutput> {
+    let me = agent_info()?.agent_latest_pubkey;
+    let mut known_keys = vec![me];
+    for (_, record) in latest_entries(UnitEntryTypes::PrivateContact)? {
+        if let Some(contact) = record
+            .entry()
+            .to_app_option::<Contact>()
+            .map_err(|e| wasm_error!(e))?
+        {
+            known_keys.push(contact.public_key);
+        }
+    }
+    let (contacts, skipped) = new_contacts(&vcard, known_keys);
+    let imported = contacts.len() as u32;
+    for contact in contacts {
+        create_contact(contact)?;
+    }
+    Ok(ImportContactsOutput { imported, skipped })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(byte: u8) -> AgentPubKey {
+        AgentPubKey::from_raw_36(vec![byte; 36])
+    }
+
+    fn contact(byte: u8, first_name: &str, last_name: &str, avatar: &str) -> Contact {
+        Contact {
+            public_key: agent(byte),
+            first_name: first_name.to_string(),
+            last_name: last_name.to_string(),
+            avatar: avatar.to_string(),
+            verification: None,
+        }
+    }
+
+    fn export(contacts: &[Contact]) -> String {
+        contacts.iter().map(contact_to_vcard).collect()
+    }
+
+    #[test]
+    fn export_round_trips_through_import() {
+        let avatar = format!("data:image/png;base64,{}", "iVBORw0KGgo".repeat(40));
+        let contacts = vec![
+            contact(1, "Ada", "Lovelace", ""),
+            contact(2, "Jean; Paul", "Sartre, \\ Jr.", &avatar),
+            contact(3, "Line\nbreak", "", ""),
+            contact(4, "Zoë", "Åström", ""),
+        ];
+        let (imported, skipped) = new_contacts(&export(&contacts), vec![]);
+        assert_eq!(imported, contacts);
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn exported_lines_are_folded() {
+        let long_name = "é".repeat(100);
+        let vcard = export(&[contact(1, &long_name, "", "")]);
+        for line in vcard.split("\r\n") {
+            assert!(line.len() <= MAX_LINE_LENGTH, "{line:?} is too long");
+        }
+        let (imported, _) = new_contacts(&vcard, vec![]);
+        assert_eq!(imported[0].first_name, long_name);
+    }
+
+    #[test]
+    fn escaped_separators_stay_in_their_component() {
+        assert_eq!(
+            split_components("Doe\\;Smith;John\\, Jr.;;"),
+            vec!["Doe;Smith", "John, Jr.", "", ""]
+        );
+        assert_eq!(split_components("a\\\\;b"), vec!["a\\", "b"]);
+        assert_eq!(unescape("one\\ntwo\\Nthree"), "one\ntwo\nthree");
+    }
+
+    #[test]
+    fn imports_vcard_3_photos_and_grouped_properties() {
+        let vcard = format!(
+            "BEGIN:VCARD\r\nVERSION:3.0\r\nFN:Grace Hopper\r\n\
+             item1.PHOTO;ENCODING=b;TYPE=PNG:iVBORw0K\r\n Ggo=\r\n\
+             item2.{PUBLIC_KEY_PROPERTY}:{}\r\nEND:VCARD\r\n",
+            agent(5)
+        );
+        let (imported, skipped) = new_contacts(&vcard, vec![]);
+        assert_eq!(skipped, 0);
+        assert_eq!(
+            imported,
+            vec![contact(5, "Grace Hopper", "", "data:image/png;base64,iVBORw0KGgo=")]
+        );
+    }
+
+    #[test]
+    fn skips_known_keys_duplicates_and_cards_without_keys() {
+        let mut vcard = export(&[
+            contact(1, "Me", "", ""),
+            contact(2, "Known", "", ""),
+            contact(3, "New", "", ""),
+            contact(3, "New again", "", ""),
+        ]);
+        vcard.push_str("BEGIN:VCARD\r\nVERSION:4.0\r\nFN:No key\r\nEND:VCARD\r\n");
+        let (imported, skipped) = new_contacts(&vcard, vec![agent(1), agent(2)]);
+        assert_eq!(imported, vec![contact(3, "New", "", "")]);
+        assert_eq!(skipped, 4);
+    }
+}
@@ -0,0 +1,282 @@
+use hdk::prelude::*;
+use relay_integrity::*;
+
+use crate::{get_my_membrane_proof, RemoteSignal};
+
+fn all_device_links_path() -> ExternResult<EntryHash> {
+    Path::from("all_device_links").path_entry_hash()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeviceLinkRequestRecord {
+    pub action_hash: ActionHash,
+    pub request: DeviceLinkRequest,
+}
+
+// Called by the new device in the device sync cell, with the existing device's key from its QR code
+#[hdk_extern]
+pub fn request_device_link(linked_to: AgentPubKey) -> ExternResult<Record> {
+    let device = agent_info()?.agent_latest_pubkey;
+    let data = DeviceLinkData {
+        device: device.clone(),
+        linked_to: linked_to.clone(),
+    };
+    let request = DeviceLinkRequest {
+        device: device.clone(),
+        linked_to: linked_to.clone(),
+        device_signature: sign(device, data)?,
+    };
+    let request_hash = create_entry(&EntryTypes::DeviceLinkRequest(request))?;
+    create_link(linked_to, request_hash.clone(), LinkTypes::DeviceLinkRequests, ())?;
+    get(request_hash, GetOptions::default())?
+        .ok_or(
+            wasm_error!(
+                WasmErrorInner::Guest("Could not find the newly created DeviceLinkRequest"
+                .to_string())
+            ),
+        )
+}
+
+// Requests addressed to this device that it hasn't accepted yet
+#[hdk_extern]
+pub fn get_device_link_requests(_: ()) -> ExternResult<Vec<DeviceLinkRequestRecord>> {
+    let me = agent_info()?.agent_latest_pubkey;
+    let linked_devices = get_linked_devices(me.clone())?;
+    let links = get_links(
+        GetLinksInputBuilder::try_new(me, LinkTypes::DeviceLinkRequests)?.build(),
+    )?;
+    let mut requests = vec![];
+    for link in links {
+        let Some(action_hash) = link.target.into_action_hash() else {
+            continue;
+        };
+        let Some(record) = get(action_hash.clone(), GetOptions::default())? else {
+            continue;
+        };
+        let Some(request) = record
+            .entry()
+            .to_app_option::<DeviceLinkRequest>()
+            .map_err(|e| wasm_error!(e))? else {
+            continue;
+        };
+        if !linked_devices.contains(&request.device) {
+            requests.push(DeviceLinkRequestRecord { action_hash, request });
+        }
+    }
+    Ok(requests)
+}
+
+// Countersigns a request and publishes the resulting link in this cell
+#[hdk_extern]
+pub fn accept_device_link(request_hash: ActionHash) -> ExternResult<DeviceLink> {
+    let me = agent_info()?.agent_latest_pubkey;
+    let record = get(request_hash, GetOptions::default())?
+        .ok_or(
+            wasm_error!(
+                WasmErrorInner::Guest("Could not find the DeviceLinkRequest".to_string())
+            ),
+        )?;
+    let request: DeviceLinkRequest = record
+        .entry()
+        .to_app_option()
+        .map_err(|e| wasm_error!(e))?
+        .ok_or(
+            wasm_error!(
+                WasmErrorInner::Guest("Record is not a DeviceLinkRequest".to_string())
+            ),
+        )?;
+    if request.linked_to != me {
+        return Err(
+            wasm_error!(
+                WasmErrorInner::Guest("The device link request is for another device"
+                .to_string())
+            ),
+        );
+    }
+    let data = DeviceLinkData {
+        device: request.device.clone(),
+        linked_to: me.clone(),
+    };
+    let device_link = DeviceLink {
+        device: request.device,
+        linked_to: me.clone(),
+        device_signature: request.device_signature,
+        linked_to_signature: sign(me, data)?,
+    };
+    publish_device_link(device_link.clone())?;
+    let _ = send_remote_signal(RemoteSignal::DeviceSync, vec![device_link.device.clone()]);
+    Ok(device_link)
+}
+
+// Publishes a link both devices have signed, so the other members of a conversation can resolve it.
+// Does nothing if it's already published.
+#[hdk_extern]
+pub fn publish_device_link(device_link: DeviceLink) -> ExternResult<Option<ActionHash>> {
+    if get_all_device_links(())?.contains(&device_link) {
+        return Ok(None);
+    }
+    let device_link_hash = create_entry(&EntryTypes::DeviceLink(device_link))?;
+    create_link(
+        all_device_links_path()?,
+        device_link_hash.clone(),
+        LinkTypes::AllDeviceLinks,
+        (),
+    )?;
+    Ok(Some(device_link_hash))
+}
+
+#[hdk_extern]
+pub fn get_all_device_links(_: ()) -> ExternResult<Vec<DeviceLink>> {
+    Ok(device_link_records()?.into_iter().map(|(_, device_link)| device_link).collect())
+}
+
+fn device_link_records() -> ExternResult<Vec<(ActionHash, DeviceLink)>> {
+    let links = get_links(
+        GetLinksInputBuilder::try_new(all_device_links_path()?, LinkTypes::AllDeviceLinks)?
+            .build(),
+    )?;
+    let mut device_links = vec![];
+    for link in links {
+        let Some(action_hash) = link.target.into_action_hash() else {
+            continue;
+        };
+        let Some(record) = get(action_hash.clone(), GetOptions::default())? else {
+            continue;
+        };
+        if let Some(device_link) = record
+            .entry()
+            .to_app_option::<DeviceLink>()
+            .map_err(|e| wasm_error!(e))?
+        {
+            device_links.push((action_hash, device_link));
+        }
+    }
+    Ok(device_links)
+}
+
+// The links this device is part of
+#[hdk_extern]
+pub fn get_my_device_links(_: ()) -> ExternResult<Vec<DeviceLink>> {
+    let me = agent_info()?.agent_latest_pubkey;
+    Ok(
+        get_all_device_links(())?
+            .into_iter()
+            .filter(|device_link| device_link.other_device(&me).is_some())
+            .collect(),
+    )
+}
+
+// All other devices of the same user as `agent`, following the links between devices
+#[hdk_extern]
+pub fn get_linked_devices(agent: AgentPubKey) -> ExternResult<Vec<AgentPubKey>> {
+    let device_links = get_all_device_links(())?;
+    let mut devices = vec![agent];
+    let mut i = 0;
+    while i < devices.len() {
+        for device_link in &device_links {
+            if let Some(other) = device_link.other_device(&devices[i]) {
+                if !devices.contains(&other) {
+                    devices.push(other);
+                }
+            }
+        }
+        i += 1;
+    }
+    devices.remove(0);
+    Ok(devices)
+}
+
+// Issues a membrane proof for this conversation to the other device of `device_link`.
+// Unless this device is the progenitor, its own proof is included so the chain can be validated.
+#[hdk_extern]
+pub fn generate_linked_device_membrane_proof(
+    device_link: DeviceLink,
+) -> ExternResult<SerializedBytes> {
+    let me = agent_info()?.agent_latest_pubkey;
+    let device = device_link
+        .other_device(&me)
+        .ok_or(
+            wasm_error!(
+                WasmErrorInner::Guest("This device is not part of the device link"
+                .to_string())
+            ),
+        )?;
+    let info = dna_info()?;
+    let props = Properties::try_from(info.modifiers.properties)
+        .map_err(|e| wasm_error!(e))?;
+    let my_proof = if me == props.progenitor { None } else { get_my_membrane_proof(())? };
+    let as_role = match &my_proof {
+        Some(proof) => {
+            MembraneProofEnvelope::try_from(proof.clone())
+                .map_err(|e| wasm_error!(e))?
+                .data
+                .as_role
+        }
+        None => 0,
+    };
+    let data = MembraneProofData {
+        conversation_id: info.modifiers.network_seed,
+        for_agent: device,
+        as_role,
+    };
+    let envelope = MembraneProofEnvelope {
+        signature: sign(me, data.clone())?,
+        data,
+        device_link: Some(LinkedDeviceProof {
+            link: device_link,
+            membrane_proof: my_proof,
+        }),
+    };
+    SerializedBytes::try_from(envelope).map_err(|e| wasm_error!(e))
+}
+
+// Leaves an invite code in the device sync cell for a linked device to pick up.
+// The invite refers to the link between the two devices, which validation requires.
+#[hdk_extern]
+pub fn invite_linked_device(mut invite: ConversationInvite) -> ExternResult<ActionHash> {
+    let me = agent_info()?.agent_latest_pubkey;
+    let device_link_hash = device_link_records()?
+        .into_iter()
+        .find(|(_, device_link)| device_link.other_device(&me).as_ref() == Some(&invite.for_device))
+        .map(|(action_hash, _)| action_hash)
+        .ok_or(
+            wasm_error!(
+                WasmErrorInner::Guest("The device to invite is not linked to this one"
+                .to_string())
+            ),
+        )?;
+    invite.device_link = Some(device_link_hash);
+    let invite_hash = create_entry(&EntryTypes::ConversationInvite(invite.clone()))?;
+    create_link(
+        invite.for_device.clone(),
+        invite_hash.clone(),
+        LinkTypes::ConversationInvites,
+        (),
+    )?;
+    let _ = send_remote_signal(RemoteSignal::DeviceSync, vec![invite.for_device]);
+    Ok(invite_hash)
+}
+
+#[hdk_extern]
+pub fn get_conversation_invites(for_device: AgentPubKey) -> ExternResult<Vec<ConversationInvite>> {
+    let links = get_links(
+        GetLinksInputBuilder::try_new(for_device, LinkTypes::ConversationInvites)?.build(),
+    )?;
+    let mut invites = vec![];
+    for link in links {
+        let Some(action_hash) = link.target.into_action_hash() else {
+            continue;
+        };
+        let Some(record) = get(action_hash, GetOptions::default())? else {
+            continue;
+        };
+        if let Some(invite) = record
+            .entry()
+            .to_app_option::<ConversationInvite>()
+            .map_err(|e| wasm_error!(e))?
+        {
+            invites.push(invite);
+        }
+    }
+    Ok(invites)
+}
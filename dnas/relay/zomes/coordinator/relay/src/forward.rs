@@ -0,0 +1,134 @@
+use hdk::prelude::*;
+use relay_integrity::*;
+
+use crate::message::{get_latest_message, SendMessageInput};
+use crate::message_files::stored_file;
+
+// Messages are forwarded from the cell of the conversation they are in,
+// which copies them into the cell of another conversation along with the action their
+// author signed them with
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardMessageInput {
+    pub original_message_hash: ActionHash,
+    pub target_cell_id: CellId,
+    // The bucket of the target conversation the message is posted in
+    pub bucket: u32,
+    pub agents: Vec<AgentPubKey>,
+}
+
+fn call_cell<I, O>(
+    cell_id: &CellId,
+    zome_name: &str,
+    fn_name: &str,
+    payload: I,
+) -> ExternResult<O>
+where
+    I: serde::Serialize + std::fmt::Debug,
+    O: serde::de::DeserializeOwned + std::fmt::Debug,
+{
+    let response = call(
+        CallTargetCell::OtherCell(cell_id.clone()),
+        zome_name,
+        fn_name.into(),
+        None,
+        payload,
+    )?;
+    match response {
+        ZomeCallResponse::Ok(result) => result.decode().map_err(|e| wasm_error!(e)),
+        _ => Err(wasm_error!(WasmErrorInner::Guest(format!(
+            "Failed to call {fn_name} in the target conversation: {response:?}"
+        )))),
+    }
+}
+
+// Creates a stored file's chunks and metadata in another cell.
+// Chunks are content addressed, so their hashes are the same there.
+fn copy_stored_file(hash: &EntryHash, target: &CellId) -> ExternResult<EntryHash> {
+    let metadata = stored_file(hash)?.ok_or(wasm_error!(WasmErrorInner::Guest(
+        "Attachment file not found".to_string()
+    )))?;
+    for chunk_hash in &metadata.chunks_hashes {
+        let Entry::App(bytes) = must_get_entry(chunk_hash.clone())?.content else {
+            return Err(wasm_error!(WasmErrorInner::Guest(
+                "Malformed file chunk".to_string()
+            )));
+        };
+        let chunk = FileChunk::try_from(bytes.into_sb()).map_err(|e| wasm_error!(e))?;
+        let _: EntryHash = call_cell(target, "file_storage", "create_file_chunk", chunk)?;
+    }
+    call_cell(target, "file_storage", "create_file_metadata", metadata)
+}
+
+// Reuses a copy of the file this agent stored in the target cell already, or copies it there
+fn copy_file(file: &mut File, target: &CellId) -> ExternResult<()> {
+    if let Some(thumbnail_hash) = &file.thumbnail_storage_entry_hash {
+        file.thumbnail_storage_entry_hash = Some(copy_stored_file(thumbnail_hash, target)?);
+    }
+    if let Some(content_hash) = &file.content_hash {
+        let existing: Option<File> =
+            call_cell(target, "relay", "get_file_by_content_hash", content_hash.clone())?;
+        if let Some(existing) = existing {
+            if existing.size == file.size && existing.file_type == file.file_type {
+                file.storage_entry_hash = existing.storage_entry_hash;
+                return Ok(());
+            }
+        }
+    }
+    file.storage_entry_hash = copy_stored_file(&file.storage_entry_hash, target)?;
+    Ok(())
+}
+
+// The latest version of the message, as its author signed it
+fn original_message(record: MessageRecord) -> ExternResult<ForwardedFrom> {
+    let action = record.signed_action.action();
+    let entry_hash = action.entry_hash().ok_or(wasm_error!(WasmErrorInner::Guest(
+        "Message action has no entry".to_string()
+    )))?;
+    Ok(ForwardedFrom {
+        author: action.author().clone(),
+        entry: must_get_entry(entry_hash.clone())?.content,
+        signed_action: record.signed_action,
+    })
+}
+
+#[hdk_extern]
+pub fn forward_message(input: ForwardMessageInput) -> ExternResult<Record> {
+    let record = get_latest_message(input.original_message_hash)?.ok_or(wasm_error!(
+        WasmErrorInner::Guest("Message not found".to_string())
+    ))?;
+    let message = record.message.clone().ok_or(wasm_error!(WasmErrorInner::Guest(
+        "Message not found".to_string()
+    )))?;
+    // A message that was forwarded already keeps where it was first written
+    let forwarded_from = match message.forwarded_from {
+        Some(forwarded_from) => forwarded_from,
+        None => original_message(record)?,
+    };
+    let mut attachments = message.attachments;
+    for attachment in attachments.iter_mut() {
+        copy_file(attachment.file_mut(), &input.target_cell_id)?;
+    }
+    let mut link_preview = message.link_preview;
+    if let Some(image) = link_preview.as_mut().and_then(|preview| preview.image.as_mut()) {
+        copy_file(image, &input.target_cell_id)?;
+    }
+    call_cell(
+        &input.target_cell_id,
+        "relay",
+        "create_message",
+        SendMessageInput {
+            message: Message {
+                content: message.content,
+                bucket: input.bucket,
+                attachments,
+                forwarded_from: Some(forwarded_from),
+                // Who is mentioned only means something in the original conversation
+                mentions: vec![],
+                link_preview,
+            },
+            agents: input.agents,
+        },
+    )
+}
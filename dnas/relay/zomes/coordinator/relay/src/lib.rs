@@ -2,17 +2,53 @@ pub mod contact;
 pub mod message;
 pub mod config;
 pub mod ping;
+pub mod device_link;
+pub mod contact_group;
+pub mod block;
+pub mod contact_sync;
+pub mod contact_vcard;
+pub mod message_files;
+pub mod forward;
 use hdk::prelude::*;
 use relay_integrity::*;
 
+// What other agents send with `send_remote_signal`. Untagged, so messages keep the shape
+// earlier versions send.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum RemoteSignal {
+    Message(MessageRecord),
+    // Sent in the device sync cell by a linked device after it linked to or invited this one
+    DeviceSync,
+}
+
 #[hdk_extern]
-fn recv_remote_signal(message_record: MessageRecord) -> ExternResult<()> {
+fn recv_remote_signal(remote_signal: RemoteSignal) -> ExternResult<()> {
     let info: CallInfo = call_info()?;
+    let message_record = match remote_signal {
+        RemoteSignal::Message(message_record) => message_record,
+        RemoteSignal::DeviceSync => {
+            return emit_signal(Signal::DeviceSyncRequested {
+                from: info.provenance,
+            });
+        }
+    };
+    // Signals of blocked agents are dropped by the UI, which keeps the block list, rather than
+    // asking the base cell for it on every signal
     let message = message_record.message.unwrap();
-    let signal = Signal::Message {
-        action: message_record.signed_action.clone(),
-        message,
-        from: info.provenance,
+    let me = agent_info()?.agent_latest_pubkey;
+    let signal = if message.mentions.iter().any(|mention| mention.agent == me) {
+        Signal::Mentioned {
+            action: message_record.signed_action.clone(),
+            message,
+            from: info.provenance,
+        }
+    } else {
+        Signal::Message {
+            action: message_record.signed_action.clone(),
+            message,
+            from: info.provenance,
+        }
     };
     emit_signal(signal)
 }
@@ -34,6 +70,8 @@ pub fn init(_: ()) -> ExternResult<InitCallbackResult> {
 #[serde(tag = "type")]
 pub enum Signal {
     Message { action: SignedActionHashed, message: Message, from: AgentPubKey },
+    // A message that mentions this agent, which notifies them even in archived conversations
+    Mentioned { action: SignedActionHashed, message: Message, from: AgentPubKey },
     LinkCreated { action: SignedActionHashed, link_type: LinkTypes },
     LinkDeleted {
         action: SignedActionHashed,
@@ -47,6 +85,16 @@ pub enum Signal {
         original_app_entry: EntryTypes,
     },
     EntryDeleted { action: SignedActionHashed, original_app_entry: EntryTypes },
+    // A contact was updated to a different key, which may mean someone is impersonating them
+    ContactKeyChanged {
+        original_contact_hash: ActionHash,
+        previous_key: AgentPubKey,
+        new_key: AgentPubKey,
+    },
+    // A contact's name or avatar differs from the latest profile of its agent
+    ContactProfileChanged { original_contact_hash: ActionHash, updated_contact: Contact },
+    // A linked device has something new in the device sync cell for this one
+    DeviceSyncRequested { from: AgentPubKey },
 }
 #[hdk_extern(infallible)]
 pub fn post_commit(committed_actions: Vec<SignedActionHashed>) {
@@ -172,6 +220,7 @@ pub fn generate_membrane_proof(input: MembraneProofData) -> ExternResult<Seriali
     let result = MembraneProofEnvelope {
         signature: sign(me,input.clone())?,
         data: input,
+        device_link: None,
     };
     let proof = SerializedBytes::try_from(result).map_err(|e| wasm_error!(e))?;
     Ok(proof)
@@ -210,4 +259,16 @@ pub fn get_membrane_proof(agent: AgentPubKey) -> ExternResult<Option<MembranePro
             }
         }
     }
-}
\ No newline at end of file
+}
+// The membrane proof this agent joined with, so the same conversation can be joined again
+// when restoring from a backup
+#[hdk_extern]
+pub fn get_my_membrane_proof(_: ()) -> ExternResult<Option<SerializedBytes>> {
+    let records = query(ChainQueryFilter::new().action_type(ActionType::AgentValidationPkg))?;
+    Ok(records.first().and_then(|record| match record.action() {
+        Action::AgentValidationPkg(AgentValidationPkg { membrane_proof, .. }) => {
+            membrane_proof.as_ref().map(|proof| (**proof).clone())
+        }
+        _ => None,
+    }))
+}
@@ -1,6 +1,8 @@
 use hdk::prelude::*;
 use relay_integrity::*;
 
+use crate::block::get_blocked_agents;
+use crate::message_files::{dedupe_message_files, delete_message_files};
 use crate::get_entry_for_action;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -10,9 +12,32 @@ pub struct SendMessageInput {
     pub agents: Vec<AgentPubKey>,
 }
 
+// The action an agent joined this conversation with, which is the second on their chain
+fn membership_action(agent: &AgentPubKey) -> ExternResult<Option<ActionHash>> {
+    let activity = get_agent_activity(
+        agent.clone(),
+        ChainQueryFilter::new().sequence_range(ChainQueryFilterRange::ActionSeqRange(1, 1)),
+        ActivityRequest::Full,
+    )?;
+    Ok(activity.valid_activity.into_iter().next().map(|(_, hash)| hash))
+}
+
+// Adds the proof that each mentioned agent is a member, which validation requires
+fn prove_mentions(message: &mut Message) -> ExternResult<()> {
+    for mention in message.mentions.iter_mut().filter(|m| m.membership.is_none()) {
+        mention.membership = Some(membership_action(&mention.agent)?.ok_or(wasm_error!(
+            WasmErrorInner::Guest(format!("{} is not a member of this conversation", mention.agent))
+        ))?);
+    }
+    Ok(())
+}
+
 #[hdk_extern]
 pub fn create_message(input: SendMessageInput) -> ExternResult<Record> {
-    let message_hash = create_entry(&EntryTypes::Message(input.message.clone()))?;
+    let mut message = input.message;
+    dedupe_message_files(&mut message)?;
+    prove_mentions(&mut message)?;
+    let message_hash = create_entry(&EntryTypes::Message(message.clone()))?;
     let record = get(message_hash.clone(), GetOptions::default())?
         .ok_or(
             wasm_error!(
@@ -21,7 +46,7 @@ pub fn create_message(input: SendMessageInput) -> ExternResult<Record> {
             ),
         )?;
 
-    let path = messages_path(input.message.bucket);
+    let path = messages_path(message.bucket);
     debug!("create_message path {:?}", path);
     let link = create_link(
         path.path_entry_hash()?,
@@ -30,19 +55,55 @@ pub fn create_message(input: SendMessageInput) -> ExternResult<Record> {
         (),
     )?;
 
-    let _ = send_remote_signal(
-        MessageRecord {
-            message: Some(input.message),
-            original_action: message_hash.clone(),
-            signed_action: record.signed_action().clone()
-        },
-        input.agents,
-    );
+    // Mentioned agents are signaled first, so they hear of it even if signaling everyone fails
+    let (mentioned, others): (Vec<_>, Vec<_>) = input
+        .agents
+        .into_iter()
+        .partition(|agent| message.mentions.iter().any(|m| &m.agent == agent));
+    let message_record = MessageRecord {
+        message: Some(message),
+        original_action: message_hash.clone(),
+        signed_action: record.signed_action().clone(),
+    };
+    if !mentioned.is_empty() {
+        let _ = send_remote_signal(&message_record, mentioned);
+    }
+    let _ = send_remote_signal(message_record, others);
 
     debug!("create message all messages link: {:?}", link);
     Ok(record)
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SendVoiceMessageInput {
+    pub bucket: u32,
+    pub file: File,
+    pub duration_ms: u64,
+    pub waveform: Vec<u8>,
+    pub agents: Vec<AgentPubKey>,
+}
+
+// Posts a recorded voice message, whose file is already in file_storage
+#[hdk_extern]
+pub fn create_voice_message(input: SendVoiceMessageInput) -> ExternResult<Record> {
+    create_message(SendMessageInput {
+        message: Message {
+            content: String::new(),
+            bucket: input.bucket,
+            attachments: vec![Attachment::Voice {
+                file: input.file,
+                duration_ms: input.duration_ms,
+                waveform: input.waveform,
+            }],
+            forwarded_from: None,
+            mentions: vec![],
+            link_preview: None,
+        },
+        agents: input.agents,
+    })
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BucketInput {
     pub bucket: u32,
@@ -86,20 +147,78 @@ struct GetAgenProfileInput {
     agent_key: AgentPubKey,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(from = "HashesOrInput")]
+pub struct GetMessageEntriesInput {
+    pub hashes: Vec<ActionHash>,
+    pub include_blocked: bool,
+}
+
+// Earlier versions took the hashes alone, which still leaves out blocked agents
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HashesOrInput {
+    Hashes(Vec<ActionHash>),
+    Input {
+        hashes: Vec<ActionHash>,
+        #[serde(default)]
+        include_blocked: bool,
+    },
+}
+
+impl From<HashesOrInput> for GetMessageEntriesInput {
+    fn from(input: HashesOrInput) -> Self {
+        match input {
+            HashesOrInput::Hashes(hashes) => Self { hashes, include_blocked: false },
+            HashesOrInput::Input { hashes, include_blocked } => Self { hashes, include_blocked },
+        }
+    }
+}
+
 #[hdk_extern]
-pub fn get_message_entries(hashes: Vec<ActionHash>) -> ExternResult<Vec<MessageRecord>> {
+pub fn get_message_entries(input: GetMessageEntriesInput) -> ExternResult<Vec<MessageRecord>> {
     let mut results: Vec<MessageRecord> = Vec::new();
-    for hash in hashes {
+    for hash in input.hashes {
         if let Some(r) = get_latest_message(hash)? {
             results.push (r);
         }
     }
-    Ok(results)
+    without_blocked(results, input.include_blocked)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(from = "BucketsOrInput")]
+pub struct GetMessagesForBucketsInput {
+    pub buckets: Vec<u32>,
+    pub include_blocked: bool,
+}
+
+// Earlier versions took the buckets alone, which still leaves out blocked agents
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BucketsOrInput {
+    Buckets(Vec<u32>),
+    Input {
+        buckets: Vec<u32>,
+        #[serde(default)]
+        include_blocked: bool,
+    },
+}
+
+impl From<BucketsOrInput> for GetMessagesForBucketsInput {
+    fn from(input: BucketsOrInput) -> Self {
+        match input {
+            BucketsOrInput::Buckets(buckets) => Self { buckets, include_blocked: false },
+            BucketsOrInput::Input { buckets, include_blocked } => Self { buckets, include_blocked },
+        }
+    }
 }
 
 #[hdk_extern]
-pub fn get_messages_for_buckets(buckets: Vec<u32>) -> ExternResult<Vec<MessageRecord>> {
-    let links = get_message_links_for_buckets(buckets)?;
+pub fn get_messages_for_buckets(
+    input: GetMessagesForBucketsInput,
+) -> ExternResult<Vec<MessageRecord>> {
+    let links = get_message_links_for_buckets(input.buckets)?;
     let mut results: Vec<MessageRecord> = Vec::new();
     for l in links {
         let hash  = ActionHash::try_from(l.target).map_err(|e|wasm_error!(e))?;
@@ -108,7 +227,24 @@ pub fn get_messages_for_buckets(buckets: Vec<u32>) -> ExternResult<Vec<MessageRe
         }
     }
 
-    Ok(results)
+    without_blocked(results, input.include_blocked)
+}
+
+// Drops the messages of blocked agents, unless they were asked for
+fn without_blocked(
+    messages: Vec<MessageRecord>,
+    include_blocked: bool,
+) -> ExternResult<Vec<MessageRecord>> {
+    if include_blocked {
+        return Ok(messages);
+    }
+    let blocked = get_blocked_agents(())?;
+    Ok(
+        messages
+            .into_iter()
+            .filter(|m| !blocked.contains(m.signed_action.hashed.content.author()))
+            .collect(),
+    )
 }
 
 #[hdk_extern]
@@ -218,9 +354,11 @@ pub struct UpdateMessageInput {
 }
 #[hdk_extern]
 pub fn update_message(input: UpdateMessageInput) -> ExternResult<Record> {
+    let mut updated_message = input.updated_message;
+    prove_mentions(&mut updated_message)?;
     let updated_message_hash = update_entry(
         input.previous_message_hash.clone(),
-        &input.updated_message,
+        &updated_message,
     )?;
     create_link(
         input.original_message_hash.clone(),
@@ -270,7 +408,9 @@ pub fn delete_message(original_message_hash: ActionHash) -> ExternResult<ActionH
             }
         }
     }
-    delete_entry(original_message_hash)
+    let delete_hash = delete_entry(original_message_hash.clone())?;
+    delete_message_files(&original_message_hash)?;
+    Ok(delete_hash)
 }
 
 #[hdk_extern]
@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, HashSet};
+
+use hdk::prelude::*;
+use relay_integrity::*;
+
+use crate::contact::{deleted_actions, entry_revisions};
+
+// Attachments are stored by the file_storage zome as a metadata entry and its chunks.
+// Only an agent's own entries can be deleted, so everything here is read from their chain.
+// Files are only reused by the agent who stored them, so no other agent's messages refer to them.
+
+const FILE_STORAGE_ZOME: &str = "file_storage_integrity";
+
+// The files of a message's attachments and of its link preview's image
+fn message_files(record: &Record) -> ExternResult<Vec<File>> {
+    let message = record
+        .entry()
+        .to_app_option::<Message>()
+        .map_err(|e| wasm_error!(e))?;
+    Ok(message
+        .map(|message| {
+            let preview_image = message.link_preview.and_then(|preview| preview.image);
+            message
+                .attachments
+                .into_iter()
+                .map(|attachment| attachment.file().clone())
+                .chain(preview_image)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+// The metadata entries of a file and its thumbnail
+fn metadata_hashes(file: &File) -> impl Iterator<Item = &EntryHash> {
+    std::iter::once(&file.storage_entry_hash).chain(&file.thumbnail_storage_entry_hash)
+}
+
+pub(crate) fn stored_file(hash: &EntryHash) -> ExternResult<Option<FileMetadata>> {
+    let Entry::App(bytes) = must_get_entry(hash.clone())?.content else {
+        return Ok(None);
+    };
+    Ok(FileMetadata::try_from(bytes.into_sb()).ok())
+}
+
+// A metadata entry with its chunks
+fn with_chunks(hash: &EntryHash) -> ExternResult<Vec<EntryHash>> {
+    let mut hashes = vec![hash.clone()];
+    if let Some(metadata) = stored_file(hash)? {
+        hashes.extend(metadata.chunks_hashes);
+    }
+    Ok(hashes)
+}
+
+// The entries this agent created and didn't delete, with the actions that created them
+fn own_entries(deleted: &HashSet<ActionHash>) -> ExternResult<BTreeMap<EntryHash, ActionHash>> {
+    Ok(query(ChainQueryFilter::new().action_type(ActionType::Create))?
+        .into_iter()
+        .filter(|record| !deleted.contains(record.action_address()))
+        .filter_map(|record| {
+            let entry_hash = record.action().entry_hash()?.clone();
+            Some((entry_hash, record.action_address().clone()))
+        })
+        .collect())
+}
+
+// Every entry of the files that messages on this chain, which weren't deleted, refer to
+fn referenced_entries(deleted: &HashSet<ActionHash>) -> ExternResult<HashSet<EntryHash>> {
+    let mut referenced = HashSet::new();
+    for (original, record) in entry_revisions(UnitEntryTypes::Message)? {
+        if deleted.contains(&original) {
+            continue;
+        }
+        for file in message_files(&record)? {
+            for hash in metadata_hashes(&file) {
+                referenced.extend(with_chunks(hash)?);
+            }
+        }
+    }
+    Ok(referenced)
+}
+
+// Deletes this agent's own entries of the files attached to any revision of a message,
+// unless a message that wasn't deleted still refers to them.
+// Called once the message itself is deleted.
+pub(crate) fn delete_message_files(original_message_hash: &ActionHash) -> ExternResult<()> {
+    let deleted = deleted_actions()?;
+    let own = own_entries(&deleted)?;
+    let mut files = vec![];
+    for (original, record) in entry_revisions(UnitEntryTypes::Message)? {
+        if &original == original_message_hash {
+            files.extend(message_files(&record)?);
+        }
+    }
+    if files.is_empty() {
+        return Ok(());
+    }
+    let referenced = referenced_entries(&deleted)?;
+    let mut to_delete = HashSet::new();
+    for file in &files {
+        for hash in metadata_hashes(file).filter(|hash| own.contains_key(*hash)) {
+            to_delete.extend(with_chunks(hash)?);
+        }
+    }
+    for hash in to_delete.difference(&referenced) {
+        if let Some(action_hash) = own.get(hash) {
+            delete_entry(action_hash.clone())?;
+        }
+    }
+    Ok(())
+}
+
+// This agent's files that none of their messages refer to anymore, e.g. the attachments of
+// messages deleted before files were deleted with them. Files being uploaded for a message
+// that isn't sent yet are listed too.
+#[hdk_extern]
+pub fn get_orphaned_files(_: ()) -> ExternResult<Vec<EntryHash>> {
+    let Some(zome_index) = dna_info()?
+        .zome_names
+        .iter()
+        .position(|name| name.0 == FILE_STORAGE_ZOME)
+    else {
+        return Ok(vec![]);
+    };
+    let deleted = deleted_actions()?;
+    let referenced = referenced_entries(&deleted)?;
+    let mut orphaned = vec![];
+    for record in query(ChainQueryFilter::new().action_type(ActionType::Create))? {
+        let Some(EntryType::App(def)) = record.action().entry_type() else {
+            continue;
+        };
+        let Some(entry_hash) = record.action().entry_hash() else {
+            continue;
+        };
+        if def.zome_index.0 as usize != zome_index
+            || deleted.contains(record.action_address())
+            || referenced.contains(entry_hash)
+        {
+            continue;
+        }
+        // Chunks are file_storage entries too, but aren't files
+        if stored_file(entry_hash)?.is_some() {
+            orphaned.push(entry_hash.clone());
+        }
+    }
+    Ok(orphaned)
+}
+
+// A file with these contents that this agent stored in this cell already and didn't delete,
+// so it doesn't need to be uploaded again. Anyone can link a file under any content hash,
+// so the files of other agents aren't trusted to have these contents.
+#[hdk_extern]
+pub fn get_file_by_content_hash(content_hash: String) -> ExternResult<Option<File>> {
+    let me = agent_info()?.agent_latest_pubkey;
+    let own = own_entries(&deleted_actions()?)?;
+    let links = get_links(
+        GetLinksInputBuilder::try_new(
+            file_contents_path(&content_hash).path_entry_hash()?,
+            LinkTypes::FileContents,
+        )?
+        .build(),
+    )?;
+    let Some(storage_entry_hash) = links
+        .into_iter()
+        .filter(|link| link.author == me)
+        .filter_map(|link| Some((link.timestamp, link.target.into_entry_hash()?)))
+        .filter(|(_, hash)| own.contains_key(hash))
+        .min_by_key(|(timestamp, _)| *timestamp)
+        .map(|(_, hash)| hash)
+    else {
+        return Ok(None);
+    };
+    Ok(stored_file(&storage_entry_hash)?.map(|metadata| File {
+        name: metadata.name,
+        last_modified: metadata.last_modified,
+        size: metadata.size,
+        file_type: metadata.file_type,
+        storage_entry_hash,
+        thumbnail_storage_entry_hash: None,
+        content_hash: Some(content_hash),
+    }))
+}
+
+// Points the attachments of a new message at stored files with the same contents,
+// and indexes the ones that are new
+pub(crate) fn dedupe_message_files(message: &mut Message) -> ExternResult<()> {
+    for attachment in message.attachments.iter_mut() {
+        let file = attachment.file_mut();
+        let Some(content_hash) = file.content_hash.clone() else {
+            continue;
+        };
+        match get_file_by_content_hash(content_hash.clone())? {
+            // Validation compares the size and type with the stored file's
+            Some(existing)
+                if existing.size == file.size && existing.file_type == file.file_type =>
+            {
+                file.storage_entry_hash = existing.storage_entry_hash;
+            }
+            Some(_) => {}
+            None => {
+                create_link(
+                    file_contents_path(&content_hash).path_entry_hash()?,
+                    file.storage_entry_hash.clone(),
+                    LinkTypes::FileContents,
+                    (),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use hdi::prelude::*;
+
+// An agent whose messages and signals are ignored
+#[derive(Clone, PartialEq)]
+#[hdk_entry_helper]
+pub struct Block {
+    pub agent: AgentPubKey,
+}
+
+pub fn validate_create_block(
+    _action: EntryCreationAction,
+    _block: Block,
+) -> ExternResult<ValidateCallbackResult> {
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_update_block(
+    _action: Update,
+    _block: Block,
+) -> ExternResult<ValidateCallbackResult> {
+    Ok(ValidateCallbackResult::Invalid(String::from("Blocks cannot be updated")))
+}
+
+pub fn validate_delete_block(
+    _action: Delete,
+    _original_action: EntryCreationAction,
+    _original_block: Block,
+) -> ExternResult<ValidateCallbackResult> {
+    Ok(ValidateCallbackResult::Valid)
+}
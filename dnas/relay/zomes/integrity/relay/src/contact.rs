@@ -7,6 +7,40 @@ pub struct Contact {
     pub first_name: String,
     pub last_name: String,
     pub avatar: String,
+    // Set once the safety numbers were compared with the contact
+    #[serde(default)]
+    pub verification: Option<ContactVerification>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ContactVerification {
+    pub safety_number: String,
+    // Signed by the author over `ContactVerificationData`
+    pub signature: Signature,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, SerializedBytes)]
+pub struct ContactVerificationData {
+    pub public_key: AgentPubKey,
+    pub safety_number: String,
+}
+
+// A number two agents can read to each other to make sure they have each other's real keys.
+// It is the same whichever of the two computes it: 12 groups of 5 digits from a hash of both keys.
+pub fn safety_number(a: &AgentPubKey, b: &AgentPubKey) -> ExternResult<String> {
+    let mut keys = [a.get_raw_32().to_vec(), b.get_raw_32().to_vec()];
+    keys.sort();
+    let hash = hash_blake2b(keys.concat(), 60)?;
+    Ok(
+        hash
+            .chunks(5)
+            .map(|chunk| {
+                let n = chunk.iter().fold(0u64, |n, byte| (n << 8) | *byte as u64);
+                format!("{:05}", n % 100_000)
+            })
+            .collect::<Vec<String>>()
+            .join(" "),
+    )
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -17,9 +51,31 @@ pub struct ContactRecord {
 }
 
 pub fn validate_create_contact(
-    _action: EntryCreationAction,
-    _contact: Contact,
+    action: EntryCreationAction,
+    contact: Contact,
 ) -> ExternResult<ValidateCallbackResult> {
+    let Some(verification) = contact.verification else {
+        return Ok(ValidateCallbackResult::Valid);
+    };
+    // A verification can't be carried over to a different key
+    if verification.safety_number != safety_number(action.author(), &contact.public_key)? {
+        return Ok(
+            ValidateCallbackResult::Invalid(
+                String::from("Safety number does not match the contact's key"),
+            ),
+        );
+    }
+    let data = ContactVerificationData {
+        public_key: contact.public_key,
+        safety_number: verification.safety_number,
+    };
+    if !verify_signature(action.author().clone(), verification.signature, data)? {
+        return Ok(
+            ValidateCallbackResult::Invalid(
+                String::from("Contact verification signature is invalid"),
+            ),
+        );
+    }
     Ok(ValidateCallbackResult::Valid)
 }
 
@@ -33,10 +89,17 @@ pub fn validate_update_contact(
 }
 
 pub fn validate_delete_contact(
-    _action: Delete,
-    _original_action: EntryCreationAction,
+    action: Delete,
+    original_action: EntryCreationAction,
     _original_contact: Contact,
 ) -> ExternResult<ValidateCallbackResult> {
+    if &action.author != original_action.author() {
+        return Ok(
+            ValidateCallbackResult::Invalid(
+                String::from("Only the author of a contact can delete it"),
+            ),
+        );
+    }
     Ok(ValidateCallbackResult::Valid)
 }
 
@@ -122,18 +185,22 @@ pub fn validate_create_link_contact_updates(
     Ok(ValidateCallbackResult::Valid)
 }
 
+// Only deleted when published contacts are migrated to private ones
 pub fn validate_delete_link_contact_updates(
-    _action: DeleteLink,
-    _original_action: CreateLink,
+    action: DeleteLink,
+    original_action: CreateLink,
     _base: AnyLinkableHash,
     _target: AnyLinkableHash,
     _tag: LinkTag,
 ) -> ExternResult<ValidateCallbackResult> {
-    Ok(
-        ValidateCallbackResult::Invalid(
-            String::from("ContactUpdates links cannot be deleted"),
-        ),
-    )
+    if action.author != original_action.author {
+        return Ok(
+            ValidateCallbackResult::Invalid(
+                String::from("ContactUpdates links can only be deleted by their author"),
+            ),
+        );
+    }
+    Ok(ValidateCallbackResult::Valid)
 }
 
 pub fn validate_create_link_all_contacts(
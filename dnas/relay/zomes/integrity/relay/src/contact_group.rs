@@ -0,0 +1,43 @@
+use hdi::prelude::*;
+
+// A named set of contacts, referenced by the actions that created them
+#[derive(Clone, PartialEq)]
+#[hdk_entry_helper]
+pub struct ContactGroup {
+    pub name: String,
+    pub members: Vec<ActionHash>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContactGroupRecord {
+    pub original_action: ActionHash,
+    pub signed_action: SignedActionHashed,
+    pub group: Option<ContactGroup>,
+}
+
+pub fn validate_create_contact_group(
+    _action: EntryCreationAction,
+    group: ContactGroup,
+) -> ExternResult<ValidateCallbackResult> {
+    if group.name.trim().is_empty() {
+        return Ok(
+            ValidateCallbackResult::Invalid(String::from("Contact groups must have a name")),
+        );
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_update_contact_group(
+    _action: Update,
+    _group: ContactGroup,
+) -> ExternResult<ValidateCallbackResult> {
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_delete_contact_group(
+    _action: Delete,
+    _original_action: EntryCreationAction,
+    _original_group: ContactGroup,
+) -> ExternResult<ValidateCallbackResult> {
+    Ok(ValidateCallbackResult::Valid)
+}
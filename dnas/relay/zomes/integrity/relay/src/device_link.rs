@@ -0,0 +1,321 @@
+use hdi::prelude::*;
+
+// What both devices sign to be linked to one identity
+#[derive(Serialize, Deserialize, Debug, Clone, SerializedBytes, PartialEq)]
+pub struct DeviceLinkData {
+    pub device: AgentPubKey,
+    pub linked_to: AgentPubKey,
+}
+
+// Ties a new device to an existing one of the same user
+#[derive(Clone, PartialEq)]
+#[hdk_entry_helper]
+pub struct DeviceLink {
+    pub device: AgentPubKey,
+    pub linked_to: AgentPubKey,
+    pub device_signature: Signature,
+    pub linked_to_signature: Signature,
+}
+
+impl DeviceLink {
+    pub fn data(&self) -> DeviceLinkData {
+        DeviceLinkData {
+            device: self.device.clone(),
+            linked_to: self.linked_to.clone(),
+        }
+    }
+
+    pub fn verify(&self) -> ExternResult<bool> {
+        Ok(verify_signature(self.device.clone(), self.device_signature.clone(), self.data())?
+            && verify_signature(
+                self.linked_to.clone(),
+                self.linked_to_signature.clone(),
+                self.data(),
+            )?)
+    }
+
+    // The device on the other end of the link, if `agent` is one of the two
+    pub fn other_device(&self, agent: &AgentPubKey) -> Option<AgentPubKey> {
+        if &self.device == agent {
+            Some(self.linked_to.clone())
+        } else if &self.linked_to == agent {
+            Some(self.device.clone())
+        } else {
+            None
+        }
+    }
+}
+
+// Filed by the new device in the device sync cell, for the existing device to countersign
+#[derive(Clone, PartialEq)]
+#[hdk_entry_helper]
+pub struct DeviceLinkRequest {
+    pub device: AgentPubKey,
+    pub linked_to: AgentPubKey,
+    pub device_signature: Signature,
+}
+
+// An invite code for a conversation, with a membrane proof issued for a linked device
+#[derive(Clone, PartialEq)]
+#[hdk_entry_helper]
+pub struct ConversationInvite {
+    pub for_device: AgentPubKey,
+    pub network_seed: String,
+    pub invite_code: String,
+    // The device link between the author and `for_device`, filled in by the coordinator
+    #[serde(default)]
+    pub device_link: Option<ActionHash>,
+}
+
+// Only the original author can delete the entries and links of the device sync cell
+fn validate_delete_by_author(
+    author: &AgentPubKey,
+    original_author: &AgentPubKey,
+    what: &str,
+) -> ExternResult<ValidateCallbackResult> {
+    if author != original_author {
+        return Ok(ValidateCallbackResult::Invalid(format!(
+            "Only the author of {} can delete it",
+            what
+        )));
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_create_device_link(
+    action: EntryCreationAction,
+    device_link: DeviceLink,
+) -> ExternResult<ValidateCallbackResult> {
+    if device_link.other_device(action.author()).is_none() {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Only one of the linked devices can publish a device link",
+        )));
+    }
+    if !device_link.verify()? {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Device link signatures are invalid",
+        )));
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_update_device_link(
+    _action: Update,
+    _device_link: DeviceLink,
+) -> ExternResult<ValidateCallbackResult> {
+    Ok(ValidateCallbackResult::Invalid(String::from("Device links cannot be updated")))
+}
+
+pub fn validate_delete_device_link(
+    action: Delete,
+    _original_action: EntryCreationAction,
+    original_device_link: DeviceLink,
+) -> ExternResult<ValidateCallbackResult> {
+    if original_device_link.other_device(&action.author).is_none() {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Only one of the linked devices can remove a device link",
+        )));
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_create_device_link_request(
+    action: EntryCreationAction,
+    request: DeviceLinkRequest,
+) -> ExternResult<ValidateCallbackResult> {
+    if action.author() != &request.device {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Only the new device can request a device link",
+        )));
+    }
+    let data = DeviceLinkData {
+        device: request.device.clone(),
+        linked_to: request.linked_to,
+    };
+    if !verify_signature(request.device, request.device_signature, data)? {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Device link request signature is invalid",
+        )));
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_update_device_link_request(
+    _action: Update,
+    _request: DeviceLinkRequest,
+) -> ExternResult<ValidateCallbackResult> {
+    Ok(ValidateCallbackResult::Invalid(String::from(
+        "Device link requests cannot be updated",
+    )))
+}
+
+pub fn validate_delete_device_link_request(
+    action: Delete,
+    original_action: EntryCreationAction,
+    _original_request: DeviceLinkRequest,
+) -> ExternResult<ValidateCallbackResult> {
+    validate_delete_by_author(&action.author, original_action.author(), "a device link request")
+}
+
+// Linked devices join the conversations they are invited to without asking,
+// so only a device linked to `for_device` can invite it
+pub fn validate_create_conversation_invite(
+    action: EntryCreationAction,
+    invite: ConversationInvite,
+) -> ExternResult<ValidateCallbackResult> {
+    let Some(device_link_hash) = invite.device_link else {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Conversation invites must refer to a device link",
+        )));
+    };
+    let record = must_get_valid_record(device_link_hash)?;
+    let device_link: DeviceLink = record
+        .entry()
+        .to_app_option()
+        .map_err(|e| wasm_error!(e))?
+        .ok_or(wasm_error!(WasmErrorInner::Guest(
+            "Conversation invites must refer to a device link".to_string()
+        )))?;
+    if device_link.other_device(action.author()) != Some(invite.for_device) {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Only a linked device can invite a device to a conversation",
+        )));
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_update_conversation_invite(
+    _action: Update,
+    _invite: ConversationInvite,
+) -> ExternResult<ValidateCallbackResult> {
+    Ok(ValidateCallbackResult::Invalid(String::from(
+        "Conversation invites cannot be updated",
+    )))
+}
+
+pub fn validate_delete_conversation_invite(
+    action: Delete,
+    original_action: EntryCreationAction,
+    _original_invite: ConversationInvite,
+) -> ExternResult<ValidateCallbackResult> {
+    validate_delete_by_author(&action.author, original_action.author(), "a conversation invite")
+}
+
+pub fn validate_create_link_all_device_links(
+    _action: CreateLink,
+    _base_address: AnyLinkableHash,
+    target_address: AnyLinkableHash,
+    _tag: LinkTag,
+) -> ExternResult<ValidateCallbackResult> {
+    let action_hash = target_address
+        .into_action_hash()
+        .ok_or(wasm_error!(WasmErrorInner::Guest(
+            "No action hash associated with link".to_string()
+        )))?;
+    let record = must_get_valid_record(action_hash)?;
+    let _device_link: crate::DeviceLink = record
+        .entry()
+        .to_app_option()
+        .map_err(|e| wasm_error!(e))?
+        .ok_or(wasm_error!(WasmErrorInner::Guest(
+            "Linked action must reference an entry".to_string()
+        )))?;
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_delete_link_all_device_links(
+    action: DeleteLink,
+    original_action: CreateLink,
+    _base: AnyLinkableHash,
+    _target: AnyLinkableHash,
+    _tag: LinkTag,
+) -> ExternResult<ValidateCallbackResult> {
+    validate_delete_by_author(&action.author, &original_action.author, "a device link's link")
+}
+
+pub fn validate_create_link_device_link_requests(
+    _action: CreateLink,
+    base_address: AnyLinkableHash,
+    target_address: AnyLinkableHash,
+    _tag: LinkTag,
+) -> ExternResult<ValidateCallbackResult> {
+    let action_hash = target_address
+        .into_action_hash()
+        .ok_or(wasm_error!(WasmErrorInner::Guest(
+            "No action hash associated with link".to_string()
+        )))?;
+    let record = must_get_valid_record(action_hash)?;
+    let request: crate::DeviceLinkRequest = record
+        .entry()
+        .to_app_option()
+        .map_err(|e| wasm_error!(e))?
+        .ok_or(wasm_error!(WasmErrorInner::Guest(
+            "Linked action must reference an entry".to_string()
+        )))?;
+    if AnyLinkableHash::from(request.linked_to) != base_address {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Device link requests must be linked from the device they are for",
+        )));
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_delete_link_device_link_requests(
+    action: DeleteLink,
+    original_action: CreateLink,
+    _base: AnyLinkableHash,
+    _target: AnyLinkableHash,
+    _tag: LinkTag,
+) -> ExternResult<ValidateCallbackResult> {
+    validate_delete_by_author(
+        &action.author,
+        &original_action.author,
+        "a device link request's link",
+    )
+}
+
+pub fn validate_create_link_conversation_invites(
+    action: CreateLink,
+    base_address: AnyLinkableHash,
+    target_address: AnyLinkableHash,
+    _tag: LinkTag,
+) -> ExternResult<ValidateCallbackResult> {
+    let action_hash = target_address
+        .into_action_hash()
+        .ok_or(wasm_error!(WasmErrorInner::Guest(
+            "No action hash associated with link".to_string()
+        )))?;
+    let record = must_get_valid_record(action_hash)?;
+    let invite: crate::ConversationInvite = record
+        .entry()
+        .to_app_option()
+        .map_err(|e| wasm_error!(e))?
+        .ok_or(wasm_error!(WasmErrorInner::Guest(
+            "Linked action must reference an entry".to_string()
+        )))?;
+    if AnyLinkableHash::from(invite.for_device) != base_address {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Conversation invites must be linked from the device they are for",
+        )));
+    }
+    if record.action().author() != &action.author {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Only the author of a conversation invite can link it",
+        )));
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+pub fn validate_delete_link_conversation_invites(
+    action: DeleteLink,
+    original_action: CreateLink,
+    _base: AnyLinkableHash,
+    _target: AnyLinkableHash,
+    _tag: LinkTag,
+) -> ExternResult<ValidateCallbackResult> {
+    validate_delete_by_author(
+        &action.author,
+        &original_action.author,
+        "a conversation invite's link",
+    )
+}
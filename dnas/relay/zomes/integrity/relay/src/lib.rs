@@ -4,6 +4,12 @@ pub mod message;
 pub use message::*;
 pub mod config;
 pub use config::*;
+pub mod device_link;
+pub use device_link::*;
+pub mod contact_group;
+pub use contact_group::*;
+pub mod block;
+pub use block::*;
 use hdi::prelude::*;
 
 pub const MESSAGES_PATH_PREFIX: &str = "msg";
@@ -12,6 +18,13 @@ pub fn messages_path(bucket: u32) -> Path {
     Path::from(format!("{}.{}", MESSAGES_PATH_PREFIX, bucket))
 }
 
+pub const FILE_CONTENTS_PATH_PREFIX: &str = "file_contents";
+
+// Stored files are linked from the hash of their contents, so they can be reused
+pub fn file_contents_path(content_hash: &str) -> Path {
+    Path::from(format!("{}.{}", FILE_CONTENTS_PATH_PREFIX, content_hash))
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[hdk_entry_types]
@@ -19,7 +32,17 @@ pub fn messages_path(bucket: u32) -> Path {
 pub enum EntryTypes {
     Config(Config),
     Message(Message),
+    // Published contacts of earlier versions, only kept so they can be migrated
     Contact(Contact),
+    DeviceLink(DeviceLink),
+    DeviceLinkRequest(DeviceLinkRequest),
+    ConversationInvite(ConversationInvite),
+    #[entry_type(visibility = "private")]
+    PrivateContact(Contact),
+    #[entry_type(visibility = "private")]
+    ContactGroup(ContactGroup),
+    #[entry_type(visibility = "private")]
+    Block(Block),
 }
 
 #[derive(Serialize, Deserialize)]
@@ -31,6 +54,10 @@ pub enum LinkTypes {
     ContactToContacts,
     ContactUpdates,
     AllContacts,
+    AllDeviceLinks,
+    DeviceLinkRequests,
+    ConversationInvites,
+    FileContents,
 }
 
 #[derive(Serialize, Deserialize, Debug, SerializedBytes, Clone)]
@@ -44,6 +71,16 @@ pub struct MembraneProofData {
 pub struct MembraneProofEnvelope {
     pub signature: Signature,
     pub data: MembraneProofData,
+    // Set when the proof is signed by a linked device of the agent instead of the progenitor
+    #[serde(default)]
+    pub device_link: Option<LinkedDeviceProof>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkedDeviceProof {
+    pub link: DeviceLink,
+    // The signing device's own membrane proof, unless it is the progenitor
+    pub membrane_proof: Option<SerializedBytes>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, SerializedBytes, PartialEq)]
@@ -57,16 +94,33 @@ pub struct Properties {
     pub created: Timestamp,
     pub privacy: Privacy,
     pub progenitor: AgentPubKey,
+    // Attachment limits, see `DEFAULT_MAX_ATTACHMENT_SIZE` and `DEFAULT_MAX_ATTACHMENTS`
+    #[serde(default)]
+    pub max_attachment_size: Option<usize>,
+    #[serde(default)]
+    pub max_attachments: Option<usize>,
+    // Voice message limits, see `DEFAULT_MAX_VOICE_DURATION_MS` and `DEFAULT_MAX_VOICE_SIZE`
+    #[serde(default)]
+    pub max_voice_duration_ms: Option<u64>,
+    #[serde(default)]
+    pub max_voice_size: Option<usize>,
+}
+
+// The base cell is installed without properties (`properties: ~` in happ.yaml), unlike the
+// conversation and device sync cells, which are cloned with `Properties`
+pub fn is_base_cell() -> ExternResult<bool> {
+    let unset = SerializedBytes::try_from(()).map_err(|e| wasm_error!(e))?;
+    Ok(dna_info()?.modifiers.properties == unset)
 }
 
 pub fn check_agent(
     agent_pub_key: AgentPubKey,
     membrane_proof: Option<MembraneProof>,
 ) -> ExternResult<ValidateCallbackResult> {
-    let info = dna_info()?;
-    if info.modifiers.properties.bytes().len() == 1 {
+    if is_base_cell()? {
         return Ok(ValidateCallbackResult::Valid);
     }
+    let info = dna_info()?;
     let props = Properties::try_from(info.modifiers.properties)
         .map_err(|e| wasm_error!(e))?;
     if props.privacy == Privacy::Public {
@@ -84,28 +138,78 @@ pub fn check_agent(
             )
         }
         Some(serialized_proof) => {
-            let envelope = MembraneProofEnvelope::try_from((*serialized_proof).clone())
-                .map_err(|e| wasm_error!(e))?;
-            if envelope.data.conversation_id != info.modifiers.network_seed {
-                return Ok(
-                    ValidateCallbackResult::Invalid(
-                        "membrane proof is not for this conversation".to_string(),
-                    ),
-                );
-            }
-            if envelope.data.for_agent != agent_pub_key {
-                return Ok(
-                    ValidateCallbackResult::Invalid(
-                        "membrane proof is not for this agent".to_string(),
-                    ),
-                );
-            }
-            if verify_signature(props.progenitor, envelope.signature, envelope.data)? {
-                return Ok(ValidateCallbackResult::Valid);
-            }
+            check_membrane_proof(
+                &props,
+                &info.modifiers.network_seed,
+                agent_pub_key,
+                (*serialized_proof).clone(),
+            )
+        }
+    }
+}
+
+fn check_membrane_proof(
+    props: &Properties,
+    network_seed: &str,
+    agent_pub_key: AgentPubKey,
+    serialized_proof: SerializedBytes,
+) -> ExternResult<ValidateCallbackResult> {
+    let envelope = MembraneProofEnvelope::try_from(serialized_proof)
+        .map_err(|e| wasm_error!(e))?;
+    if envelope.data.conversation_id != network_seed {
+        return Ok(
+            ValidateCallbackResult::Invalid(
+                "membrane proof is not for this conversation".to_string(),
+            ),
+        );
+    }
+    if envelope.data.for_agent != agent_pub_key {
+        return Ok(
+            ValidateCallbackResult::Invalid(
+                "membrane proof is not for this agent".to_string(),
+            ),
+        );
+    }
+    let Some(linked) = envelope.device_link else {
+        if verify_signature(props.progenitor.clone(), envelope.signature, envelope.data)? {
+            return Ok(ValidateCallbackResult::Valid);
+        }
+        return Ok(
+            ValidateCallbackResult::Invalid(
+                "membrane proof signature invalid".to_string(),
+            ),
+        );
+    };
+
+    // Signed by a linked device, which has to be a member of the conversation itself
+    let Some(signer) = linked.link.other_device(&agent_pub_key) else {
+        return Ok(
+            ValidateCallbackResult::Invalid(
+                "device link is not for this agent".to_string(),
+            ),
+        );
+    };
+    if !linked.link.verify()? {
+        return Ok(
+            ValidateCallbackResult::Invalid("device link signature invalid".to_string()),
+        );
+    }
+    if !verify_signature(signer.clone(), envelope.signature, envelope.data)? {
+        return Ok(
+            ValidateCallbackResult::Invalid(
+                "membrane proof signature invalid".to_string(),
+            ),
+        );
+    }
+    if signer == props.progenitor {
+        return Ok(ValidateCallbackResult::Valid);
+    }
+    match linked.membrane_proof {
+        Some(proof) => check_membrane_proof(props, network_seed, signer, proof),
+        None => {
             Ok(
                 ValidateCallbackResult::Invalid(
-                    "membrane proof signature invalid".to_string(),
+                    "membrane proof of the linked device must be provided".to_string(),
                 ),
             )
         }
@@ -145,12 +249,39 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                                 message,
                             )
                         }
-                        EntryTypes::Contact(contact) => {
+                        EntryTypes::Contact(contact) | EntryTypes::PrivateContact(contact) => {
                             validate_create_contact(
                                 EntryCreationAction::Create(action),
                                 contact,
                             )
                         }
+                        EntryTypes::DeviceLink(device_link) => {
+                            validate_create_device_link(
+                                EntryCreationAction::Create(action),
+                                device_link,
+                            )
+                        }
+                        EntryTypes::DeviceLinkRequest(request) => {
+                            validate_create_device_link_request(
+                                EntryCreationAction::Create(action),
+                                request,
+                            )
+                        }
+                        EntryTypes::ConversationInvite(invite) => {
+                            validate_create_conversation_invite(
+                                EntryCreationAction::Create(action),
+                                invite,
+                            )
+                        }
+                        EntryTypes::ContactGroup(group) => {
+                            validate_create_contact_group(
+                                EntryCreationAction::Create(action),
+                                group,
+                            )
+                        }
+                        EntryTypes::Block(block) => {
+                            validate_create_block(EntryCreationAction::Create(action), block)
+                        }
                     }
                 }
                 OpEntry::UpdateEntry { app_entry, action, .. } => {
@@ -167,12 +298,39 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                                 message,
                             )
                         }
-                        EntryTypes::Contact(contact) => {
+                        EntryTypes::Contact(contact) | EntryTypes::PrivateContact(contact) => {
                             validate_create_contact(
                                 EntryCreationAction::Update(action),
                                 contact,
                             )
                         }
+                        EntryTypes::DeviceLink(device_link) => {
+                            validate_create_device_link(
+                                EntryCreationAction::Update(action),
+                                device_link,
+                            )
+                        }
+                        EntryTypes::DeviceLinkRequest(request) => {
+                            validate_create_device_link_request(
+                                EntryCreationAction::Update(action),
+                                request,
+                            )
+                        }
+                        EntryTypes::ConversationInvite(invite) => {
+                            validate_create_conversation_invite(
+                                EntryCreationAction::Update(action),
+                                invite,
+                            )
+                        }
+                        EntryTypes::ContactGroup(group) => {
+                            validate_create_contact_group(
+                                EntryCreationAction::Update(action),
+                                group,
+                            )
+                        }
+                        EntryTypes::Block(block) => {
+                            validate_create_block(EntryCreationAction::Update(action), block)
+                        }
                     }
                 }
                 _ => Ok(ValidateCallbackResult::Valid),
@@ -201,7 +359,7 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                         }
                     };
                     match app_entry {
-                        EntryTypes::Contact(contact) => {
+                        EntryTypes::Contact(contact) | EntryTypes::PrivateContact(contact) => {
                             let original_app_entry = must_get_valid_record(
                                 action.clone().original_action_address,
                             )?;
@@ -230,6 +388,15 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                         EntryTypes::Config(config) => {
                             validate_update_config(action, config)
                         }
+                        EntryTypes::DeviceLink(device_link) => {
+                            validate_update_device_link(action, device_link)
+                        }
+                        EntryTypes::DeviceLinkRequest(request) => {
+                            validate_update_device_link_request(action, request)
+                        }
+                        EntryTypes::ConversationInvite(invite) => {
+                            validate_update_conversation_invite(action, invite)
+                        }
                         _ => {
                             Ok(
                                 ValidateCallbackResult::Invalid(
@@ -270,12 +437,23 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
             let entry = match original_record.entry().as_option() {
                 Some(entry) => entry,
                 None => {
-                    return Ok(
-                        ValidateCallbackResult::Invalid(
-                            "Original record for a delete must contain an entry"
-                                .to_string(),
-                        ),
-                    );
+                    if original_action.entry_type().visibility().is_public() {
+                        return Ok(
+                            ValidateCallbackResult::Invalid(
+                                "Original record for a delete of a public entry must contain an entry"
+                                    .to_string(),
+                            ),
+                        );
+                    } else if delete_entry.action.author != *original_action.author() {
+                        // Only the author holds a private entry, and only they can delete it
+                        return Ok(
+                            ValidateCallbackResult::Invalid(
+                                "Only the author of a private entry can delete it".to_string(),
+                            ),
+                        );
+                    } else {
+                        return Ok(ValidateCallbackResult::Valid);
+                    }
                 }
             };
             let original_app_entry = match EntryTypes::deserialize_from_type(
@@ -294,7 +472,8 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                 }
             };
             match original_app_entry {
-                EntryTypes::Contact(original_contact) => {
+                EntryTypes::Contact(original_contact)
+                | EntryTypes::PrivateContact(original_contact) => {
                     validate_delete_contact(
                         delete_entry.clone().action,
                         original_action,
@@ -315,6 +494,41 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                         ),
                     );
                 }
+                EntryTypes::DeviceLink(original_device_link) => {
+                    validate_delete_device_link(
+                        delete_entry.clone().action,
+                        original_action,
+                        original_device_link,
+                    )
+                }
+                EntryTypes::DeviceLinkRequest(original_request) => {
+                    validate_delete_device_link_request(
+                        delete_entry.clone().action,
+                        original_action,
+                        original_request,
+                    )
+                }
+                EntryTypes::ConversationInvite(original_invite) => {
+                    validate_delete_conversation_invite(
+                        delete_entry.clone().action,
+                        original_action,
+                        original_invite,
+                    )
+                }
+                EntryTypes::ContactGroup(original_group) => {
+                    validate_delete_contact_group(
+                        delete_entry.clone().action,
+                        original_action,
+                        original_group,
+                    )
+                }
+                EntryTypes::Block(original_block) => {
+                    validate_delete_block(
+                        delete_entry.clone().action,
+                        original_action,
+                        original_block,
+                    )
+                }
             }
         }
         FlatOp::RegisterCreateLink {
@@ -373,6 +587,38 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                         tag,
                     )
                 }
+                LinkTypes::AllDeviceLinks => {
+                    validate_create_link_all_device_links(
+                        action,
+                        base_address,
+                        target_address,
+                        tag,
+                    )
+                }
+                LinkTypes::DeviceLinkRequests => {
+                    validate_create_link_device_link_requests(
+                        action,
+                        base_address,
+                        target_address,
+                        tag,
+                    )
+                }
+                LinkTypes::ConversationInvites => {
+                    validate_create_link_conversation_invites(
+                        action,
+                        base_address,
+                        target_address,
+                        tag,
+                    )
+                }
+                LinkTypes::FileContents => {
+                    validate_create_link_file_contents(
+                        action,
+                        base_address,
+                        target_address,
+                        tag,
+                    )
+                }
             }
         }
         FlatOp::RegisterDeleteLink {
@@ -438,6 +684,42 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                         tag,
                     )
                 }
+                LinkTypes::AllDeviceLinks => {
+                    validate_delete_link_all_device_links(
+                        action,
+                        original_action,
+                        base_address,
+                        target_address,
+                        tag,
+                    )
+                }
+                LinkTypes::DeviceLinkRequests => {
+                    validate_delete_link_device_link_requests(
+                        action,
+                        original_action,
+                        base_address,
+                        target_address,
+                        tag,
+                    )
+                }
+                LinkTypes::ConversationInvites => {
+                    validate_delete_link_conversation_invites(
+                        action,
+                        original_action,
+                        base_address,
+                        target_address,
+                        tag,
+                    )
+                }
+                LinkTypes::FileContents => {
+                    validate_delete_link_file_contents(
+                        action,
+                        original_action,
+                        base_address,
+                        target_address,
+                        tag,
+                    )
+                }
             }
         }
         FlatOp::StoreRecord(store_record) => {
@@ -456,12 +738,39 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                                 message,
                             )
                         }
-                        EntryTypes::Contact(contact) => {
+                        EntryTypes::Contact(contact) | EntryTypes::PrivateContact(contact) => {
                             validate_create_contact(
                                 EntryCreationAction::Create(action),
                                 contact,
                             )
                         }
+                        EntryTypes::DeviceLink(device_link) => {
+                            validate_create_device_link(
+                                EntryCreationAction::Create(action),
+                                device_link,
+                            )
+                        }
+                        EntryTypes::DeviceLinkRequest(request) => {
+                            validate_create_device_link_request(
+                                EntryCreationAction::Create(action),
+                                request,
+                            )
+                        }
+                        EntryTypes::ConversationInvite(invite) => {
+                            validate_create_conversation_invite(
+                                EntryCreationAction::Create(action),
+                                invite,
+                            )
+                        }
+                        EntryTypes::ContactGroup(group) => {
+                            validate_create_contact_group(
+                                EntryCreationAction::Create(action),
+                                group,
+                            )
+                        }
+                        EntryTypes::Block(block) => {
+                            validate_create_block(EntryCreationAction::Create(action), block)
+                        }
                     }
                 }
                 OpRecord::UpdateEntry {
@@ -537,7 +846,7 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                                 Ok(result)
                             }
                         }
-                        EntryTypes::Contact(contact) => {
+                        EntryTypes::Contact(contact) | EntryTypes::PrivateContact(contact) => {
                             let result = validate_create_contact(
                                 EntryCreationAction::Update(action.clone()),
                                 contact.clone(),
@@ -568,6 +877,19 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                                 Ok(result)
                             }
                         }
+                        EntryTypes::DeviceLink(device_link) => {
+                            validate_update_device_link(action, device_link)
+                        }
+                        EntryTypes::DeviceLinkRequest(request) => {
+                            validate_update_device_link_request(action, request)
+                        }
+                        EntryTypes::ConversationInvite(invite) => {
+                            validate_update_conversation_invite(action, invite)
+                        }
+                        EntryTypes::ContactGroup(group) => {
+                            validate_update_contact_group(action, group)
+                        }
+                        EntryTypes::Block(block) => validate_update_block(action, block),
                     }
                 }
                 OpRecord::DeleteEntry { original_action_hash, action, .. } => {
@@ -636,13 +958,45 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                                 original_message,
                             )
                         }
-                        EntryTypes::Contact(original_contact) => {
+                        EntryTypes::Contact(original_contact)
+                        | EntryTypes::PrivateContact(original_contact) => {
                             validate_delete_contact(
                                 action,
                                 original_action,
                                 original_contact,
                             )
                         }
+                        EntryTypes::DeviceLink(original_device_link) => {
+                            validate_delete_device_link(
+                                action,
+                                original_action,
+                                original_device_link,
+                            )
+                        }
+                        EntryTypes::DeviceLinkRequest(original_request) => {
+                            validate_delete_device_link_request(
+                                action,
+                                original_action,
+                                original_request,
+                            )
+                        }
+                        EntryTypes::ConversationInvite(original_invite) => {
+                            validate_delete_conversation_invite(
+                                action,
+                                original_action,
+                                original_invite,
+                            )
+                        }
+                        EntryTypes::ContactGroup(original_group) => {
+                            validate_delete_contact_group(
+                                action,
+                                original_action,
+                                original_group,
+                            )
+                        }
+                        EntryTypes::Block(original_block) => {
+                            validate_delete_block(action, original_action, original_block)
+                        }
                     }
                 }
                 OpRecord::CreateLink {
@@ -701,6 +1055,38 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                                 tag,
                             )
                         }
+                        LinkTypes::AllDeviceLinks => {
+                            validate_create_link_all_device_links(
+                                action,
+                                base_address,
+                                target_address,
+                                tag,
+                            )
+                        }
+                        LinkTypes::DeviceLinkRequests => {
+                            validate_create_link_device_link_requests(
+                                action,
+                                base_address,
+                                target_address,
+                                tag,
+                            )
+                        }
+                        LinkTypes::ConversationInvites => {
+                            validate_create_link_conversation_invites(
+                                action,
+                                base_address,
+                                target_address,
+                                tag,
+                            )
+                        }
+                        LinkTypes::FileContents => {
+                            validate_create_link_file_contents(
+                                action,
+                                base_address,
+                                target_address,
+                                tag,
+                            )
+                        }
                     }
                 }
                 OpRecord::DeleteLink { original_action_hash, base_address, action } => {
@@ -780,6 +1166,42 @@ pub fn validate(op: Op) -> ExternResult<ValidateCallbackResult> {
                                 create_link.tag,
                             )
                         }
+                        LinkTypes::AllDeviceLinks => {
+                            validate_delete_link_all_device_links(
+                                action,
+                                create_link.clone(),
+                                base_address,
+                                create_link.target_address,
+                                create_link.tag,
+                            )
+                        }
+                        LinkTypes::DeviceLinkRequests => {
+                            validate_delete_link_device_link_requests(
+                                action,
+                                create_link.clone(),
+                                base_address,
+                                create_link.target_address,
+                                create_link.tag,
+                            )
+                        }
+                        LinkTypes::ConversationInvites => {
+                            validate_delete_link_conversation_invites(
+                                action,
+                                create_link.clone(),
+                                base_address,
+                                create_link.target_address,
+                                create_link.tag,
+                            )
+                        }
+                        LinkTypes::FileContents => {
+                            validate_delete_link_file_contents(
+                                action,
+                                create_link.clone(),
+                                base_address,
+                                create_link.target_address,
+                                create_link.tag,
+                            )
+                        }
                     }
                 }
                 OpRecord::CreatePrivateEntry { .. } => Ok(ValidateCallbackResult::Valid),
@@ -7,14 +7,168 @@ pub struct File {
     pub size: usize, // Size in bytes
     pub file_type: String,
     pub storage_entry_hash: EntryHash,
+    // A smaller copy of an image, stored as its own file, so previews don't need the original
+    #[serde(default)]
+    pub thumbnail_storage_entry_hash: Option<EntryHash>,
+    // The hex encoded SHA-256 of the file's bytes, see `file_contents_path`
+    #[serde(default)]
+    pub content_hash: Option<String>,
+}
+
+// Mirrors `FileMetadata` of the file_storage zome, whose integrity crate can't be linked into this one
+#[derive(Serialize, Deserialize, Debug, SerializedBytes, Clone)]
+pub struct FileMetadata {
+    pub name: String,
+    pub last_modified: Timestamp,
+    pub size: usize,
+    pub file_type: String,
+    pub chunks_hashes: Vec<EntryHash>,
+}
+
+// Mirrors `FileChunk` of the file_storage zome
+#[derive(Serialize, Deserialize, Debug, SerializedBytes, Clone)]
+pub struct FileChunk(pub SerializedBytes);
+
+// Used unless the conversation's properties set their own limits
+pub const DEFAULT_MAX_ATTACHMENT_SIZE: usize = 100 * 1024 * 1024;
+pub const DEFAULT_MAX_ATTACHMENTS: usize = 20;
+pub const DEFAULT_MAX_VOICE_DURATION_MS: u64 = 5 * 60 * 1000;
+pub const DEFAULT_MAX_VOICE_SIZE: usize = 10 * 1024 * 1024;
+pub const MAX_WAVEFORM_LENGTH: usize = 128;
+pub const MAX_LINK_PREVIEW_TEXT_LENGTH: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, SerializedBytes, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum Attachment {
+    Image {
+        file: File,
+        width: Option<u32>,
+        height: Option<u32>,
+    },
+    Audio {
+        file: File,
+        duration_ms: Option<u64>,
+    },
+    // A recorded voice message
+    Voice {
+        file: File,
+        duration_ms: u64,
+        // The peak amplitude of each equal slice of the recording, from 0 to 255
+        waveform: Vec<u8>,
+    },
+    Video {
+        file: File,
+        width: Option<u32>,
+        height: Option<u32>,
+        duration_ms: Option<u64>,
+    },
+    Document {
+        file: File,
+    },
+}
+
+impl Attachment {
+    pub fn file(&self) -> &File {
+        match self {
+            Attachment::Image { file, .. }
+            | Attachment::Audio { file, .. }
+            | Attachment::Voice { file, .. }
+            | Attachment::Video { file, .. }
+            | Attachment::Document { file } => file,
+        }
+    }
+
+    pub fn file_mut(&mut self) -> &mut File {
+        match self {
+            Attachment::Image { file, .. }
+            | Attachment::Audio { file, .. }
+            | Attachment::Voice { file, .. }
+            | Attachment::Video { file, .. }
+            | Attachment::Document { file } => file,
+        }
+    }
+}
+
+// Where a forwarded message was first written. The original entry is kept as it was stored,
+// so its hash can be checked against the action its author signed.
+#[derive(Serialize, Deserialize, Debug, SerializedBytes, Clone, PartialEq)]
+pub struct ForwardedFrom {
+    pub author: AgentPubKey,
+    pub signed_action: SignedActionHashed,
+    pub entry: Entry,
+}
+
+// An `@name` span of a message's content, as character offsets, that refers to an agent
+#[derive(Serialize, Deserialize, Debug, SerializedBytes, Clone, PartialEq)]
+pub struct Mention {
+    pub agent: AgentPubKey,
+    pub start: u32,
+    pub end: u32,
+    // The agent's AgentValidationPkg action in this conversation, which proves they joined it.
+    // Set by `create_message`.
+    #[serde(default)]
+    pub membership: Option<ActionHash>,
+}
+
+// The Open Graph metadata of a link in a message. The sender fetches it, so recipients see the
+// preview without contacting the site.
+#[derive(Serialize, Deserialize, Debug, SerializedBytes, Clone, PartialEq)]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: String,
+    pub description: String,
+    // Stored in file_storage like attachments
+    pub image: Option<File>,
 }
 
 #[hdk_entry_helper]
 #[derive(Clone, PartialEq)]
+#[serde(from = "MessageData")]
 pub struct Message {
+    // Markdown
     pub content: String,
     pub bucket: u32,
-    pub images: Vec<File>,
+    pub attachments: Vec<Attachment>,
+    pub forwarded_from: Option<ForwardedFrom>,
+    pub mentions: Vec<Mention>,
+    pub link_preview: Option<LinkPreview>,
+}
+
+// What messages are read from. Messages written before attachments were typed
+// only have `images`, which become image attachments.
+#[derive(Deserialize)]
+struct MessageData {
+    content: String,
+    bucket: u32,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    #[serde(default)]
+    images: Vec<File>,
+    #[serde(default)]
+    forwarded_from: Option<ForwardedFrom>,
+    #[serde(default)]
+    mentions: Vec<Mention>,
+    #[serde(default)]
+    link_preview: Option<LinkPreview>,
+}
+
+impl From<MessageData> for Message {
+    fn from(data: MessageData) -> Self {
+        let mut attachments = data.attachments;
+        attachments.extend(data.images.into_iter().map(|file| Attachment::Image {
+            file,
+            width: None,
+            height: None,
+        }));
+        Message {
+            content: data.content,
+            bucket: data.bucket,
+            attachments,
+            forwarded_from: data.forwarded_from,
+            mentions: data.mentions,
+            link_preview: data.link_preview,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -23,17 +177,289 @@ pub struct MessageRecord {
     pub signed_action: SignedActionHashed,
     pub message: Option<Message>,
 }
+struct AttachmentLimits {
+    max_attachment_size: usize,
+    max_attachments: usize,
+    max_voice_duration_ms: u64,
+    max_voice_size: usize,
+}
+
+// The attachment limits of this conversation
+fn attachment_limits() -> ExternResult<AttachmentLimits> {
+    let props = if crate::is_base_cell()? {
+        None
+    } else {
+        let properties = dna_info()?.modifiers.properties;
+        Some(crate::Properties::try_from(properties).map_err(|e| wasm_error!(e))?)
+    };
+    Ok(AttachmentLimits {
+        max_attachment_size: props
+            .as_ref()
+            .and_then(|p| p.max_attachment_size)
+            .unwrap_or(DEFAULT_MAX_ATTACHMENT_SIZE),
+        max_attachments: props
+            .as_ref()
+            .and_then(|p| p.max_attachments)
+            .unwrap_or(DEFAULT_MAX_ATTACHMENTS),
+        max_voice_duration_ms: props
+            .as_ref()
+            .and_then(|p| p.max_voice_duration_ms)
+            .unwrap_or(DEFAULT_MAX_VOICE_DURATION_MS),
+        max_voice_size: props
+            .as_ref()
+            .and_then(|p| p.max_voice_size)
+            .unwrap_or(DEFAULT_MAX_VOICE_SIZE),
+    })
+}
+
+fn validate_voice(
+    file: &File,
+    duration_ms: u64,
+    waveform: &[u8],
+    limits: &AttachmentLimits,
+) -> ValidateCallbackResult {
+    if !file.file_type.starts_with("audio/") {
+        return ValidateCallbackResult::Invalid(String::from(
+            "Voice messages must be audio files",
+        ));
+    }
+    if duration_ms > limits.max_voice_duration_ms {
+        return ValidateCallbackResult::Invalid(format!(
+            "Voice messages can be at most {} ms long",
+            limits.max_voice_duration_ms
+        ));
+    }
+    if file.size > limits.max_voice_size {
+        return ValidateCallbackResult::Invalid(format!(
+            "Voice messages can be at most {} bytes",
+            limits.max_voice_size
+        ));
+    }
+    if waveform.len() > MAX_WAVEFORM_LENGTH {
+        return ValidateCallbackResult::Invalid(format!(
+            "Waveforms can have at most {MAX_WAVEFORM_LENGTH} values"
+        ));
+    }
+    ValidateCallbackResult::Valid
+}
+
+// The metadata of the file_storage entry at this hash, if it is one
+fn stored_file(hash: &EntryHash) -> ExternResult<Option<FileMetadata>> {
+    let Entry::App(bytes) = must_get_entry(hash.clone())?.content else {
+        return Ok(None);
+    };
+    Ok(FileMetadata::try_from(bytes.into_sb()).ok())
+}
+
+// Checks every file against the file_storage entry it points to
+fn validate_message_files(message: &Message) -> ExternResult<ValidateCallbackResult> {
+    let limits = attachment_limits()?;
+    if message.attachments.len() > limits.max_attachments {
+        return Ok(ValidateCallbackResult::Invalid(format!(
+            "Messages can have at most {} attachments",
+            limits.max_attachments
+        )));
+    }
+    for attachment in &message.attachments {
+        if let Attachment::Voice {
+            file,
+            duration_ms,
+            waveform,
+        } = attachment
+        {
+            let result = validate_voice(file, *duration_ms, waveform, &limits);
+            if !matches!(result, ValidateCallbackResult::Valid) {
+                return Ok(result);
+            }
+        }
+        let file = attachment.file();
+        if file.size > limits.max_attachment_size {
+            return Ok(ValidateCallbackResult::Invalid(format!(
+                "Attachments can be at most {} bytes",
+                limits.max_attachment_size
+            )));
+        }
+        let Some(metadata) = stored_file(&file.storage_entry_hash)? else {
+            return Ok(ValidateCallbackResult::Invalid(String::from(
+                "Attachments must point to a file_storage entry",
+            )));
+        };
+        if metadata.size != file.size || metadata.file_type != file.file_type {
+            return Ok(ValidateCallbackResult::Invalid(String::from(
+                "Attachment size and type must match the stored file",
+            )));
+        }
+        if let Some(thumbnail_hash) = &file.thumbnail_storage_entry_hash {
+            let is_image = stored_file(thumbnail_hash)?
+                .is_some_and(|thumbnail| thumbnail.file_type.starts_with("image/"));
+            if !is_image {
+                return Ok(ValidateCallbackResult::Invalid(String::from(
+                    "Thumbnails must point to a stored image",
+                )));
+            }
+        }
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+// Checks that a forwarded message has the content and attachments of a message its original
+// author signed
+fn validate_forwarded_from(message: &Message) -> ExternResult<ValidateCallbackResult> {
+    let Some(forwarded_from) = &message.forwarded_from else {
+        return Ok(ValidateCallbackResult::Valid);
+    };
+    let action = forwarded_from.signed_action.action();
+    if action.author() != &forwarded_from.author {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Forwarded messages must be signed by their original author",
+        )));
+    }
+    let signature = forwarded_from.signed_action.signature().clone();
+    if !verify_signature(forwarded_from.author.clone(), signature, action.clone())? {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "The signature of the original message is invalid",
+        )));
+    }
+    let is_message = matches!(action, Action::Create(_) | Action::Update(_))
+        && action.entry_hash() == Some(&hash_entry(forwarded_from.entry.clone())?);
+    let original = match &forwarded_from.entry {
+        Entry::App(bytes) if is_message => Message::try_from(bytes.clone().into_sb()).ok(),
+        _ => None,
+    };
+    let Some(original) = original else {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Forwarded messages must refer to the entry of the original message",
+        )));
+    };
+    // Forwarding a forwarded message keeps where it was first written
+    if original.forwarded_from.is_some() {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Forwarded messages must refer to the original message",
+        )));
+    }
+    // Attachments are copied to another file_storage entry, which may have another name
+    let same_files = original.attachments.len() == message.attachments.len()
+        && original
+            .attachments
+            .iter()
+            .zip(&message.attachments)
+            .map(|(a, b)| (a.file(), b.file()))
+            .all(|(a, b)| a.size == b.size && a.file_type == b.file_type);
+    if original.content != message.content || !same_files {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Forwarded messages must have the content and attachments of the original message",
+        )));
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+// Checks that mentions are `@` spans of the content, in order, of agents who joined
+fn validate_mentions(message: &Message) -> ExternResult<ValidateCallbackResult> {
+    let chars: Vec<char> = message.content.chars().collect();
+    let mut previous_end = 0;
+    for mention in &message.mentions {
+        let (start, end) = (mention.start as usize, mention.end as usize);
+        if start < previous_end || start >= end || end > chars.len() || chars[start] != '@' {
+            return Ok(ValidateCallbackResult::Invalid(String::from(
+                "Mentions must be separate @ spans of the message content",
+            )));
+        }
+        previous_end = end;
+        let Some(membership) = &mention.membership else {
+            return Ok(ValidateCallbackResult::Invalid(String::from(
+                "Mentions must prove the agent joined the conversation",
+            )));
+        };
+        let record = must_get_valid_record(membership.clone())?;
+        let is_member = matches!(
+            record.action(),
+            Action::AgentValidationPkg(pkg) if pkg.author == mention.agent
+        );
+        if !is_member {
+            return Ok(ValidateCallbackResult::Invalid(String::from(
+                "Mentioned agents must be members of the conversation",
+            )));
+        }
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+// Whether the URL is an absolute http(s) URL with a host
+fn is_web_url(url: &str) -> bool {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    (scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https"))
+        && !host.is_empty()
+        && !url.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+fn validate_link_preview(message: &Message) -> ExternResult<ValidateCallbackResult> {
+    let Some(preview) = &message.link_preview else {
+        return Ok(ValidateCallbackResult::Valid);
+    };
+    if !message.content.contains(&preview.url) {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Link previews must be of a link in the message",
+        )));
+    }
+    // Recipients open the link, so it can't be one that runs in the app, like `javascript:`
+    if !is_web_url(&preview.url) {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "Link previews must be of an http or https link",
+        )));
+    }
+    if preview.title.chars().count() > MAX_LINK_PREVIEW_TEXT_LENGTH
+        || preview.description.chars().count() > MAX_LINK_PREVIEW_TEXT_LENGTH
+    {
+        return Ok(ValidateCallbackResult::Invalid(format!(
+            "Link preview titles and descriptions can be at most {} characters",
+            MAX_LINK_PREVIEW_TEXT_LENGTH
+        )));
+    }
+    if let Some(image) = &preview.image {
+        let is_image = stored_file(&image.storage_entry_hash)?.is_some_and(|metadata| {
+            metadata.file_type.starts_with("image/")
+                && metadata.file_type == image.file_type
+                && metadata.size == image.size
+        });
+        if !is_image {
+            return Ok(ValidateCallbackResult::Invalid(String::from(
+                "Link preview images must point to a stored image",
+            )));
+        }
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+
+fn validate_message(message: &Message) -> ExternResult<ValidateCallbackResult> {
+    let result = validate_message_files(message)?;
+    if !matches!(result, ValidateCallbackResult::Valid) {
+        return Ok(result);
+    }
+    let result = validate_forwarded_from(message)?;
+    if !matches!(result, ValidateCallbackResult::Valid) {
+        return Ok(result);
+    }
+    let result = validate_link_preview(message)?;
+    if !matches!(result, ValidateCallbackResult::Valid) {
+        return Ok(result);
+    }
+    validate_mentions(message)
+}
+
 pub fn validate_create_message(
     _action: EntryCreationAction,
-    _message: Message,
+    message: Message,
 ) -> ExternResult<ValidateCallbackResult> {
-    Ok(ValidateCallbackResult::Valid)
+    validate_message(&message)
 }
 pub fn validate_update_message(
     _action: Update,
-    _message: Message,
+    message: Message,
 ) -> ExternResult<ValidateCallbackResult> {
-    Ok(ValidateCallbackResult::Valid)
+    validate_message(&message)
 }
 pub fn validate_delete_message(
     _action: Delete,
@@ -135,3 +561,35 @@ pub fn validate_delete_link_all_messages(
 ) -> ExternResult<ValidateCallbackResult> {
     Ok(ValidateCallbackResult::Valid)
 }
+pub fn validate_create_link_file_contents(
+    _action: CreateLink,
+    _base_address: AnyLinkableHash,
+    target_address: AnyLinkableHash,
+    _tag: LinkTag,
+) -> ExternResult<ValidateCallbackResult> {
+    let entry_hash = target_address
+        .into_entry_hash()
+        .ok_or(wasm_error!(WasmErrorInner::Guest(
+            "No entry hash associated with link".to_string()
+        )))?;
+    if stored_file(&entry_hash)?.is_none() {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "File contents must be linked to a file_storage entry",
+        )));
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
+pub fn validate_delete_link_file_contents(
+    action: DeleteLink,
+    original_action: CreateLink,
+    _base: AnyLinkableHash,
+    _target: AnyLinkableHash,
+    _tag: LinkTag,
+) -> ExternResult<ValidateCallbackResult> {
+    if action.author != original_action.author {
+        return Ok(ValidateCallbackResult::Invalid(String::from(
+            "File contents links can only be deleted by their author",
+        )));
+    }
+    Ok(ValidateCallbackResult::Valid)
+}
@@ -18,4 +18,9 @@
 
 # If you keep the line number information, uncomment this to
 # hide the original source file name.
-#-renamesourcefileattribute SourceFile
\ No newline at end of file
+#-renamesourcefileattribute SourceFile
+
+# Called through JNI by voice.rs
+-keep class com.volla.messages.MainActivity {
+    public boolean requestRecordAudioPermission();
+}
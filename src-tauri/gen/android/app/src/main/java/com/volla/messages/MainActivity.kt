@@ -1,3 +1,23 @@
 package com.volla.messages
 
-class MainActivity : TauriActivity()
\ No newline at end of file
+import android.Manifest
+import android.content.pm.PackageManager
+import androidx.core.app.ActivityCompat
+import androidx.core.content.ContextCompat
+
+class MainActivity : TauriActivity() {
+  // Called from voice.rs before recording. Asks for the microphone if it isn't granted yet,
+  // and returns whether it is.
+  fun requestRecordAudioPermission(): Boolean {
+    val permission = Manifest.permission.RECORD_AUDIO
+    if (ContextCompat.checkSelfPermission(this, permission) == PackageManager.PERMISSION_GRANTED) {
+      return true
+    }
+    ActivityCompat.requestPermissions(this, arrayOf(permission), RECORD_AUDIO_REQUEST_CODE)
+    return false
+  }
+
+  companion object {
+    private const val RECORD_AUDIO_REQUEST_CODE = 1001
+  }
+}
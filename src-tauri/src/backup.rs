@@ -0,0 +1,521 @@
+use anyhow::anyhow;
+use holochain_client::{AppWebsocket, CellInfo, ZomeCallTarget};
+use holochain_types::prelude::*;
+use lair_keystore::dependencies::lair_keystore_api::types::{BinDataSized, SecretData};
+use lair_keystore::dependencies::sodoken::{self, BufReadSized, BufWriteSized};
+use relay_integrity::{Contact, ContactRecord};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_holochain::HolochainExt;
+
+use crate::keystore::{self, KeystoreState, KeystoreStatus, KeystoreUnlock};
+use crate::settings::Settings;
+use crate::storage::HolochainDir;
+use crate::{start_holochain, vec_to_locked, APP_ID};
+
+const ROLE_NAME: &str = "relay";
+const ZOME_NAME: &str = "relay";
+const BACKUP_VERSION: u8 = 1;
+// Restored by `setup` once the conductor has started with the restored keystore
+const PENDING_RESTORE_FILE: &str = "pending-restore";
+// Lair keeps its process id here, which is meaningless on another device
+const LAIR_PID_FILE: &str = "pid_file";
+// Lair's database can't be copied consistently while lair is running, so backups hold the copy
+// made on startup before the conductor starts lair. Lair only adds keys when the app is
+// installed, so the copy has all of them from the second start on.
+const KEYSTORE_COPY_DIR: &str = "keystore-copy";
+
+// The file written to disk. `data` is a msgpack encoded `Backup`.
+#[derive(Serialize, Deserialize, Debug)]
+struct EncryptedBackup {
+    version: u8,
+    salt: BinDataSized<16>,
+    ops_limit: u32,
+    mem_limit: u32,
+    data: SecretData,
+}
+
+// Lair's agent key seeds can't be exported, so the backup holds the whole keystore.
+// It stays encrypted with the keystore passphrase inside the backup.
+#[derive(Serialize, Deserialize, Debug)]
+struct Backup {
+    keystore_dir: PathBuf,
+    keystore_files: Vec<KeystoreFile>,
+    keystore_unlock: KeystoreUnlock,
+    restore: PendingRestore,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct KeystoreFile {
+    name: String,
+    #[serde(with = "serde_bytes")]
+    bytes: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PendingRestore {
+    agent_pub_key: AgentPubKey,
+    profile: Option<Profile>,
+    conversations: Vec<ConversationBackup>,
+    contacts: Vec<Contact>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ConversationBackup {
+    name: String,
+    network_seed: String,
+    properties: SerializedBytes,
+    membrane_proof: Option<SerializedBytes>,
+    enabled: bool,
+}
+
+// Mirrors `Profile` in the profiles zome
+#[derive(Serialize, Deserialize, Debug, Clone, SerializedBytes)]
+struct Profile {
+    nickname: String,
+    fields: BTreeMap<String, String>,
+}
+
+#[tauri::command]
+pub async fn export_backup(
+    handle: AppHandle,
+    path: PathBuf,
+    passphrase: String,
+) -> Result<(), String> {
+    export(&handle, &path, passphrase)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Only possible before the conductor has created a keystore, i.e. on a fresh install.
+// The restored keystore is then unlocked the way it was on the device it came from,
+// right away if it had no passphrase.
+#[tauri::command]
+pub async fn restore_backup(
+    handle: AppHandle,
+    path: PathBuf,
+    passphrase: String,
+) -> Result<KeystoreStatus, String> {
+    let state = handle.state::<KeystoreState>();
+    if state.get() != KeystoreStatus::New {
+        return Err(String::from(
+            "A backup can only be restored on a new installation",
+        ));
+    }
+    let holochain_dir = handle.state::<HolochainDir>().0.clone();
+    let settings = restore(&holochain_dir, &path, passphrase)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match keystore::startup_passphrase(&holochain_dir, &settings) {
+        Some(passphrase) => {
+            state.set(KeystoreStatus::Unlocking);
+            start_holochain(&handle, passphrase).map_err(|e| {
+                state.set(KeystoreStatus::Failed);
+                e.to_string()
+            })?;
+        }
+        None => state.set(KeystoreStatus::Locked),
+    }
+    Ok(state.get())
+}
+
+async fn export(handle: &AppHandle, path: &Path, passphrase: String) -> anyhow::Result<()> {
+    let holochain_dir = handle.state::<HolochainDir>().0.clone();
+    let copy_dir = holochain_dir.join(KEYSTORE_COPY_DIR);
+    if !copy_dir.exists() {
+        return Err(anyhow!(
+            "The keystore was created since the app started. Restart the app to export a backup."
+        ));
+    }
+    let backup = Backup {
+        keystore_files: read_keystore_files(&copy_dir)?,
+        // The config in the copy still refers to the live keystore's files
+        keystore_dir: keystore::keystore_dir(&holochain_dir),
+        keystore_unlock: Settings::load().keystore_unlock,
+        restore: PendingRestore::collect(handle).await?,
+    };
+
+    let encrypted = encrypt(ExternIO::encode(backup)?.into_vec(), passphrase).await?;
+    std::fs::write(path, serde_json::to_vec(&encrypted)?)?;
+    Ok(())
+}
+
+async fn restore(
+    holochain_dir: &Path,
+    path: &Path,
+    passphrase: String,
+) -> anyhow::Result<Settings> {
+    let encrypted: EncryptedBackup = serde_json::from_slice(&std::fs::read(path)?)?;
+    let backup: Backup = ExternIO::from(decrypt(encrypted, passphrase).await?).decode()?;
+
+    write_keystore_files(holochain_dir, &backup.keystore_dir, backup.keystore_files)?;
+    backup.restore.save(holochain_dir)?;
+
+    let mut settings = Settings::load();
+    // The passphrase isn't in this device's keychain yet, so it's asked for instead
+    settings.keystore_unlock = match backup.keystore_unlock {
+        KeystoreUnlock::Empty => KeystoreUnlock::Empty,
+        _ => KeystoreUnlock::Passphrase,
+    };
+    settings.save()?;
+    Ok(settings)
+}
+
+// Called on startup before lair runs, see `KEYSTORE_COPY_DIR`
+pub fn copy_keystore_at_rest(holochain_dir: &Path) -> anyhow::Result<()> {
+    let keystore_dir = keystore::keystore_dir(holochain_dir);
+    if !keystore_dir.exists() {
+        return Ok(());
+    }
+    // Renamed into place once complete, so a crash can't leave a partial copy behind
+    let tmp_dir = holochain_dir.join(format!("{KEYSTORE_COPY_DIR}.tmp"));
+    if tmp_dir.exists() {
+        std::fs::remove_dir_all(&tmp_dir)?;
+    }
+    std::fs::create_dir_all(&tmp_dir)?;
+    for file in read_keystore_files(&keystore_dir)? {
+        std::fs::write(tmp_dir.join(file.name), file.bytes)?;
+    }
+    let copy_dir = holochain_dir.join(KEYSTORE_COPY_DIR);
+    if copy_dir.exists() {
+        std::fs::remove_dir_all(&copy_dir)?;
+    }
+    std::fs::rename(tmp_dir, copy_dir)?;
+    Ok(())
+}
+
+// Copies another holochain directory's keystore into `holochain_dir`
+pub fn copy_keystore(from_holochain_dir: &Path, holochain_dir: &Path) -> anyhow::Result<()> {
+    let from_dir = keystore::keystore_dir(from_holochain_dir);
+    write_keystore_files(holochain_dir, &from_dir, read_keystore_files(&from_dir)?)
+}
+
+// Lair's config holds absolute paths to its other files
+fn write_keystore_files(
+    holochain_dir: &Path,
+    old_keystore_dir: &Path,
+    files: Vec<KeystoreFile>,
+) -> anyhow::Result<()> {
+    let keystore_dir = keystore::keystore_dir(holochain_dir);
+    std::fs::create_dir_all(&keystore_dir)?;
+    let old_dir = old_keystore_dir.to_string_lossy().to_string();
+    let new_dir = keystore_dir.to_string_lossy().to_string();
+    for file in files {
+        let target = keystore_dir.join(&file.name);
+        if target == keystore::lair_config_path(holochain_dir) {
+            let config = String::from_utf8(file.bytes)?.replace(&old_dir, &new_dir);
+            std::fs::write(target, config)?;
+        } else {
+            std::fs::write(target, file.bytes)?;
+        }
+    }
+    Ok(())
+}
+
+// Files kept next to the conductor data that only mean something in the directory they were
+// written in, as they refer to its paths
+pub fn is_transient_file(name: &std::ffi::OsStr) -> bool {
+    let name = name.to_string_lossy();
+    name == PENDING_RESTORE_FILE
+        || name == KEYSTORE_COPY_DIR
+        || name == format!("{KEYSTORE_COPY_DIR}.tmp")
+}
+
+// Removes a keystore and pending restore that were written but won't be used
+pub fn discard_restore(holochain_dir: &Path) -> anyhow::Result<()> {
+    for dir in [keystore::keystore_dir(holochain_dir), holochain_dir.join(KEYSTORE_COPY_DIR)] {
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+    }
+    let pending_restore = holochain_dir.join(PENDING_RESTORE_FILE);
+    if pending_restore.exists() {
+        std::fs::remove_file(pending_restore)?;
+    }
+    Ok(())
+}
+
+pub fn pending_restore(holochain_dir: &Path) -> anyhow::Result<Option<PendingRestore>> {
+    PendingRestore::load(&holochain_dir.join(PENDING_RESTORE_FILE))
+}
+
+impl PendingRestore {
+    // Everything needed to rejoin the app's conversations with the same agent
+    pub async fn collect(handle: &AppHandle) -> anyhow::Result<Self> {
+        let app_ws = handle
+            .holochain()?
+            .app_websocket(String::from(APP_ID))
+            .await?;
+        let app_info = app_ws
+            .app_info()
+            .await
+            .map_err(tauri_plugin_holochain::Error::ConductorApiError)?
+            .ok_or(anyhow!("{APP_ID} is not installed"))?;
+
+        let mut main_cell_id = None;
+        let mut conversations = vec![];
+        for cell in app_info
+            .cell_info
+            .get(ROLE_NAME)
+            .cloned()
+            .unwrap_or_default()
+        {
+            match cell {
+                CellInfo::Provisioned(cell) => main_cell_id = Some(cell.cell_id),
+                CellInfo::Cloned(cell) => {
+                    // Disabled cells can't be called, so they are rejoined without a proof
+                    let membrane_proof = if cell.enabled {
+                        call_zome(
+                            &app_ws,
+                            &cell.cell_id,
+                            ZOME_NAME,
+                            "get_my_membrane_proof",
+                            (),
+                        )
+                        .await?
+                    } else {
+                        None
+                    };
+                    conversations.push(ConversationBackup {
+                        name: cell.name,
+                        network_seed: cell.dna_modifiers.network_seed,
+                        properties: cell.dna_modifiers.properties,
+                        membrane_proof,
+                        enabled: cell.enabled,
+                    });
+                }
+                _ => {}
+            }
+        }
+        let main_cell_id = main_cell_id.ok_or(anyhow!("No provisioned {ROLE_NAME} cell found"))?;
+
+        let contacts: Vec<ContactRecord> = call_zome(
+            &app_ws,
+            &main_cell_id,
+            ZOME_NAME,
+            "get_all_contact_entries",
+            (),
+        )
+        .await?;
+        let profile: Option<Record> = call_zome(
+            &app_ws,
+            &main_cell_id,
+            "profiles",
+            "get_agent_profile",
+            app_info.agent_pub_key.clone(),
+        )
+        .await?;
+
+        Ok(Self {
+            agent_pub_key: app_info.agent_pub_key,
+            profile: profile.and_then(|record| record.entry().to_app_option().ok().flatten()),
+            conversations,
+            contacts: contacts.into_iter().filter_map(|c| c.contact).collect(),
+        })
+    }
+
+    fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(ExternIO::from(std::fs::read(path)?).decode()?))
+    }
+
+    // Leaves it for `setup` to apply once the conductor has started
+    pub fn save(&self, holochain_dir: &Path) -> anyhow::Result<()> {
+        let path = holochain_dir.join(PENDING_RESTORE_FILE);
+        std::fs::write(path, ExternIO::encode(self)?.into_vec())?;
+        Ok(())
+    }
+
+    pub fn agent_pub_key(&self) -> AgentPubKey {
+        self.agent_pub_key.clone()
+    }
+
+    // Rejoins the conversations and recreates the profile and contacts in the freshly installed app.
+    // The conversations' history is gossiped back by their other members.
+    pub async fn apply(self, handle: &AppHandle, holochain_dir: &Path) -> anyhow::Result<()> {
+        let app_ws = handle
+            .holochain()?
+            .app_websocket(String::from(APP_ID))
+            .await?;
+
+        let network_seeds: Vec<String> = self
+            .conversations
+            .iter()
+            .map(|conversation| conversation.network_seed.clone())
+            .collect();
+        let contacts_count = self.contacts.len();
+        for conversation in self.conversations {
+            let cell = app_ws
+                .create_clone_cell(CreateCloneCellPayload {
+                    role_name: ROLE_NAME.to_string(),
+                    modifiers: DnaModifiersOpt::none()
+                        .with_network_seed(conversation.network_seed)
+                        .with_properties(conversation.properties),
+                    membrane_proof: conversation.membrane_proof.map(Arc::new),
+                    name: Some(conversation.name),
+                })
+                .await
+                .map_err(tauri_plugin_holochain::Error::ConductorApiError)?;
+            if !conversation.enabled {
+                app_ws
+                    .disable_clone_cell(DisableCloneCellPayload {
+                        clone_cell_id: CloneCellId::CloneId(cell.clone_id),
+                    })
+                    .await
+                    .map_err(tauri_plugin_holochain::Error::ConductorApiError)?;
+            }
+        }
+
+        let app_info = app_ws
+            .app_info()
+            .await
+            .map_err(tauri_plugin_holochain::Error::ConductorApiError)?
+            .ok_or(anyhow!("{APP_ID} is not installed"))?;
+        let main_cell_id = app_info
+            .cell_info
+            .get(ROLE_NAME)
+            .and_then(|cells| {
+                cells.iter().find_map(|c| match c {
+                    CellInfo::Provisioned(cell) => Some(cell.cell_id.clone()),
+                    _ => None,
+                })
+            })
+            .ok_or(anyhow!("No provisioned {ROLE_NAME} cell found"))?;
+
+        if let Some(profile) = self.profile {
+            let _: Record = call_zome(
+                &app_ws,
+                &main_cell_id,
+                "profiles",
+                "create_profile",
+                profile,
+            )
+            .await?;
+        }
+        for contact in self.contacts {
+            let _: Record =
+                call_zome(&app_ws, &main_cell_id, ZOME_NAME, "create_contact", contact).await?;
+        }
+
+        // A migration is only confirmed, and its earlier data deleted, once everything is there
+        let cloned_seeds: Vec<String> = app_info
+            .cell_info
+            .get(ROLE_NAME)
+            .into_iter()
+            .flatten()
+            .filter_map(|cell| match cell {
+                CellInfo::Cloned(cell) => Some(cell.dna_modifiers.network_seed.clone()),
+                _ => None,
+            })
+            .collect();
+        if let Some(seed) = network_seeds.iter().find(|seed| !cloned_seeds.contains(seed)) {
+            return Err(anyhow!("The conversation with network seed {seed} wasn't restored"));
+        }
+        let contacts: Vec<ContactRecord> = call_zome(
+            &app_ws,
+            &main_cell_id,
+            ZOME_NAME,
+            "get_all_contact_entries",
+            (),
+        )
+        .await?;
+        if contacts.len() < contacts_count {
+            return Err(anyhow!(
+                "Only {} of {contacts_count} contacts were restored",
+                contacts.len()
+            ));
+        }
+
+        std::fs::remove_file(holochain_dir.join(PENDING_RESTORE_FILE))?;
+        Ok(())
+    }
+}
+
+fn read_keystore_files(keystore_dir: &Path) -> anyhow::Result<Vec<KeystoreFile>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(keystore_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !entry.metadata()?.is_file() || name == LAIR_PID_FILE {
+            continue;
+        }
+        files.push(KeystoreFile {
+            name,
+            bytes: std::fs::read(entry.path())?,
+        });
+    }
+    Ok(files)
+}
+
+async fn call_zome<I, O>(
+    app_ws: &AppWebsocket,
+    cell_id: &CellId,
+    zome_name: &str,
+    fn_name: &str,
+    payload: I,
+) -> anyhow::Result<O>
+where
+    I: serde::Serialize + std::fmt::Debug,
+    O: serde::de::DeserializeOwned + std::fmt::Debug,
+{
+    let response = app_ws
+        .call_zome(
+            ZomeCallTarget::CellId(cell_id.clone()),
+            zome_name.into(),
+            fn_name.into(),
+            ExternIO::encode(payload)?,
+        )
+        .await
+        .map_err(tauri_plugin_holochain::Error::ConductorApiError)?;
+    Ok(response.decode()?)
+}
+
+async fn encrypt(data: Vec<u8>, passphrase: String) -> anyhow::Result<EncryptedBackup> {
+    let salt = BufWriteSized::<16>::new_no_lock();
+    sodoken::random::bytes_buf(salt.clone()).await?;
+    let salt = *salt.read_lock_sized();
+    let ops_limit = sodoken::hash::argon2id::OPSLIMIT_INTERACTIVE;
+    let mem_limit = sodoken::hash::argon2id::MEMLIMIT_INTERACTIVE;
+
+    let key = keystore::hash_passphrase(
+        vec_to_locked(passphrase.into_bytes())?,
+        BufReadSized::from(salt),
+        ops_limit,
+        mem_limit,
+    )
+    .await?;
+    Ok(EncryptedBackup {
+        version: BACKUP_VERSION,
+        salt: BinDataSized::from(salt),
+        ops_limit,
+        mem_limit,
+        data: SecretData::encrypt(key, vec_to_locked(data)?).await?,
+    })
+}
+
+async fn decrypt(encrypted: EncryptedBackup, passphrase: String) -> anyhow::Result<Vec<u8>> {
+    if encrypted.version > BACKUP_VERSION {
+        return Err(anyhow!(
+            "The backup was made by a newer version of Volla Messages"
+        ));
+    }
+    let key = keystore::hash_passphrase(
+        vec_to_locked(passphrase.into_bytes())?,
+        BufReadSized::from(*encrypted.salt.0),
+        encrypted.ops_limit,
+        encrypted.mem_limit,
+    )
+    .await?;
+    let data = encrypted
+        .data
+        .decrypt(key)
+        .await
+        .map_err(|_| anyhow!("Wrong backup passphrase"))?;
+    let bytes = data.read_lock().to_vec();
+    Ok(bytes)
+}
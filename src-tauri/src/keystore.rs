@@ -0,0 +1,289 @@
+use lair_keystore::dependencies::lair_keystore_api::config::LairServerConfigInner;
+use lair_keystore::dependencies::lair_keystore_api::types::{BinDataSized, SecretDataSized};
+use lair_keystore::dependencies::sodoken::{self, BufRead, BufReadSized, BufWriteSized};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, State};
+
+use crate::settings::Settings;
+use crate::storage::HolochainDir;
+use crate::{start_holochain, APP_ID};
+
+const KEYSTORE_DIR: &str = "keystore";
+const LAIR_CONFIG_FILE: &str = "lair-keystore-config.yaml";
+
+// How the keystore passphrase is obtained on startup
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum KeystoreUnlock {
+    // Keystores created before passphrases were supported use an empty one
+    #[default]
+    Empty,
+    // Asked for on every start
+    Passphrase,
+    // Remembered in the OS keychain, and asked for if that fails
+    Keychain,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum KeystoreStatus {
+    // First launch, a passphrase needs to be chosen
+    New,
+    // Waiting for the passphrase
+    Locked,
+    Unlocking,
+    // The conductor failed to start with the given passphrase
+    Failed,
+    Unlocked,
+}
+
+pub struct KeystoreState {
+    status: Mutex<KeystoreStatus>,
+    // The passphrase being tried and whether to remember it, saved once the conductor accepts it
+    pending_unlock: Mutex<Option<(String, bool)>>,
+}
+
+impl KeystoreState {
+    pub fn new(status: KeystoreStatus) -> Self {
+        KeystoreState {
+            status: Mutex::new(status),
+            pending_unlock: Mutex::new(None),
+        }
+    }
+
+    pub fn set(&self, status: KeystoreStatus) {
+        *self.status.lock().unwrap() = status;
+        if status == KeystoreStatus::Failed {
+            self.pending_unlock.lock().unwrap().take();
+        }
+    }
+
+    pub fn get(&self) -> KeystoreStatus {
+        *self.status.lock().unwrap()
+    }
+
+    // Called when the conductor started, which means the passphrase was right
+    pub fn unlocked(&self) {
+        self.set(KeystoreStatus::Unlocked);
+        if let Some((passphrase, remember)) = self.pending_unlock.lock().unwrap().take() {
+            if let Err(err) = save_unlock(&passphrase, remember) {
+                log::error!("Failed to save how to unlock the keystore: {err:?}");
+            }
+        }
+    }
+}
+
+pub fn keystore_dir(holochain_dir: &Path) -> PathBuf {
+    holochain_dir.join(KEYSTORE_DIR)
+}
+
+pub fn lair_config_path(holochain_dir: &Path) -> PathBuf {
+    keystore_dir(holochain_dir).join(LAIR_CONFIG_FILE)
+}
+
+// Returns the passphrase to start the conductor with right away, if no user input is needed
+pub fn startup_passphrase(holochain_dir: &Path, settings: &Settings) -> Option<Vec<u8>> {
+    // Dev conductors live in a fresh temporary directory on every start
+    if tauri::is_dev() {
+        return Some(vec![]);
+    }
+    if !lair_config_path(holochain_dir).exists() {
+        return None;
+    }
+    match settings.keystore_unlock {
+        KeystoreUnlock::Empty => Some(vec![]),
+        KeystoreUnlock::Passphrase => None,
+        KeystoreUnlock::Keychain => keychain::get().ok().map(String::into_bytes),
+    }
+}
+
+pub fn initial_status(holochain_dir: &Path) -> KeystoreStatus {
+    if lair_config_path(holochain_dir).exists() {
+        KeystoreStatus::Locked
+    } else {
+        KeystoreStatus::New
+    }
+}
+
+fn save_unlock(passphrase: &str, remember: bool) -> anyhow::Result<()> {
+    let mut settings = Settings::load();
+    settings.keystore_unlock = if passphrase.is_empty() {
+        KeystoreUnlock::Empty
+    } else if remember {
+        keychain::set(passphrase)?;
+        KeystoreUnlock::Keychain
+    } else {
+        KeystoreUnlock::Passphrase
+    };
+    if settings.keystore_unlock != KeystoreUnlock::Keychain {
+        let _ = keychain::delete();
+    }
+    settings.save()
+}
+
+#[tauri::command]
+pub fn get_keystore_status(state: State<'_, KeystoreState>) -> KeystoreStatus {
+    state.get()
+}
+
+// Both creates the keystore on first launch and unlocks an existing one
+#[tauri::command]
+pub fn unlock_keystore(
+    handle: AppHandle,
+    state: State<'_, KeystoreState>,
+    passphrase: String,
+    remember: bool,
+) -> Result<(), String> {
+    match state.get() {
+        KeystoreStatus::New | KeystoreStatus::Locked | KeystoreStatus::Failed => {}
+        _ => return Err(String::from("The keystore is already being unlocked")),
+    }
+    // A wrong passphrase must not be remembered, so this waits until the conductor started
+    if state.get() == KeystoreStatus::New || remember {
+        *state.pending_unlock.lock().unwrap() = Some((passphrase.clone(), remember));
+    }
+    state.set(KeystoreStatus::Unlocking);
+    start_holochain(&handle, passphrase.into_bytes()).map_err(|e| {
+        state.set(KeystoreStatus::Failed);
+        e.to_string()
+    })
+}
+
+// Re-encrypts the keystore with a new passphrase and restarts the app
+#[tauri::command]
+pub async fn change_keystore_passphrase(
+    handle: AppHandle,
+    old_passphrase: String,
+    new_passphrase: String,
+    remember: bool,
+) -> Result<(), String> {
+    let holochain_dir = handle.state::<HolochainDir>().0.clone();
+    rekey(
+        &lair_config_path(&holochain_dir),
+        old_passphrase.into_bytes(),
+        new_passphrase.clone().into_bytes(),
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    save_unlock(&new_passphrase, remember).map_err(|e| e.to_string())?;
+    handle.restart()
+}
+
+// Lair keeps its database key and id seed encrypted in its config file, with secrets derived
+// from the passphrase. Re-encrypting those two values is enough to change the passphrase.
+async fn rekey(
+    config_path: &Path,
+    old_passphrase: Vec<u8>,
+    new_passphrase: Vec<u8>,
+) -> anyhow::Result<()> {
+    let bytes = std::fs::read(config_path)?;
+    let mut config = LairServerConfigInner::from_bytes(&bytes)?;
+
+    let (ctx_secret, id_secret) = derive_secrets(
+        crate::vec_to_locked(old_passphrase)?,
+        BufReadSized::from(*config.runtime_secrets_salt.0),
+        config.runtime_secrets_ops_limit,
+        config.runtime_secrets_mem_limit,
+    )
+    .await?;
+    let context_key = config
+        .runtime_secrets_context_key
+        .decrypt(ctx_secret)
+        .await
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase"))?;
+    let id_seed = config.runtime_secrets_id_seed.decrypt(id_secret).await?;
+
+    let salt = BufWriteSized::<16>::new_no_lock();
+    sodoken::random::bytes_buf(salt.clone()).await?;
+    let salt = *salt.read_lock_sized();
+    let (ctx_secret, id_secret) = derive_secrets(
+        crate::vec_to_locked(new_passphrase)?,
+        BufReadSized::from(salt),
+        config.runtime_secrets_ops_limit,
+        config.runtime_secrets_mem_limit,
+    )
+    .await?;
+    config.runtime_secrets_salt = BinDataSized::from(salt);
+    config.runtime_secrets_context_key = SecretDataSized::encrypt(ctx_secret, context_key).await?;
+    config.runtime_secrets_id_seed = SecretDataSized::encrypt(id_secret, id_seed).await?;
+
+    // Renaming replaces the config at once, so a crash leaves either the old or the new one
+    let temp_path = config_path.with_extension("yaml.tmp");
+    let mut file = std::fs::File::create(&temp_path)?;
+    file.write_all(config.to_string().as_bytes())?;
+    file.sync_all()?;
+    std::fs::rename(temp_path, config_path)?;
+    Ok(())
+}
+
+// Mirrors the derivation in LairServerConfigInner::new
+async fn derive_secrets(
+    passphrase: BufRead,
+    salt: BufReadSized<16>,
+    ops_limit: u32,
+    mem_limit: u32,
+) -> anyhow::Result<(BufReadSized<32>, BufReadSized<32>)> {
+    let pre_secret = hash_passphrase(passphrase, salt, ops_limit, mem_limit).await?;
+
+    let ctx_secret = BufWriteSized::<32>::new_mem_locked()?;
+    sodoken::kdf::derive_from_key(ctx_secret.clone(), 42, *b"CtxSecKy", pre_secret.clone())?;
+    let id_secret = BufWriteSized::<32>::new_mem_locked()?;
+    sodoken::kdf::derive_from_key(id_secret.clone(), 142, *b"IdnSecKy", pre_secret)?;
+
+    Ok((ctx_secret.to_read_sized(), id_secret.to_read_sized()))
+}
+
+// Stretches a passphrase into a 32 byte secret
+pub async fn hash_passphrase(
+    passphrase: BufRead,
+    salt: BufReadSized<16>,
+    ops_limit: u32,
+    mem_limit: u32,
+) -> anyhow::Result<BufReadSized<32>> {
+    let pw_hash = BufWriteSized::<64>::new_mem_locked()?;
+    sodoken::hash::blake2b::hash(pw_hash.clone(), passphrase).await?;
+
+    let secret = BufWriteSized::<32>::new_mem_locked()?;
+    sodoken::hash::argon2id::hash(secret.clone(), pw_hash, salt, ops_limit, mem_limit).await?;
+    Ok(secret.to_read_sized())
+}
+
+#[cfg(desktop)]
+mod keychain {
+    use super::APP_ID;
+
+    const KEYCHAIN_USER: &str = "keystore";
+
+    fn entry() -> keyring::Result<keyring::Entry> {
+        keyring::Entry::new(APP_ID, KEYCHAIN_USER)
+    }
+
+    pub fn get() -> keyring::Result<String> {
+        entry()?.get_password()
+    }
+
+    pub fn set(passphrase: &str) -> keyring::Result<()> {
+        entry()?.set_password(passphrase)
+    }
+
+    pub fn delete() -> keyring::Result<()> {
+        entry()?.delete_credential()
+    }
+}
+
+// There is no OS keychain on mobile, so the passphrase is asked for on every start
+#[cfg(mobile)]
+mod keychain {
+    pub fn get() -> anyhow::Result<String> {
+        Err(anyhow::anyhow!("No keychain available"))
+    }
+
+    pub fn set(_passphrase: &str) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("No keychain available"))
+    }
+
+    pub fn delete() -> anyhow::Result<()> {
+        Ok(())
+    }
+}
@@ -2,11 +2,27 @@ use holochain_types::prelude::AppBundle;
 use lair_keystore::dependencies::sodoken::{BufRead, BufWrite};
 use std::path::PathBuf;
 use std::time::{UNIX_EPOCH, SystemTime};
-use tauri::{AppHandle, Listener};
-#[cfg(desktop)]
-use tauri::Manager;
+use tauri::{AppHandle, Listener, Manager};
 use tauri_plugin_holochain::{GossipArcClamp, HolochainExt, HolochainPluginConfig, WANNetworkConfig};
 
+mod backup;
+mod keystore;
+use keystore::{KeystoreState, KeystoreStatus};
+mod link_preview;
+mod local_services;
+mod migration;
+use migration::{MigrationState, PreviousVersion};
+use local_services::LocalServices;
+mod settings;
+use settings::{GossipArc, NetworkProfile, NetworkSettings, Settings};
+mod storage;
+use storage::HolochainDir;
+mod thumbnail;
+mod transfer;
+use transfer::TransferLocks;
+mod voice;
+use voice::VoiceRecorder;
+
 const APP_ID: &'static str = "volla-messages";
 const SIGNAL_URL: &'static str = "wss://sbd.holo.host";
 const BOOTSTRAP_URL: &'static str = "https://bootstrap-0.infra.holochain.org";
@@ -24,6 +40,17 @@ pub fn happ_bundle() -> anyhow::Result<AppBundle> {
 #[allow(unused_mut)]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let settings = Settings::load();
+    let local_services = start_local_services(&settings);
+    let holochain_dir = holochain_dir();
+    if let Err(err) = backup::copy_keystore_at_rest(&holochain_dir) {
+        log::warn!("Failed to copy the keystore for backups: {err:?}");
+    }
+    let startup_passphrase = keystore::startup_passphrase(&holochain_dir, &settings);
+    let keystore_state = KeystoreState::new(keystore::initial_status(&holochain_dir));
+    let previous_version = PreviousVersion(std::sync::Mutex::new(
+        migration::find_previous_version(&holochain_dir),
+    ));
     let mut builder = tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_notification::init())
@@ -36,26 +63,67 @@ pub fn run() {
                 .level(log::LevelFilter::Warn)
                 .build(),
         )
-        .plugin(tauri_plugin_holochain::async_init(
-            vec_to_locked(vec![]).expect("Can't build passphrase"),
-            HolochainPluginConfig::new(holochain_dir(), wan_network_config()).gossip_arc_clamp(GossipArcClamp::Full),
-        ));
+        .manage(local_services)
+        .manage(HolochainDir(holochain_dir))
+        .manage(keystore_state)
+        .manage(previous_version)
+        .manage(MigrationState::default())
+        .manage(TransferLocks::default())
+        .manage(VoiceRecorder::default())
+        .invoke_handler(tauri::generate_handler![
+            settings::get_network_settings,
+            settings::get_default_network_settings,
+            settings::set_network_settings,
+            settings::get_network_profile,
+            settings::get_local_services_urls,
+            settings::get_gossip_arc_settings,
+            settings::set_gossip_arc,
+            settings::get_link_previews,
+            settings::set_link_previews,
+            storage::get_storage_usage,
+            keystore::get_keystore_status,
+            keystore::unlock_keystore,
+            keystore::change_keystore_passphrase,
+            backup::export_backup,
+            backup::restore_backup,
+            migration::get_previous_version,
+            migration::migrate_previous_version,
+            migration::get_migrated_from,
+            migration::confirm_migration,
+            thumbnail::generate_thumbnail,
+            voice::request_microphone_permission,
+            voice::start_voice_recording,
+            voice::stop_voice_recording,
+            voice::cancel_voice_recording,
+            transfer::upload_file,
+            transfer::download_file,
+            transfer::get_pending_transfers,
+            transfer::cancel_transfer,
+            link_preview::fetch_link_preview,
+        ]);
     #[cfg(mobile)]
     {
         builder = builder.plugin(tauri_plugin_sharesheet::init());
     }
     builder
-        .setup(|app| {
+        .setup(move |app| {
             let handle = app.handle().clone();
             let handle_fail: AppHandle = app.handle().clone();
+            // The splashscreen listens to this event too, and asks for the passphrase again
             app.handle()
                 .listen("holochain://setup-failed", move |_event| {
-                    handle_fail.exit(1);
+                    handle_fail.state::<KeystoreState>().set(KeystoreStatus::Failed);
                 });
             app.handle()
                 .listen("holochain://setup-completed", move |_event| {
                     let handle = handle.clone();
+                    handle.state::<KeystoreState>().unlocked();
                     tauri::async_runtime::spawn(async move {
+                        // The conductor ran on the copy of an earlier version being migrated
+                        if migration::is_exporting(&handle) {
+                            migration::finish_export(handle).await;
+                            return;
+                        }
                         setup(handle.clone()).await.expect("Failed to setup");
 
                         let mut window = handle
@@ -92,6 +160,16 @@ pub fn run() {
                     });
                 });
 
+            // Otherwise the conductor is started once the splashscreen has the passphrase
+            if let Some(passphrase) = startup_passphrase {
+                let state = app.state::<KeystoreState>();
+                state.set(KeystoreStatus::Unlocking);
+                if let Err(err) = start_holochain(app.handle(), passphrase) {
+                    log::error!("Failed to start holochain: {err:?}");
+                    state.set(KeystoreStatus::Failed);
+                }
+            }
+
             Ok(())
         })
         .run(tauri::generate_context!())
@@ -125,16 +203,23 @@ async fn setup(handle: AppHandle) -> anyhow::Result<()> {
             "{}",
             SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros()
         );
+        // A restored backup brings the agent key along in the keystore
+        let holochain_dir = handle.state::<HolochainDir>().0.clone();
+        let pending_restore = backup::pending_restore(&holochain_dir)?;
         handle
             .holochain()?
             .install_app(
                 String::from(APP_ID),
                 happ_bundle()?,
                 None,
-                None,
+                pending_restore.as_ref().map(|restore| restore.agent_pub_key()),
                 Some(random_seed),
             )
             .await?;
+        if let Some(restore) = pending_restore {
+            restore.apply(&handle, &holochain_dir).await?;
+            migration::imported()?;
+        }
     } else {
         handle
             .holochain()?
@@ -144,16 +229,113 @@ async fn setup(handle: AppHandle) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn wan_network_config() -> Option<WANNetworkConfig> {
+// Registers the holochain plugin, which starts the conductor with the given keystore passphrase.
+// A plugin left over from a failed attempt is removed first, so unlocking can be retried.
+// While an earlier version is being migrated, the conductor runs on the copy of its data.
+fn start_holochain(handle: &AppHandle, passphrase: Vec<u8>) -> anyhow::Result<()> {
+    handle.remove_plugin("holochain");
+    let settings = Settings::load();
+    let local_services = handle.state::<Option<LocalServices>>();
+    let holochain_dir = migration::export_dir(handle, &passphrase)
+        .unwrap_or_else(|| handle.state::<HolochainDir>().0.clone());
+    handle.plugin(tauri_plugin_holochain::async_init(
+        vec_to_locked(passphrase)?,
+        plugin_config(&settings, local_services.inner().as_ref(), holochain_dir),
+    ))?;
+    Ok(())
+}
+
+fn plugin_config(
+    settings: &Settings,
+    local_services: Option<&LocalServices>,
+    holochain_dir: PathBuf,
+) -> HolochainPluginConfig {
+    let wan_network_config = match &settings.network_profile {
+        NetworkProfile::Internet => wan_network_config(settings),
+        // Without a WAN config the conductor only discovers peers through mDNS
+        NetworkProfile::Mdns => None,
+        NetworkProfile::LanServer {
+            signal_url,
+            bootstrap_url,
+        } => lan_network_config(signal_url, bootstrap_url),
+        NetworkProfile::LanHost { .. } => match local_services {
+            Some(services) => {
+                let urls = services.loopback_urls();
+                lan_network_config(&urls.signal_url, &urls.bootstrap_url)
+            }
+            None => None,
+        },
+    };
+    let config = HolochainPluginConfig::new(holochain_dir, wan_network_config);
+    match settings.gossip_arc() {
+        GossipArc::Full => config.gossip_arc_clamp(GossipArcClamp::Full),
+        GossipArc::Empty => config.gossip_arc_clamp(GossipArcClamp::Empty),
+        // Without a clamp the arc is resized to the device's capacity
+        GossipArc::Dynamic => config,
+    }
+}
+
+// Starts the bundled bootstrap and signal server if this device hosts them for the LAN.
+// Falls back to mDNS only discovery if they can't be started.
+fn start_local_services(settings: &Settings) -> Option<LocalServices> {
+    let NetworkProfile::LanHost {
+        bootstrap_port,
+        signal_port,
+    } = settings.network_profile
+    else {
+        return None;
+    };
+    match tauri::async_runtime::block_on(local_services::start(bootstrap_port, signal_port)) {
+        Ok(services) => {
+            // The picked ports are kept, so the urls other devices were given stay valid
+            if bootstrap_port == 0 || signal_port == 0 {
+                let mut settings = Settings::load();
+                settings.network_profile = NetworkProfile::LanHost {
+                    bootstrap_port: services.bootstrap_port,
+                    signal_port: services.signal_port,
+                };
+                if let Err(err) = settings.save() {
+                    log::warn!("Failed to save the ports of the local services: {err:?}");
+                }
+            }
+            Some(services)
+        }
+        Err(err) => {
+            log::error!("Failed to start the local services: {err:?}");
+            None
+        }
+    }
+}
+
+fn lan_network_config(signal_url: &str, bootstrap_url: &str) -> Option<WANNetworkConfig> {
+    match NetworkSettings::for_lan(signal_url, bootstrap_url).wan_network_config() {
+        Ok(config) => Some(config),
+        Err(err) => {
+            log::warn!("Falling back to mDNS only discovery: {err:?}");
+            None
+        }
+    }
+}
+
+fn wan_network_config(settings: &Settings) -> Option<WANNetworkConfig> {
+    match &settings.network {
+        Some(network) => match network.wan_network_config() {
+            Ok(config) => Some(config),
+            Err(err) => {
+                log::warn!("Falling back to the default network endpoints: {err:?}");
+                default_wan_network_config()
+            }
+        },
+        None => default_wan_network_config(),
+    }
+}
+
+fn default_wan_network_config() -> Option<WANNetworkConfig> {
     // Resolved at compile time to be able to point to local services
     if tauri::is_dev() {
         None
     } else {
-        Some(WANNetworkConfig {
-            signal_url: url2::url2!("{}", SIGNAL_URL),
-            bootstrap_url: url2::url2!("{}", BOOTSTRAP_URL),
-            ice_servers_urls: ICE_URLS.into_iter().map(|v| url2::url2!("{}", v)).collect()
-        })
+        NetworkSettings::default().wan_network_config().ok()
     }
 }
 
@@ -181,19 +363,21 @@ fn holochain_dir() -> PathBuf {
             tmp_path
         }
     } else {
-        app_dirs2::app_root(
-            app_dirs2::AppDataType::UserData,
-            &app_dirs2::AppInfo {
-                name: APP_ID,
-                author: std::env!("CARGO_PKG_AUTHORS"),
-            },
-        )
-        .expect("Could not get app root")
-        .join("holochain")
-        .join(get_version())
+        app_data_dir().join("holochain").join(get_version())
     }
 }
 
+fn app_data_dir() -> PathBuf {
+    app_dirs2::app_root(
+        app_dirs2::AppDataType::UserData,
+        &app_dirs2::AppInfo {
+            name: APP_ID,
+            author: std::env!("CARGO_PKG_AUTHORS"),
+        },
+    )
+    .expect("Could not get app root")
+}
+
 fn vec_to_locked(mut pass_tmp: Vec<u8>) -> std::io::Result<BufRead> {
     match BufWrite::new_mem_locked(pass_tmp.len()) {
         Err(e) => {
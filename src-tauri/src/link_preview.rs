@@ -0,0 +1,545 @@
+use anyhow::anyhow;
+use holochain_types::prelude::*;
+use relay_integrity::{File, LinkPreview, MAX_LINK_PREVIEW_TEXT_LENGTH};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Response, Url};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use tauri::{AppHandle, State};
+
+use crate::settings::Settings;
+use crate::storage::HolochainDir;
+use crate::thumbnail::thumbnail;
+use crate::transfer;
+
+// Only the start of a page is read, as its metadata is in the head
+const MAX_PAGE_SIZE: usize = 512 * 1024;
+const MAX_IMAGE_SIZE: usize = 10 * 1024 * 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: usize = 5;
+
+// Fetches the Open Graph metadata of a link in a message being sent, and stores its image in
+// the conversation's cell. Returns None for anything but HTML pages with a title, and when
+// link previews are turned off in the settings.
+#[tauri::command]
+pub async fn fetch_link_preview(
+    handle: AppHandle,
+    holochain_dir: State<'_, HolochainDir>,
+    conversation_id: String,
+    url: String,
+) -> Result<Option<LinkPreview>, String> {
+    if Settings::load().link_previews_disabled {
+        return Ok(None);
+    }
+    link_preview(&handle, &holochain_dir.0, &conversation_id, &url)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn link_preview(
+    handle: &AppHandle,
+    holochain_dir: &Path,
+    conversation_id: &str,
+    url: &str,
+) -> anyhow::Result<Option<LinkPreview>> {
+    let client = client()?;
+    let Some(page) = fetch_page(&client, url).await? else {
+        return Ok(None);
+    };
+    let image = match page.image_url {
+        Some(image_url) => {
+            match store_image(handle, holochain_dir, conversation_id, &client, image_url).await {
+                Ok(image) => Some(image),
+                Err(err) => {
+                    log::warn!("Failed to store the image of a link preview: {err}");
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    Ok(Some(LinkPreview {
+        url: url.to_string(),
+        title: page.title,
+        description: page.description,
+        image,
+    }))
+}
+
+fn client() -> reqwest::Result<Client> {
+    Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS))
+        .build()
+}
+
+// What the metadata of a page previews it with
+#[derive(Debug, PartialEq)]
+struct Page {
+    title: String,
+    description: String,
+    image_url: Option<Url>,
+}
+
+// Returns None for anything but HTML pages with a title
+async fn fetch_page(client: &Client, url: &str) -> anyhow::Result<Option<Page>> {
+    let page_url = Url::parse(url)?;
+    if !matches!(page_url.scheme(), "http" | "https") {
+        return Ok(None);
+    }
+    let response = client.get(page_url).send().await?.error_for_status()?;
+    let is_html = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    if !is_html {
+        return Ok(None);
+    }
+    // Relative image URLs are relative to where the page was redirected to
+    let page_url = response.url().clone();
+    let html = String::from_utf8_lossy(&read_limited(response, MAX_PAGE_SIZE).await?).into_owned();
+    let metadata = Metadata::parse(&html);
+
+    let Some(title) = metadata
+        .get(&["og:title", "twitter:title"])
+        .or(metadata.title.clone())
+    else {
+        return Ok(None);
+    };
+    let description = metadata
+        .get(&["og:description", "twitter:description", "description"])
+        .unwrap_or_default();
+    let image_url = metadata
+        .get(&["og:image", "og:image:url", "twitter:image"])
+        .and_then(|image| page_url.join(&image).ok());
+    Ok(Some(Page {
+        title: truncate(&title),
+        description: truncate(&description),
+        image_url,
+    }))
+}
+
+async fn fetch_image(client: &Client, image_url: Url) -> anyhow::Result<Vec<u8>> {
+    let response = client.get(image_url).send().await?.error_for_status()?;
+    let bytes = read_limited(response, MAX_IMAGE_SIZE).await?;
+    if bytes.len() >= MAX_IMAGE_SIZE {
+        return Err(anyhow!("The image is larger than {MAX_IMAGE_SIZE} bytes"));
+    }
+    Ok(bytes)
+}
+
+// Only a thumbnail of the image is stored, which is all a preview shows
+async fn store_image(
+    handle: &AppHandle,
+    holochain_dir: &Path,
+    conversation_id: &str,
+    client: &Client,
+    image_url: Url,
+) -> anyhow::Result<File> {
+    let bytes = fetch_image(client, image_url).await?;
+    let jpeg = thumbnail(&bytes)?;
+    let now = Timestamp::now();
+    let name = format!("link-preview-{}.jpg", now.as_millis());
+    let file_type = String::from("image/jpeg");
+    let storage_entry_hash = transfer::upload_bytes(
+        handle,
+        holochain_dir,
+        conversation_id,
+        &transfer::new_transfer_id(now),
+        &jpeg,
+        name.clone(),
+        file_type.clone(),
+        now,
+    )
+    .await?;
+
+    Ok(File {
+        name,
+        last_modified: now,
+        size: jpeg.len(),
+        file_type,
+        storage_entry_hash,
+        thumbnail_storage_entry_hash: None,
+        content_hash: None,
+    })
+}
+
+// Reads at most `limit` bytes of the body
+async fn read_limited(mut response: Response, limit: usize) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() >= limit {
+            bytes.truncate(limit);
+            break;
+        }
+    }
+    Ok(bytes)
+}
+
+fn truncate(text: &str) -> String {
+    text.chars().take(MAX_LINK_PREVIEW_TEXT_LENGTH).collect()
+}
+
+// The `<meta>` properties and the title of a page's head
+struct Metadata {
+    properties: HashMap<String, String>,
+    title: Option<String>,
+}
+
+impl Metadata {
+    fn parse(html: &str) -> Self {
+        // Lowercasing ASCII keeps the byte offsets the same, so they apply to `html` too
+        let lower = html.to_ascii_lowercase();
+        let head_end = lower.find("</head>").unwrap_or(lower.len());
+        let (html, lower) = (&html[..head_end], &lower[..head_end]);
+
+        let mut properties = HashMap::new();
+        for (start, tag) in lower.match_indices("<meta") {
+            let Some(length) = lower[start..].find('>') else {
+                break;
+            };
+            let attributes = attributes(&html[start + tag.len()..start + length]);
+            let name = attributes.get("property").or(attributes.get("name"));
+            if let (Some(name), Some(content)) = (name, attributes.get("content")) {
+                // The first of repeated properties is the preferred one
+                properties
+                    .entry(name.to_ascii_lowercase())
+                    .or_insert_with(|| decode_entities(content.trim()));
+            }
+        }
+        let title = lower
+            .find("<title")
+            .and_then(|start| {
+                let content_start = start + lower[start..].find('>')? + 1;
+                let content_end = content_start + lower[content_start..].find("</title")?;
+                Some(decode_entities(html[content_start..content_end].trim()))
+            })
+            .filter(|title| !title.is_empty());
+        Metadata { properties, title }
+    }
+
+    // The first of these properties the page has
+    fn get(&self, names: &[&str]) -> Option<String> {
+        names
+            .iter()
+            .filter_map(|name| self.properties.get(*name))
+            .find(|value| !value.is_empty())
+            .cloned()
+    }
+}
+
+// The attributes of a tag, from after its name up to its `>`
+fn attributes(tag: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = tag;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let Some(value) = rest.strip_prefix('=') else {
+            attributes.insert(name, String::new());
+            continue;
+        };
+        let value = value.trim_start();
+        let (value, remaining) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &value[1..];
+                let end = value.find(quote).unwrap_or(value.len());
+                (&value[..end], value.get(end + 1..).unwrap_or_default())
+            }
+            _ => {
+                let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                (&value[..end], &value[end..])
+            }
+        };
+        attributes.insert(name, value.to_string());
+        rest = remaining;
+    }
+    attributes
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        match entity.and_then(|entity| Some((entity, entity_char(entity)?))) {
+            Some((entity, c)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn entity_char(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    struct Route {
+        path: &'static str,
+        status: &'static str,
+        headers: Vec<(&'static str, &'static str)>,
+        body: Vec<u8>,
+    }
+
+    fn page(path: &'static str, html: &str) -> Route {
+        Route {
+            path,
+            status: "200 OK",
+            headers: vec![("Content-Type", "text/html; charset=utf-8")],
+            body: html.as_bytes().to_vec(),
+        }
+    }
+
+    fn redirect(path: &'static str, location: &'static str) -> Route {
+        Route {
+            path,
+            status: "301 Moved Permanently",
+            headers: vec![("Location", location)],
+            body: vec![],
+        }
+    }
+
+    // Serves the routes on a local port for the rest of the test run, and returns its URL
+    fn serve(routes: Vec<Route>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                let mut request = vec![];
+                let mut buffer = [0; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match stream.read(&mut buffer) {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let (status, headers, body) = match routes.iter().find(|r| r.path == path) {
+                    Some(route) => (route.status, route.headers.clone(), route.body.as_slice()),
+                    None => ("404 Not Found", vec![], &[][..]),
+                };
+                let mut head = format!(
+                    "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    body.len()
+                );
+                for (name, value) in headers {
+                    head.push_str(&format!("{name}: {value}\r\n"));
+                }
+                head.push_str("\r\n");
+                // The client stops reading bodies over the size limits
+                let _ = stream.write_all(head.as_bytes());
+                let _ = stream.write_all(body);
+            }
+        });
+        format!("http://{address}")
+    }
+
+    #[test]
+    fn parses_meta_properties_and_the_title() {
+        let metadata = Metadata::parse(
+            r#"<html><HEAD><title> Tom &amp; Jerry </title>
+            <meta property="og:title" content="First">
+            <meta property="og:title" content="Second">
+            <META NAME="Description" CONTENT='A &quot;quoted&quot; description'>
+            <meta content=https://example.com/image.png name=twitter:image />
+            </head><body><meta property="og:description" content="In the body"></body></html>"#,
+        );
+        assert_eq!(metadata.title.as_deref(), Some("Tom & Jerry"));
+        assert_eq!(metadata.get(&["og:title"]).as_deref(), Some("First"));
+        assert_eq!(
+            metadata.get(&["og:description", "description"]).as_deref(),
+            Some("A \"quoted\" description")
+        );
+        assert_eq!(
+            metadata.get(&["og:image", "twitter:image"]).as_deref(),
+            Some("https://example.com/image.png")
+        );
+    }
+
+    #[test]
+    fn skips_empty_properties_and_titles() {
+        let metadata = Metadata::parse(
+            r#"<head><meta property="og:title" content=" "><meta name="twitter:title" content="Fallback">
+            <title></title></head>"#,
+        );
+        assert_eq!(metadata.title, None);
+        assert_eq!(
+            metadata.get(&["og:title", "twitter:title"]).as_deref(),
+            Some("Fallback")
+        );
+    }
+
+    #[test]
+    fn parses_quoted_unquoted_and_empty_attributes() {
+        let attributes = attributes(r#" a="1" B='two words' c=3 d e = "5" f="unterminated/"#);
+        let expected = [
+            ("a", "1"),
+            ("b", "two words"),
+            ("c", "3"),
+            ("d", ""),
+            ("e", "5"),
+            ("f", "unterminated/"),
+        ];
+        assert_eq!(attributes.len(), expected.len());
+        for (name, value) in expected {
+            assert_eq!(
+                attributes.get(name).map(String::as_str),
+                Some(value),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_named_and_numeric_entities() {
+        assert_eq!(
+            decode_entities("&lt;b&gt; &quot;&apos;&amp;&nbsp;é"),
+            "<b> \"'& é"
+        );
+        assert_eq!(decode_entities("&#65;&#x42;&#X43;"), "ABC");
+    }
+
+    #[test]
+    fn keeps_what_is_not_an_entity() {
+        assert_eq!(decode_entities("&copy; & &amp"), "&copy; & &amp");
+        assert_eq!(
+            decode_entities("&#xD800; &#nope; &;"),
+            "&#xD800; &#nope; &;"
+        );
+        assert_eq!(decode_entities("R&D;&amp;"), "R&D;&");
+    }
+
+    #[tokio::test]
+    async fn fetches_the_preview_of_a_redirected_page() {
+        let url = serve(vec![
+            redirect("/old", "/articles/page"),
+            page(
+                "/articles/page",
+                r#"<head><title>Title tag</title>
+                <meta property="og:title" content="Open Graph title">
+                <meta property="og:description" content="Description">
+                <meta property="og:image" content="image.png"></head>"#,
+            ),
+        ]);
+        let page = fetch_page(&client().unwrap(), &format!("{url}/old"))
+            .await
+            .unwrap();
+        assert_eq!(
+            page,
+            Some(Page {
+                title: String::from("Open Graph title"),
+                description: String::from("Description"),
+                image_url: Some(Url::parse(&format!("{url}/articles/image.png")).unwrap()),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_title_tag_and_truncates() {
+        let title = "a".repeat(MAX_LINK_PREVIEW_TEXT_LENGTH + 10);
+        let url = serve(vec![page(
+            "/",
+            &format!("<head><title>{title}</title></head>"),
+        )]);
+        let page = fetch_page(&client().unwrap(), &url).await.unwrap().unwrap();
+        assert_eq!(page.title, &title[..MAX_LINK_PREVIEW_TEXT_LENGTH]);
+        assert_eq!(page.description, "");
+        assert_eq!(page.image_url, None);
+    }
+
+    #[tokio::test]
+    async fn skips_what_is_not_an_html_page_with_a_title() {
+        let url = serve(vec![
+            Route {
+                path: "/data",
+                status: "200 OK",
+                headers: vec![("Content-Type", "application/json")],
+                body: br#"{"title": "Not a page"}"#.to_vec(),
+            },
+            page("/untitled", "<head></head><body>No title</body>"),
+        ]);
+        let client = client().unwrap();
+        for url in [
+            format!("{url}/data"),
+            format!("{url}/untitled"),
+            String::from("file:///etc/hosts"),
+        ] {
+            assert_eq!(fetch_page(&client, &url).await.unwrap(), None, "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_on_errors_and_redirect_loops() {
+        let url = serve(vec![redirect("/loop", "/loop")]);
+        let client = client().unwrap();
+        assert!(fetch_page(&client, &format!("{url}/missing"))
+            .await
+            .is_err());
+        assert!(fetch_page(&client, &format!("{url}/loop")).await.is_err());
+        assert!(fetch_page(&client, "not a url").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fetches_images_up_to_the_size_limit() {
+        let image = |path, size| Route {
+            path,
+            status: "200 OK",
+            headers: vec![("Content-Type", "image/png")],
+            body: vec![0; size],
+        };
+        let url = serve(vec![
+            image("/small.png", 1024),
+            image("/large.png", MAX_IMAGE_SIZE),
+        ]);
+        let client = client().unwrap();
+        let small = Url::parse(&format!("{url}/small.png")).unwrap();
+        assert_eq!(fetch_image(&client, small).await.unwrap().len(), 1024);
+        let large = Url::parse(&format!("{url}/large.png")).unwrap();
+        assert!(fetch_image(&client, large).await.is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use serde::Serialize;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+// A bootstrap and signal server that one device on the LAN runs for the others,
+// so that peers can find each other without internet access
+pub struct LocalServices {
+    _signal_server: sbd_server::SbdServer,
+    _bootstrap_shutdown: Mutex<kitsune_p2p_bootstrap::BootstrapShutdown>,
+    pub bootstrap_port: u16,
+    pub signal_port: u16,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LocalServicesUrls {
+    pub signal_url: String,
+    pub bootstrap_url: String,
+}
+
+// Binds both servers on all interfaces. A port of 0 picks a free one.
+pub async fn start(bootstrap_port: u16, signal_port: u16) -> anyhow::Result<LocalServices> {
+    let (bootstrap_driver, bootstrap_addr, bootstrap_shutdown) = kitsune_p2p_bootstrap::run(
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, bootstrap_port)),
+        vec![],
+    )
+    .await
+    .map_err(|e| anyhow::anyhow!("Could not start the bootstrap server: {e}"))?;
+    tauri::async_runtime::spawn(bootstrap_driver);
+
+    let mut config = sbd_server::Config::default();
+    config.bind = vec![format!("{}:{}", Ipv4Addr::UNSPECIFIED, signal_port)];
+    let signal_server = sbd_server::SbdServer::new(Arc::new(config)).await?;
+    let signal_port = signal_server
+        .bind_addrs()
+        .first()
+        .map(|addr| addr.port())
+        .ok_or(anyhow::anyhow!("The signal server did not bind to any address"))?;
+
+    Ok(LocalServices {
+        _signal_server: signal_server,
+        _bootstrap_shutdown: Mutex::new(bootstrap_shutdown),
+        bootstrap_port: bootstrap_addr.port(),
+        signal_port,
+    })
+}
+
+impl LocalServices {
+    // The urls this device uses itself
+    pub fn loopback_urls(&self) -> LocalServicesUrls {
+        self.urls_for(Ipv4Addr::LOCALHOST)
+    }
+
+    // The urls the other devices on the LAN should enter
+    pub fn lan_urls(&self) -> LocalServicesUrls {
+        self.urls_for(lan_ip().unwrap_or(Ipv4Addr::LOCALHOST))
+    }
+
+    fn urls_for(&self, ip: Ipv4Addr) -> LocalServicesUrls {
+        LocalServicesUrls {
+            signal_url: format!("ws://{}:{}", ip, self.signal_port),
+            bootstrap_url: format!("http://{}:{}", ip, self.bootstrap_port),
+        }
+    }
+}
+
+// Asks the OS which interface it would route a private network address through.
+// No packet is sent, so this also works without internet access.
+fn lan_ip() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(10, 255, 255, 255), 1)).ok()?;
+    match socket.local_addr().ok()? {
+        SocketAddr::V4(addr) if !addr.ip().is_unspecified() => Some(*addr.ip()),
+        _ => None,
+    }
+}
@@ -0,0 +1,263 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_holochain::HolochainExt;
+
+use crate::backup::{self, PendingRestore};
+use crate::keystore::{self, KeystoreState, KeystoreStatus};
+use crate::settings::Settings;
+use crate::storage::HolochainDir;
+use crate::{app_data_dir, get_version, happ_bundle, start_holochain, APP_ID};
+
+// Copied by `backup::copy_keystore`, which rewrites the paths in lair's config
+const KEYSTORE_DIR: &str = "keystore";
+// Appended to the earlier version's directory name for the copy the data is exported from.
+// It doesn't parse as a version, so `find_previous_version` never picks it.
+const EXPORT_DIR_SUFFIX: &str = "-export";
+// Tells the splashscreen the migration failed and the earlier data was left as it was
+const MIGRATION_FAILED_EVENT: &str = "migration-failed";
+
+// The earlier version found on startup, if the current one has no data yet
+pub struct PreviousVersion(pub Mutex<Option<String>>);
+
+// The migration whose data is being exported, while the conductor runs on the export copy
+#[derive(Default)]
+pub struct MigrationState(Mutex<Option<Export>>);
+
+struct Export {
+    version: String,
+    dir: PathBuf,
+    // The conductor is started again with it on this version's directory once exported
+    passphrase: Option<Vec<u8>>,
+}
+
+fn versions_dir() -> PathBuf {
+    app_data_dir().join("holochain")
+}
+
+// Picks the latest earlier version directory that has a keystore. Every version since the
+// directories were split by version keeps its keystore in the same place, so this also finds
+// the data of versions that predate migrations.
+pub fn find_previous_version(holochain_dir: &Path) -> Option<String> {
+    if tauri::is_dev() || keystore::lair_config_path(holochain_dir).exists() {
+        return None;
+    }
+    let current = parse_version(&get_version())?;
+    std::fs::read_dir(versions_dir())
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| keystore::lair_config_path(&entry.path()).exists())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            Some((parse_version(&name)?, name))
+        })
+        .filter(|(version, _)| *version < current)
+        .max()
+        .map(|(_, name)| name)
+}
+
+// Version directories are named like `get_version` returns, e.g. "0.7"
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
+
+#[tauri::command]
+pub fn get_previous_version(previous_version: State<'_, PreviousVersion>) -> Option<String> {
+    previous_version.0.lock().unwrap().clone()
+}
+
+// Migrates like a backup is restored. The conductor is first started on a copy of the earlier
+// version's directory to export its conversations, membrane proofs, profile and contacts, see
+// `finish_export`. They are then reinstalled with the same agent key into this version's
+// conductor, whatever its holochain version or DNA hash. The earlier directory is left as it is
+// and kept until the migration is confirmed with `confirm_migration`.
+#[tauri::command]
+pub fn migrate_previous_version(
+    handle: AppHandle,
+    keystore_state: State<'_, KeystoreState>,
+    previous_version: State<'_, PreviousVersion>,
+    migration_state: State<'_, MigrationState>,
+) -> Result<KeystoreStatus, String> {
+    if keystore_state.get() != KeystoreStatus::New {
+        return Err(String::from("This version already has data"));
+    }
+    let version = previous_version
+        .0
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or(String::from("No earlier version to migrate from"))?;
+    let export_dir = prepare_export(&version).map_err(|e| e.to_string())?;
+    previous_version.0.lock().unwrap().take();
+    *migration_state.0.lock().unwrap() = Some(Export {
+        version,
+        dir: export_dir.clone(),
+        passphrase: None,
+    });
+
+    // The passphrase and how it is obtained are kept in the version independent settings
+    match keystore::startup_passphrase(&export_dir, &Settings::load()) {
+        Some(passphrase) => {
+            keystore_state.set(KeystoreStatus::Unlocking);
+            start_holochain(&handle, passphrase).map_err(|e| {
+                keystore_state.set(KeystoreStatus::Failed);
+                e.to_string()
+            })?;
+        }
+        None => keystore_state.set(KeystoreStatus::Locked),
+    }
+    Ok(keystore_state.get())
+}
+
+// Copies the earlier version's directory for the export, so its conductor data isn't touched.
+// The files backups and restores keep next to the conductor data are left out.
+fn prepare_export(version: &str) -> anyhow::Result<PathBuf> {
+    let previous_dir = versions_dir().join(version);
+    let export_dir = versions_dir().join(format!("{version}{EXPORT_DIR_SUFFIX}"));
+    // Left over if the app was closed during an earlier attempt
+    if export_dir.exists() {
+        std::fs::remove_dir_all(&export_dir)?;
+    }
+    std::fs::create_dir_all(&export_dir)?;
+    for entry in std::fs::read_dir(&previous_dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name != KEYSTORE_DIR && !backup::is_transient_file(&name) {
+            copy_all(&entry.path(), &export_dir.join(name))?;
+        }
+    }
+    backup::copy_keystore(&previous_dir, &export_dir)?;
+    Ok(export_dir)
+}
+
+fn copy_all(from: &Path, to: &Path) -> std::io::Result<()> {
+    if from.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        std::fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+// The directory to start the conductor on while an export is pending, instead of this version's.
+// Keeps the passphrase to start the conductor again once the data is exported.
+pub fn export_dir(handle: &AppHandle, passphrase: &[u8]) -> Option<PathBuf> {
+    let state = handle.state::<MigrationState>();
+    let mut export = state.0.lock().unwrap();
+    let export = export.as_mut()?;
+    export.passphrase = Some(passphrase.to_vec());
+    Some(export.dir.clone())
+}
+
+pub fn is_exporting(handle: &AppHandle) -> bool {
+    handle.state::<MigrationState>().0.lock().unwrap().is_some()
+}
+
+// Called once the conductor started on the export copy. Leaves the exported data for `setup` to
+// reinstall like a restored backup, and starts the conductor on this version's directory.
+// If the export fails, the splashscreen offers the migration again.
+pub async fn finish_export(handle: AppHandle) {
+    let Some(export) = handle.state::<MigrationState>().0.lock().unwrap().take() else {
+        return;
+    };
+    let result = export_data(&handle, &export.version).await;
+    handle.remove_plugin("holochain");
+    if let Err(err) = std::fs::remove_dir_all(&export.dir) {
+        log::warn!("Failed to delete the migration export copy: {err:?}");
+    }
+
+    let keystore_state = handle.state::<KeystoreState>();
+    match result {
+        Ok(()) => {
+            keystore_state.set(KeystoreStatus::Unlocking);
+            let passphrase = export.passphrase.unwrap_or_default();
+            if let Err(err) = start_holochain(&handle, passphrase) {
+                log::error!("Failed to start holochain after the migration: {err:?}");
+                keystore_state.set(KeystoreStatus::Failed);
+            }
+        }
+        Err(err) => {
+            log::error!("Failed to export the data of version {}: {err:?}", export.version);
+            let holochain_dir = handle.state::<HolochainDir>().0.clone();
+            if let Err(err) = backup::discard_restore(&holochain_dir) {
+                log::warn!("Failed to delete what was migrated: {err:?}");
+            }
+            keystore_state.set(KeystoreStatus::New);
+            *handle.state::<PreviousVersion>().0.lock().unwrap() = Some(export.version);
+            if let Err(err) = handle.emit(MIGRATION_FAILED_EVENT, err.to_string()) {
+                log::warn!("Failed to emit {MIGRATION_FAILED_EVENT}: {err}");
+            }
+        }
+    }
+}
+
+async fn export_data(handle: &AppHandle, version: &str) -> anyhow::Result<()> {
+    // Versions from before migrations may lack zome calls the export needs, like
+    // `get_my_membrane_proof`, so the copy's coordinators are updated if it fails
+    let restore = match PendingRestore::collect(handle).await {
+        Ok(restore) => restore,
+        Err(err) => {
+            log::warn!("Updating the coordinators of version {version} to export its data: {err:?}");
+            handle
+                .holochain()?
+                .update_app_if_necessary(String::from(APP_ID), happ_bundle()?)
+                .await?;
+            PendingRestore::collect(handle).await?
+        }
+    };
+
+    let holochain_dir = handle.state::<HolochainDir>().0.clone();
+    backup::copy_keystore(&versions_dir().join(version), &holochain_dir)?;
+    // Lair isn't running on it yet, so backups can be exported right away
+    backup::copy_keystore_at_rest(&holochain_dir)?;
+    restore.save(&holochain_dir)?;
+
+    let mut settings = Settings::load();
+    settings.migrated_from = Some(version.to_string());
+    settings.migration_imported = false;
+    settings.save()
+}
+
+// Called by `setup` once the exported data is reinstalled and checked
+pub fn imported() -> anyhow::Result<()> {
+    let mut settings = Settings::load();
+    if settings.migrated_from.is_none() {
+        return Ok(());
+    }
+    settings.migration_imported = true;
+    settings.save()
+}
+
+// Only offered once the migrated data was imported
+#[tauri::command]
+pub fn get_migrated_from() -> Option<String> {
+    let settings = Settings::load();
+    settings.migration_imported.then_some(settings.migrated_from).flatten()
+}
+
+// Deletes the directory of the version that was migrated from
+#[tauri::command]
+pub fn confirm_migration() -> Result<(), String> {
+    let mut settings = Settings::load();
+    let Some(version) = settings.migrated_from.clone() else {
+        return Ok(());
+    };
+    if !settings.migration_imported {
+        return Err(String::from(
+            "The migrated data hasn't been imported into this version yet",
+        ));
+    }
+    if version != get_version() {
+        let previous_dir = versions_dir().join(&version);
+        if previous_dir.exists() {
+            std::fs::remove_dir_all(previous_dir).map_err(|e| e.to_string())?;
+        }
+    }
+    settings.migrated_from = None;
+    settings.migration_imported = false;
+    settings.save().map_err(|e| e.to_string())
+}
@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::PathBuf;
+use tauri::{AppHandle, State};
+use tauri_plugin_holochain::WANNetworkConfig;
+use url2::Url2;
+
+use crate::keystore::KeystoreUnlock;
+use crate::local_services::{LocalServices, LocalServicesUrls};
+use crate::{app_data_dir, BOOTSTRAP_URL, ICE_URLS, SIGNAL_URL};
+
+const SETTINGS_FILE: &str = "settings.json";
+
+// Persisted next to the versioned holochain directories, so settings survive app upgrades
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Settings {
+    pub network_profile: NetworkProfile,
+    // None means the built-in endpoints are used (or the local services in dev)
+    pub network: Option<NetworkSettings>,
+    // None picks one for the platform, see `GossipArc::for_platform`
+    pub gossip_arc: Option<GossipArc>,
+    pub keystore_unlock: KeystoreUnlock,
+    // The earlier version whose data was migrated, until its directory is deleted
+    pub migrated_from: Option<String>,
+    // Set once the migrated data was reinstalled into this version's conductor
+    pub migration_imported: bool,
+    // Links in messages being sent are previewed unless this is set, see link_preview.rs
+    pub link_previews_disabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum GossipArc {
+    // Hold the entire DHT of every conversation
+    Full,
+    // Let the conductor size the arc to what the device can handle
+    Dynamic,
+    // Only hold our own data and rely on other peers for the rest
+    Empty,
+}
+
+impl GossipArc {
+    // Desktops and headless nodes hold everything, battery-constrained mobiles only part of it
+    pub fn for_platform(platform: &str) -> Self {
+        match platform {
+            "android" | "ios" => GossipArc::Dynamic,
+            _ => GossipArc::Full,
+        }
+    }
+
+    pub fn platform_default() -> Self {
+        Self::for_platform(tauri_plugin_os::platform())
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GossipArcSettings {
+    pub selected: Option<GossipArc>,
+    pub platform_default: GossipArc,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(tag = "type")]
+pub enum NetworkProfile {
+    // Signal and bootstrap servers on the internet, see `Settings::network`
+    #[default]
+    Internet,
+    // Only discover peers on the local network through mDNS
+    Mdns,
+    // Use the bootstrap and signal server another device on the LAN is running
+    LanServer {
+        signal_url: String,
+        bootstrap_url: String,
+    },
+    // Run the bundled bootstrap and signal server for the other devices on the LAN.
+    // A port of 0 picks a free one on the next start, which is then saved in its place.
+    LanHost {
+        bootstrap_port: u16,
+        signal_port: u16,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NetworkSettings {
+    pub signal_url: String,
+    pub bootstrap_url: String,
+    pub ice_urls: Vec<String>,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            signal_url: SIGNAL_URL.to_string(),
+            bootstrap_url: BOOTSTRAP_URL.to_string(),
+            ice_urls: ICE_URLS.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+}
+
+impl NetworkSettings {
+    // Without internet access there are no STUN servers to reach
+    pub fn for_lan(signal_url: &str, bootstrap_url: &str) -> Self {
+        Self {
+            signal_url: signal_url.to_string(),
+            bootstrap_url: bootstrap_url.to_string(),
+            ice_urls: vec![],
+        }
+    }
+
+    pub fn wan_network_config(&self) -> anyhow::Result<WANNetworkConfig> {
+        Ok(WANNetworkConfig {
+            signal_url: parse_url(&self.signal_url)?,
+            bootstrap_url: parse_url(&self.bootstrap_url)?,
+            ice_servers_urls: self
+                .ice_urls
+                .iter()
+                .map(|url| parse_url(url))
+                .collect::<anyhow::Result<Vec<Url2>>>()?,
+        })
+    }
+}
+
+fn parse_url(url: &str) -> anyhow::Result<Url2> {
+    Url2::try_parse(url).map_err(|e| anyhow::anyhow!("Invalid url {url}: {e:?}"))
+}
+
+impl Settings {
+    pub fn gossip_arc(&self) -> GossipArc {
+        self.gossip_arc.unwrap_or_else(GossipArc::platform_default)
+    }
+
+    fn path() -> PathBuf {
+        app_data_dir().join(SETTINGS_FILE)
+    }
+
+    // Falls back to the defaults if the file is missing or can't be read
+    pub fn load() -> Self {
+        let Ok(bytes) = std::fs::read(Self::path()) else {
+            return Self::default();
+        };
+        serde_json::from_slice(&bytes).unwrap_or_else(|err| {
+            log::warn!("Ignoring malformed {SETTINGS_FILE}: {err:?}");
+            Self::default()
+        })
+    }
+
+    // Renaming replaces the file at once, so a crash leaves either the old or the new settings
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temp_path = path.with_extension("json.tmp");
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)?;
+        file.sync_all()?;
+        std::fs::rename(temp_path, path)?;
+        Ok(())
+    }
+}
+
+#[tauri::command]
+pub fn get_network_settings() -> Option<NetworkSettings> {
+    Settings::load().network
+}
+
+#[tauri::command]
+pub fn get_default_network_settings() -> NetworkSettings {
+    NetworkSettings::default()
+}
+
+#[tauri::command]
+pub fn get_network_profile() -> NetworkProfile {
+    Settings::load().network_profile
+}
+
+// Saves the profile and endpoints and restarts the app, so the conductor is started with them.
+// Passing None for the endpoints resets them to the built-in ones.
+#[tauri::command]
+pub fn set_network_settings(
+    handle: AppHandle,
+    profile: NetworkProfile,
+    network: Option<NetworkSettings>,
+) -> Result<(), String> {
+    if let Some(network) = &network {
+        network.wan_network_config().map_err(|e| e.to_string())?;
+    }
+    if let NetworkProfile::LanServer {
+        signal_url,
+        bootstrap_url,
+    } = &profile
+    {
+        NetworkSettings::for_lan(signal_url, bootstrap_url)
+            .wan_network_config()
+            .map_err(|e| e.to_string())?;
+    }
+    let mut settings = Settings::load();
+    settings.network_profile = profile;
+    settings.network = network;
+    settings.save().map_err(|e| e.to_string())?;
+    handle.restart()
+}
+
+// The urls other devices should use, if this device is hosting the LAN services
+#[tauri::command]
+pub fn get_local_services_urls(
+    local_services: State<'_, Option<LocalServices>>,
+) -> Option<LocalServicesUrls> {
+    local_services.as_ref().map(|services| services.lan_urls())
+}
+
+#[tauri::command]
+pub fn get_gossip_arc_settings() -> GossipArcSettings {
+    GossipArcSettings {
+        selected: Settings::load().gossip_arc,
+        platform_default: GossipArc::platform_default(),
+    }
+}
+
+#[tauri::command]
+pub fn get_link_previews() -> bool {
+    !Settings::load().link_previews_disabled
+}
+
+#[tauri::command]
+pub fn set_link_previews(enabled: bool) -> Result<(), String> {
+    let mut settings = Settings::load();
+    settings.link_previews_disabled = !enabled;
+    settings.save().map_err(|e| e.to_string())
+}
+
+// Saves the override and restarts the app, so the conductor is started with it.
+// Passing None goes back to the platform default.
+#[tauri::command]
+pub fn set_gossip_arc(handle: AppHandle, gossip_arc: Option<GossipArc>) -> Result<(), String> {
+    let mut settings = Settings::load();
+    settings.gossip_arc = gossip_arc;
+    settings.save().map_err(|e| e.to_string())?;
+    handle.restart()
+}
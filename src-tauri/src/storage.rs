@@ -0,0 +1,37 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tauri::State;
+
+use crate::settings::{GossipArc, Settings};
+
+// The directory the conductor was started with.
+// In dev this is a fresh temporary directory, so it can't be recomputed later.
+pub struct HolochainDir(pub PathBuf);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct StorageUsage {
+    pub gossip_arc: GossipArc,
+    pub bytes: u64,
+}
+
+#[tauri::command]
+pub fn get_storage_usage(holochain_dir: State<'_, HolochainDir>) -> Result<StorageUsage, String> {
+    Ok(StorageUsage {
+        gossip_arc: Settings::load().gossip_arc(),
+        bytes: dir_size(&holochain_dir.0).map_err(|e| e.to_string())?,
+    })
+}
+
+fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
@@ -0,0 +1,25 @@
+use image::codecs::jpeg::JpegEncoder;
+use tauri::ipc::{InvokeBody, Request, Response};
+
+// Thumbnails fit in a square of this many pixels
+const THUMBNAIL_SIZE: u32 = 320;
+const THUMBNAIL_QUALITY: u8 = 70;
+
+// Takes the bytes of an image as the raw request body, and returns a JPEG thumbnail of it.
+// Raw bytes avoid encoding the whole image as a JSON array.
+#[tauri::command]
+pub async fn generate_thumbnail(request: Request<'_>) -> Result<Response, String> {
+    let InvokeBody::Raw(bytes) = request.body() else {
+        return Err(String::from("Expected the bytes of an image"));
+    };
+    thumbnail(bytes).map(Response::new).map_err(|e| e.to_string())
+}
+
+pub fn thumbnail(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let image = image::load_from_memory(bytes)?;
+    // JPEG has no alpha channel, so transparent images are flattened
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+    let mut jpeg = vec![];
+    JpegEncoder::new_with_quality(&mut jpeg, THUMBNAIL_QUALITY).encode_image(&thumbnail)?;
+    Ok(jpeg)
+}
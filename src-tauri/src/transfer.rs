@@ -0,0 +1,628 @@
+use anyhow::anyhow;
+use futures::stream::{self, StreamExt};
+use holochain_client::{AppWebsocket, CellInfo, ZomeCallTarget};
+use holochain_types::prelude::*;
+use lair_keystore::dependencies::sodoken::{self, BufRead, BufWriteSized};
+use relay_integrity::{FileMetadata, Properties};
+use serde_bytes::ByteBuf;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::ipc::{InvokeBody, Request, Response};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_holochain::HolochainExt;
+
+use crate::storage::HolochainDir;
+use crate::APP_ID;
+
+// Files go through the file_storage zome chunk by chunk. Each transfer keeps its progress in
+// a directory of its own, so it picks up where it stopped when it is started again, even
+// after the app restarted. Uploads are resumed by uploading the same file with the same id.
+// Ids are hex, e.g. the hash of the file's content, as they name the transfer's directory.
+// Transfers of the same id run one after the other, see `TransferLocks`.
+
+const ROLE_NAME: &str = "relay";
+const TRANSFERS_DIR: &str = "transfers";
+const TRANSFER_FILE: &str = "transfer.json";
+// The chunks downloaded so far, by index
+const CHUNKS_DIR: &str = "chunks";
+// The same chunk size the file storage client of the UI uploads with
+const CHUNK_SIZE: usize = 256 * 1024;
+const PARALLEL_DOWNLOADS: usize = 4;
+const MAX_ATTEMPTS: u32 = 5;
+const PROGRESS_EVENT: &str = "transfer://progress";
+const MAX_ID_LENGTH: usize = 128;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+enum TransferKind {
+    Upload {
+        name: String,
+        file_type: String,
+        last_modified: Timestamp,
+        size: usize,
+        // Hex of the blake2b hash of the file. Missing in uploads that were pending when it was
+        // added, which are then started over.
+        #[serde(default)]
+        content_hash: String,
+        // The chunks committed so far, in order
+        chunks_hashes: Vec<EntryHash>,
+    },
+    Download {
+        storage_entry_hash: EntryHash,
+        name: String,
+        // The number of chunks, once the file's metadata is known
+        total: usize,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Transfer {
+    id: String,
+    conversation_id: String,
+    kind: TransferKind,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TransferProgress {
+    pub id: String,
+    pub conversation_id: String,
+    pub name: String,
+    pub upload: bool,
+    pub done: usize,
+    pub total: usize,
+}
+
+// Transfers of the same id share a directory, so one waits for the other to finish.
+// A second download of a file then fetches it again, as the first deletes its chunks.
+#[derive(Default)]
+pub struct TransferLocks(Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>);
+
+impl TransferLocks {
+    fn get(&self, id: &str) -> Arc<tokio::sync::Mutex<()>> {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    // Forgets the lock of an id once no transfer holds or waits for it
+    fn release(&self, id: &str) {
+        let mut locks = self.0.lock().unwrap();
+        if locks
+            .get(id)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(id);
+        }
+    }
+}
+
+async fn locked<T>(
+    handle: &AppHandle,
+    id: &str,
+    transfer: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    let locks = handle.state::<TransferLocks>();
+    let lock = locks.get(id);
+    let result = {
+        let _guard = lock.lock().await;
+        transfer.await
+    };
+    drop(lock);
+    locks.release(id);
+    result
+}
+
+// Only hex digits and dashes, so an id can't name a path outside the transfers directory
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_ID_LENGTH
+        && id.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+}
+
+fn transfer_dir(holochain_dir: &Path, id: &str) -> anyhow::Result<PathBuf> {
+    if !is_valid_id(id) {
+        return Err(anyhow!("Invalid transfer id {id}"));
+    }
+    Ok(holochain_dir.join(TRANSFERS_DIR).join(id))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+async fn content_hash(bytes: &[u8]) -> anyhow::Result<String> {
+    let hash = BufWriteSized::<32>::new_no_lock();
+    sodoken::hash::blake2b::hash(hash.clone(), BufRead::from(bytes.to_vec())).await?;
+    let hash = hex(&hash.read_lock());
+    Ok(hash)
+}
+
+// For files made by the app itself, e.g. voice messages
+pub fn new_transfer_id(now: Timestamp) -> String {
+    format!("{:x}", now.as_micros())
+}
+
+// Mirrors downloadTransferId in ui/src/lib/transfer.ts
+fn download_transfer_id(storage_entry_hash: &EntryHash) -> String {
+    hex(storage_entry_hash.get_raw_39())
+}
+
+// Writes to a temporary file first, so an interrupted write never leaves a partial file behind
+fn write_atomically(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, bytes)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+impl Transfer {
+    fn load(dir: &Path) -> anyhow::Result<Option<Self>> {
+        let path = dir.join(TRANSFER_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&std::fs::read(path)?)?))
+    }
+
+    fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        write_atomically(&dir.join(TRANSFER_FILE), &serde_json::to_vec(self)?)
+    }
+
+    fn name(&self) -> &str {
+        match &self.kind {
+            TransferKind::Upload { name, .. } | TransferKind::Download { name, .. } => name,
+        }
+    }
+
+    fn progress(&self, dir: &Path) -> TransferProgress {
+        let (upload, done, total) = match &self.kind {
+            TransferKind::Upload {
+                size,
+                chunks_hashes,
+                ..
+            } => (true, chunks_hashes.len(), (size + CHUNK_SIZE - 1) / CHUNK_SIZE),
+            TransferKind::Download { total, .. } => {
+                let done = std::fs::read_dir(dir.join(CHUNKS_DIR))
+                    .map(|entries| {
+                        entries
+                            .flatten()
+                            .filter(|entry| entry.path().extension().is_none())
+                            .count()
+                    })
+                    .unwrap_or(0);
+                (false, done, *total)
+            }
+        };
+        TransferProgress {
+            id: self.id.clone(),
+            conversation_id: self.conversation_id.clone(),
+            name: self.name().to_string(),
+            upload,
+            done,
+            total,
+        }
+    }
+}
+
+fn emit_progress(handle: &AppHandle, transfer: &Transfer, done: usize, total: usize) {
+    let progress = TransferProgress {
+        id: transfer.id.clone(),
+        conversation_id: transfer.conversation_id.clone(),
+        name: transfer.name().to_string(),
+        upload: matches!(transfer.kind, TransferKind::Upload { .. }),
+        done,
+        total,
+    };
+    if let Err(err) = handle.emit(PROGRESS_EVENT, progress) {
+        log::warn!("Failed to emit transfer progress: {err}");
+    }
+}
+
+fn header(request: &Request<'_>, name: &str) -> anyhow::Result<String> {
+    Ok(request
+        .headers()
+        .get(name)
+        .ok_or(anyhow!("Missing header {name}"))?
+        .to_str()?
+        .to_string())
+}
+
+// Takes the bytes of the file as the raw request body, and its details as headers:
+// `conversation-id`, `transfer-id`, `file-name` (URI encoded), `file-type` and
+// `last-modified` in milliseconds. Returns the hash of the stored file's metadata.
+// Uploading with the id of an unfinished upload resumes it.
+#[tauri::command]
+pub async fn upload_file(
+    handle: AppHandle,
+    holochain_dir: State<'_, HolochainDir>,
+    request: Request<'_>,
+) -> Result<String, String> {
+    let InvokeBody::Raw(bytes) = request.body() else {
+        return Err(String::from("Expected the bytes of a file"));
+    };
+    let details = || -> anyhow::Result<(String, String, String, String, i64)> {
+        Ok((
+            header(&request, "conversation-id")?,
+            header(&request, "transfer-id")?,
+            decode_uri_component(&header(&request, "file-name")?),
+            header(&request, "file-type")?,
+            header(&request, "last-modified")?.parse()?,
+        ))
+    };
+    let (conversation_id, id, name, file_type, last_modified) =
+        details().map_err(|e| e.to_string())?;
+    let hash = upload_bytes(
+        &handle,
+        &holochain_dir.0,
+        &conversation_id,
+        &id,
+        bytes,
+        name,
+        file_type,
+        Timestamp::from_micros(last_modified * 1000),
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(hash.to_string())
+}
+
+// The UI encodes file names with encodeURIComponent, as headers can only be ASCII
+fn decode_uri_component(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| value.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_bytes(
+    handle: &AppHandle,
+    holochain_dir: &Path,
+    conversation_id: &str,
+    id: &str,
+    bytes: &[u8],
+    name: String,
+    file_type: String,
+    last_modified: Timestamp,
+) -> anyhow::Result<EntryHash> {
+    let upload = upload(
+        handle,
+        holochain_dir,
+        conversation_id,
+        id,
+        bytes,
+        name,
+        file_type,
+        last_modified,
+    );
+    locked(handle, id, upload).await
+}
+
+// Only resumes an upload of the same content, as the chunks committed so far are reused
+#[allow(clippy::too_many_arguments)]
+async fn upload(
+    handle: &AppHandle,
+    holochain_dir: &Path,
+    conversation_id: &str,
+    id: &str,
+    bytes: &[u8],
+    name: String,
+    file_type: String,
+    last_modified: Timestamp,
+) -> anyhow::Result<EntryHash> {
+    let dir = transfer_dir(holochain_dir, id)?;
+    let hash = content_hash(bytes).await?;
+    let resumable = Transfer::load(&dir)?.filter(|transfer| {
+        matches!(
+            &transfer.kind,
+            TransferKind::Upload { size, content_hash, .. }
+                if *size == bytes.len() && *content_hash == hash
+        )
+    });
+    let mut transfer = match resumable {
+        Some(transfer) => transfer,
+        None => {
+            let transfer = Transfer {
+                id: id.to_string(),
+                conversation_id: conversation_id.to_string(),
+                kind: TransferKind::Upload {
+                    name,
+                    file_type,
+                    last_modified,
+                    size: bytes.len(),
+                    content_hash: hash,
+                    chunks_hashes: vec![],
+                },
+            };
+            transfer.save(&dir)?;
+            transfer
+        }
+    };
+    let app_ws = app_websocket(handle).await?;
+    let cell_id = conversation_cell_id(&app_ws, conversation_id).await?;
+
+    let chunks: Vec<&[u8]> = bytes.chunks(CHUNK_SIZE).collect();
+    loop {
+        let TransferKind::Upload { chunks_hashes, .. } = &mut transfer.kind else {
+            unreachable!()
+        };
+        let done = chunks_hashes.len();
+        if done == chunks.len() {
+            break;
+        }
+        // Chunks are committed one at a time, as they are written to the same source chain
+        let hash: EntryHash = with_retries(|| {
+            call_file_storage(
+                &app_ws,
+                &cell_id,
+                "create_file_chunk",
+                ByteBuf::from(chunks[done].to_vec()),
+            )
+        })
+        .await?;
+        chunks_hashes.push(hash);
+        transfer.save(&dir)?;
+        emit_progress(handle, &transfer, done + 1, chunks.len());
+    }
+
+    let TransferKind::Upload {
+        name,
+        file_type,
+        last_modified,
+        size,
+        chunks_hashes,
+        ..
+    } = transfer.kind
+    else {
+        unreachable!()
+    };
+    let metadata = FileMetadata {
+        name,
+        last_modified,
+        size,
+        file_type,
+        chunks_hashes,
+    };
+    let hash = with_retries(|| {
+        call_file_storage(&app_ws, &cell_id, "create_file_metadata", metadata.clone())
+    })
+    .await?;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(hash)
+}
+
+// Returns the bytes of a stored file. The chunks downloaded so far are kept until the whole
+// file is, so downloading it again only fetches the missing ones.
+#[tauri::command]
+pub async fn download_file(
+    handle: AppHandle,
+    holochain_dir: State<'_, HolochainDir>,
+    conversation_id: String,
+    storage_entry_hash: String,
+) -> Result<Response, String> {
+    download(&handle, &holochain_dir.0, &conversation_id, &storage_entry_hash)
+        .await
+        .map(Response::new)
+        .map_err(|e| e.to_string())
+}
+
+async fn download(
+    handle: &AppHandle,
+    holochain_dir: &Path,
+    conversation_id: &str,
+    storage_entry_hash: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let storage_entry_hash = EntryHash::try_from(storage_entry_hash.to_string())?;
+    let id = download_transfer_id(&storage_entry_hash);
+    let download = download_chunks(handle, holochain_dir, conversation_id, &storage_entry_hash);
+    locked(handle, &id, download).await
+}
+
+async fn download_chunks(
+    handle: &AppHandle,
+    holochain_dir: &Path,
+    conversation_id: &str,
+    storage_entry_hash: &EntryHash,
+) -> anyhow::Result<Vec<u8>> {
+    let id = download_transfer_id(storage_entry_hash);
+    let dir = transfer_dir(holochain_dir, &id)?;
+    let chunks_dir = dir.join(CHUNKS_DIR);
+    std::fs::create_dir_all(&chunks_dir)?;
+
+    let app_ws = app_websocket(handle).await?;
+    let cell_id = conversation_cell_id(&app_ws, conversation_id).await?;
+    let metadata: FileMetadata = with_retries(|| {
+        call_file_storage(
+            &app_ws,
+            &cell_id,
+            "get_file_metadata",
+            storage_entry_hash.clone(),
+        )
+    })
+    .await?;
+
+    let total = metadata.chunks_hashes.len();
+    let transfer = Transfer {
+        id,
+        conversation_id: conversation_id.to_string(),
+        kind: TransferKind::Download {
+            storage_entry_hash: storage_entry_hash.clone(),
+            name: metadata.name.clone(),
+            total,
+        },
+    };
+    transfer.save(&dir)?;
+    let missing: Vec<(usize, &EntryHash)> = metadata
+        .chunks_hashes
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !chunks_dir.join(index.to_string()).exists())
+        .collect();
+    let mut done = total - missing.len();
+    let mut downloads = stream::iter(missing)
+        .map(|(index, chunk_hash)| {
+            let app_ws = &app_ws;
+            let cell_id = &cell_id;
+            async move {
+                let chunk: ByteBuf = with_retries(|| {
+                    call_file_storage(app_ws, cell_id, "get_file_chunk", chunk_hash.clone())
+                })
+                .await?;
+                anyhow::Ok((index, chunk))
+            }
+        })
+        .buffer_unordered(PARALLEL_DOWNLOADS);
+    while let Some(result) = downloads.next().await {
+        let (index, chunk) = result?;
+        write_atomically(&chunks_dir.join(index.to_string()), &chunk)?;
+        done += 1;
+        emit_progress(handle, &transfer, done, total);
+    }
+
+    let mut bytes = Vec::with_capacity(metadata.size);
+    for index in 0..total {
+        bytes.extend(std::fs::read(chunks_dir.join(index.to_string()))?);
+    }
+    std::fs::remove_dir_all(&dir)?;
+    Ok(bytes)
+}
+
+// Unfinished transfers, e.g. from before the app was restarted
+#[tauri::command]
+pub fn get_pending_transfers(
+    holochain_dir: State<'_, HolochainDir>,
+) -> Result<Vec<TransferProgress>, String> {
+    let Ok(entries) = std::fs::read_dir(holochain_dir.0.join(TRANSFERS_DIR)) else {
+        return Ok(vec![]);
+    };
+    let mut pending = vec![];
+    for entry in entries.flatten() {
+        if !entry.file_name().to_str().is_some_and(is_valid_id) {
+            continue;
+        }
+        if let Ok(Some(transfer)) = Transfer::load(&entry.path()) {
+            pending.push(transfer.progress(&entry.path()));
+        }
+    }
+    Ok(pending)
+}
+
+#[tauri::command]
+pub fn cancel_transfer(holochain_dir: State<'_, HolochainDir>, id: String) -> Result<(), String> {
+    let dir = transfer_dir(&holochain_dir.0, &id).map_err(|e| e.to_string())?;
+    if dir.exists() {
+        std::fs::remove_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+async fn with_retries<F, Fut, O>(f: F) -> anyhow::Result<O>
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<O>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(output) => return Ok(output),
+            Err(err) if attempt < MAX_ATTEMPTS => {
+                log::warn!("Transfer failed, attempt {attempt} of {MAX_ATTEMPTS}: {err}");
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+pub async fn app_websocket(handle: &AppHandle) -> anyhow::Result<AppWebsocket> {
+    Ok(handle
+        .holochain()?
+        .app_websocket(String::from(APP_ID))
+        .await?)
+}
+
+pub async fn conversation_cell_id(
+    app_ws: &AppWebsocket,
+    conversation_id: &str,
+) -> anyhow::Result<CellId> {
+    Ok(conversation_cell(app_ws, conversation_id).await?.cell_id)
+}
+
+// The DNA properties of a conversation, e.g. its attachment limits
+pub async fn conversation_properties(
+    app_ws: &AppWebsocket,
+    conversation_id: &str,
+) -> anyhow::Result<Properties> {
+    let cell = conversation_cell(app_ws, conversation_id).await?;
+    Ok(Properties::try_from(cell.dna_modifiers.properties)?)
+}
+
+// Conversations are clone cells, whose network seed is the conversation's id
+async fn conversation_cell(
+    app_ws: &AppWebsocket,
+    conversation_id: &str,
+) -> anyhow::Result<ClonedCell> {
+    let app_info = app_ws
+        .app_info()
+        .await
+        .map_err(tauri_plugin_holochain::Error::ConductorApiError)?
+        .ok_or(anyhow!("{APP_ID} is not installed"))?;
+    app_info
+        .cell_info
+        .get(ROLE_NAME)
+        .into_iter()
+        .flatten()
+        .find_map(|cell| match cell {
+            CellInfo::Cloned(cell) if cell.dna_modifiers.network_seed == conversation_id => {
+                Some(cell.clone())
+            }
+            _ => None,
+        })
+        .ok_or(anyhow!("Conversation {conversation_id} not found"))
+}
+
+async fn call_file_storage<I, O>(
+    app_ws: &AppWebsocket,
+    cell_id: &CellId,
+    fn_name: &str,
+    payload: I,
+) -> anyhow::Result<O>
+where
+    I: serde::Serialize + std::fmt::Debug,
+    O: serde::de::DeserializeOwned + std::fmt::Debug,
+{
+    let response = app_ws
+        .call_zome(
+            ZomeCallTarget::CellId(cell_id.clone()),
+            "file_storage".into(),
+            fn_name.into(),
+            ExternIO::encode(payload)?,
+        )
+        .await
+        .map_err(tauri_plugin_holochain::Error::ConductorApiError)?;
+    Ok(response.decode()?)
+}
@@ -0,0 +1,334 @@
+use anyhow::anyhow;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample};
+use holochain_types::prelude::*;
+use relay_integrity::{File, Properties, DEFAULT_MAX_VOICE_DURATION_MS, DEFAULT_MAX_VOICE_SIZE};
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tauri::{AppHandle, State, WebviewWindow};
+
+use crate::storage::HolochainDir;
+use crate::transfer;
+
+// Voice is recorded at about this rate, which is plenty for speech
+const VOICE_SAMPLE_RATE: u32 = 16000;
+const WAVEFORM_LENGTH: usize = 64;
+// Of the 16 bit mono wav files `wav` writes
+const WAV_HEADER_SIZE: usize = 44;
+
+struct Samples {
+    rate: u32,
+    data: Vec<f32>,
+}
+
+struct Recording {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<anyhow::Result<Samples>>,
+}
+
+// The limits relay_integrity validates voice messages against, from the conversation's properties
+#[derive(Debug, Clone, Copy)]
+struct VoiceLimits {
+    max_duration_ms: u64,
+    max_size: usize,
+}
+
+impl VoiceLimits {
+    fn of(properties: &Properties) -> Self {
+        Self {
+            max_duration_ms: properties
+                .max_voice_duration_ms
+                .unwrap_or(DEFAULT_MAX_VOICE_DURATION_MS),
+            max_size: properties.max_voice_size.unwrap_or(DEFAULT_MAX_VOICE_SIZE),
+        }
+    }
+
+    // The most samples at `rate` that fit both limits
+    fn max_samples(&self, rate: u32) -> usize {
+        let by_duration = (self.max_duration_ms * rate as u64 / 1000) as usize;
+        let by_size = self.max_size.saturating_sub(WAV_HEADER_SIZE) / 2;
+        by_duration.min(by_size)
+    }
+}
+
+async fn voice_limits(handle: &AppHandle, conversation_id: &str) -> anyhow::Result<VoiceLimits> {
+    let app_ws = transfer::app_websocket(handle).await?;
+    let properties = transfer::conversation_properties(&app_ws, conversation_id).await?;
+    Ok(VoiceLimits::of(&properties))
+}
+
+// The recording in progress, if any.
+// cpal streams can't be moved between threads, so each recording has a thread of its own.
+#[derive(Default)]
+pub struct VoiceRecorder(Mutex<Option<Recording>>);
+
+// A voice message stored in file_storage, ready to be posted with `create_voice_message`
+#[derive(Serialize, Debug)]
+pub struct VoiceRecording {
+    pub file: File,
+    pub duration_ms: u64,
+    pub waveform: Vec<u8>,
+}
+
+// Android grants the microphone at runtime. If it isn't granted yet this asks for it and returns
+// false, and the button is held again once it is. iOS asks on its own when recording starts.
+#[tauri::command]
+pub async fn request_microphone_permission(window: WebviewWindow) -> Result<bool, String> {
+    #[cfg(target_os = "android")]
+    {
+        android::request_record_audio(&window).map_err(|e| e.to_string())
+    }
+    #[cfg(not(target_os = "android"))]
+    {
+        let _ = window;
+        Ok(true)
+    }
+}
+
+#[cfg(target_os = "android")]
+mod android {
+    use std::sync::mpsc;
+    use tauri::WebviewWindow;
+
+    // Calls `MainActivity.requestRecordAudioPermission` on the UI thread
+    pub fn request_record_audio(window: &WebviewWindow) -> anyhow::Result<bool> {
+        let (sender, receiver) = mpsc::channel();
+        window.with_webview(move |webview| {
+            webview.jni_handle().exec(move |env, activity, _webview| {
+                let granted = env
+                    .call_method(activity, "requestRecordAudioPermission", "()Z", &[])
+                    .and_then(|granted| granted.z());
+                let _ = sender.send(granted);
+            });
+        })?;
+        Ok(receiver.recv()??)
+    }
+}
+
+// Returns the longest voice message the conversation allows in milliseconds.
+// Recording stops taking samples once it is reached.
+#[tauri::command]
+pub async fn start_voice_recording(
+    handle: AppHandle,
+    recorder: State<'_, VoiceRecorder>,
+    conversation_id: String,
+) -> Result<u64, String> {
+    let limits = voice_limits(&handle, &conversation_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut recording = recorder.0.lock().unwrap();
+    if recording.is_some() {
+        return Err(String::from("A voice message is already being recorded"));
+    }
+    let (stop, stopped) = mpsc::channel();
+    let (started, start_result) = mpsc::channel();
+    let thread = std::thread::spawn(move || record(started, stopped, limits));
+    start_result
+        .recv()
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    *recording = Some(Recording { stop, thread });
+    Ok(limits.max_duration_ms)
+}
+
+// Stops recording and stores the voice message in the conversation's cell
+#[tauri::command]
+pub async fn stop_voice_recording(
+    handle: AppHandle,
+    recorder: State<'_, VoiceRecorder>,
+    holochain_dir: State<'_, HolochainDir>,
+    conversation_id: String,
+) -> Result<VoiceRecording, String> {
+    let samples = finish(&recorder).map_err(|e| e.to_string())?;
+    store(&handle, &holochain_dir.0, &conversation_id, samples)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn cancel_voice_recording(recorder: State<'_, VoiceRecorder>) -> Result<(), String> {
+    finish(&recorder).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn finish(recorder: &VoiceRecorder) -> anyhow::Result<Samples> {
+    let recording = recorder
+        .0
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or(anyhow!("No voice message is being recorded"))?;
+    recording.stop.send(())?;
+    recording
+        .thread
+        .join()
+        .map_err(|_| anyhow!("The recording thread panicked"))?
+}
+
+fn record(
+    started: mpsc::Sender<anyhow::Result<()>>,
+    stopped: mpsc::Receiver<()>,
+    limits: VoiceLimits,
+) -> anyhow::Result<Samples> {
+    let data = Arc::new(Mutex::new(vec![]));
+    let (stream, rate) = match input_stream(data.clone(), limits) {
+        Ok(stream) => {
+            let _ = started.send(Ok(()));
+            stream
+        }
+        Err(err) => {
+            let _ = started.send(Err(anyhow!("Failed to start recording: {err}")));
+            return Err(err);
+        }
+    };
+    // Also stops when the sender is dropped
+    let _ = stopped.recv();
+    drop(stream);
+    let data = std::mem::take(&mut *data.lock().unwrap());
+    Ok(downsample(Samples { rate, data }))
+}
+
+fn input_stream(
+    data: Arc<Mutex<Vec<f32>>>,
+    limits: VoiceLimits,
+) -> anyhow::Result<(cpal::Stream, u32)> {
+    let device = cpal::default_host()
+        .default_input_device()
+        .ok_or(anyhow!("No microphone found"))?;
+    let config = device.default_input_config()?;
+    let channels = config.channels() as usize;
+    let rate = config.sample_rate().0;
+    let max_samples = limits.max_samples(rate);
+    let on_error = |err: cpal::StreamError| log::error!("Voice recording failed: {err}");
+    let stream = match config.sample_format() {
+        SampleFormat::F32 => device.build_input_stream(
+            &config.config(),
+            move |samples: &[f32], _| push_mono(&data, samples, channels, max_samples),
+            on_error,
+            None,
+        )?,
+        SampleFormat::I16 => device.build_input_stream(
+            &config.config(),
+            move |samples: &[i16], _| push_mono(&data, samples, channels, max_samples),
+            on_error,
+            None,
+        )?,
+        SampleFormat::U16 => device.build_input_stream(
+            &config.config(),
+            move |samples: &[u16], _| push_mono(&data, samples, channels, max_samples),
+            on_error,
+            None,
+        )?,
+        format => return Err(anyhow!("Unsupported sample format {format}")),
+    };
+    stream.play()?;
+    Ok((stream, rate))
+}
+
+// Mixes the channels of each frame down to one, up to `max_samples` in total
+fn push_mono<T>(data: &Mutex<Vec<f32>>, samples: &[T], channels: usize, max_samples: usize)
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let mut data = data.lock().unwrap();
+    let remaining = max_samples.saturating_sub(data.len());
+    data.extend(
+        samples.chunks(channels).take(remaining).map(|frame| {
+            frame.iter().map(|s| f32::from_sample(*s)).sum::<f32>() / frame.len() as f32
+        }),
+    );
+}
+
+// Averages groups of samples, to get close to VOICE_SAMPLE_RATE
+fn downsample(samples: Samples) -> Samples {
+    let step = (samples.rate / VOICE_SAMPLE_RATE).max(1) as usize;
+    Samples {
+        rate: samples.rate / step as u32,
+        data: samples
+            .data
+            .chunks(step)
+            .map(|group| group.iter().sum::<f32>() / group.len() as f32)
+            .collect(),
+    }
+}
+
+fn waveform(samples: &Samples) -> Vec<u8> {
+    let slice_length = ((samples.data.len() + WAVEFORM_LENGTH - 1) / WAVEFORM_LENGTH).max(1);
+    samples
+        .data
+        .chunks(slice_length)
+        .map(|slice| {
+            let peak = slice.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+            (peak.min(1.0) * 255.0) as u8
+        })
+        .collect()
+}
+
+fn wav(samples: &Samples) -> anyhow::Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: samples.rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut bytes = Cursor::new(vec![]);
+    let mut writer = hound::WavWriter::new(&mut bytes, spec)?;
+    for sample in &samples.data {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(bytes.into_inner())
+}
+
+// Checks the recording against the limits of the conversation it's stored in before uploading it,
+// as the message would be rejected otherwise
+async fn store(
+    handle: &AppHandle,
+    holochain_dir: &Path,
+    conversation_id: &str,
+    mut samples: Samples,
+) -> anyhow::Result<VoiceRecording> {
+    let limits = voice_limits(handle, conversation_id).await?;
+    // Downsampling rounds the rate down, which can take it a few samples over the duration
+    samples.data.truncate(limits.max_samples(samples.rate));
+    let bytes = wav(&samples)?;
+    let duration_ms = samples.data.len() as u64 * 1000 / samples.rate as u64;
+    if duration_ms > limits.max_duration_ms || bytes.len() > limits.max_size {
+        return Err(anyhow!(
+            "Voice messages in this conversation can be at most {} ms long and {} bytes",
+            limits.max_duration_ms,
+            limits.max_size
+        ));
+    }
+    let now = Timestamp::now();
+    let name = format!("voice-{}.wav", now.as_millis());
+    let file_type = String::from("audio/wav");
+    let storage_entry_hash = transfer::upload_bytes(
+        handle,
+        holochain_dir,
+        conversation_id,
+        &transfer::new_transfer_id(now),
+        &bytes,
+        name.clone(),
+        file_type.clone(),
+        now,
+    )
+    .await?;
+
+    Ok(VoiceRecording {
+        file: File {
+            name,
+            last_modified: now,
+            size: bytes.len(),
+            file_type,
+            storage_entry_hash,
+            thumbnail_storage_entry_hash: None,
+            content_hash: None,
+        },
+        duration_ms,
+        waveform: waveform(&samples),
+    })
+}
@@ -0,0 +1,105 @@
+import { assert, expect, test } from "vitest";
+
+import { runScenario } from '@holochain/tryorama';
+import { Record } from '@holochain/client';
+
+import { appSource, entryOf, sendMessage, storeFile, textMessage } from './common.js';
+
+const bytes = new TextEncoder().encode("Lorem ipsum dolor sit amet");
+
+test('send attachments that match their stored files', async () => {
+  await runScenario(async scenario => {
+    const [alice] = await scenario.addPlayersWithApps([appSource]);
+
+    const { file } = await storeFile(alice.cells[0], bytes);
+    const { file: image } = await storeFile(alice.cells[0], bytes, {
+      name: "lorem.png",
+      file_type: "image/png",
+    });
+    const { file: thumbnail } = await storeFile(alice.cells[0], bytes, {
+      name: "lorem.jpg",
+      file_type: "image/jpeg",
+    });
+    const { file: voice } = await storeFile(alice.cells[0], bytes, {
+      name: "voice.wav",
+      file_type: "audio/wav",
+    });
+
+    const record: Record = await sendMessage(
+      alice.cells[0],
+      textMessage("Files", {
+        attachments: [
+          { type: "Document", file },
+          {
+            type: "Image",
+            file: { ...image, thumbnail_storage_entry_hash: thumbnail.storage_entry_hash },
+            width: 1,
+            height: 1,
+          },
+          { type: "Voice", file: voice, duration_ms: 1000, waveform: [0, 128, 255] },
+        ],
+      }),
+    );
+    assert.equal(entryOf(record).attachments.length, 3);
+  });
+});
+
+test('reject attachments that do not match their stored files', async () => {
+  await runScenario(async scenario => {
+    const [alice] = await scenario.addPlayersWithApps([appSource]);
+    const { file, chunkHash } = await storeFile(alice.cells[0], bytes);
+    const send = (attachments: any[]) =>
+      sendMessage(alice.cells[0], textMessage("Files", { attachments }));
+
+    await expect(
+      send([{ type: "Document", file: { ...file, size: file.size + 1 } }]),
+    ).rejects.toThrow(/Attachment size and type must match the stored file/);
+    await expect(
+      send([{ type: "Document", file: { ...file, file_type: "application/pdf" } }]),
+    ).rejects.toThrow(/Attachment size and type must match the stored file/);
+    // A chunk is a file_storage entry, but not a file
+    await expect(
+      send([{ type: "Document", file: { ...file, storage_entry_hash: chunkHash } }]),
+    ).rejects.toThrow(/Attachments must point to a file_storage entry/);
+    await expect(
+      send([
+        {
+          type: "Image",
+          file: { ...file, thumbnail_storage_entry_hash: file.storage_entry_hash },
+          width: null,
+          height: null,
+        },
+      ]),
+    ).rejects.toThrow(/Thumbnails must point to a stored image/);
+  });
+});
+
+test('reject attachments over the limits', async () => {
+  await runScenario(async scenario => {
+    const [alice] = await scenario.addPlayersWithApps([appSource]);
+    const { file } = await storeFile(alice.cells[0], bytes);
+    const send = (attachments: any[]) =>
+      sendMessage(alice.cells[0], textMessage("Files", { attachments }));
+
+    // The default limits apply to conversations that don't set their own
+    const documents = Array.from({ length: 21 }, () => ({ type: "Document", file }));
+    await expect(send(documents)).rejects.toThrow(/Messages can have at most 20 attachments/);
+    await send(documents.slice(0, 20));
+
+    const { file: large } = await storeFile(alice.cells[0], bytes, { size: 100 * 1024 * 1024 + 1 });
+    await expect(send([{ type: "Document", file: large }])).rejects.toThrow(
+      /Attachments can be at most 104857600 bytes/,
+    );
+
+    const { file: voice } = await storeFile(alice.cells[0], bytes, { file_type: "audio/wav" });
+    await expect(
+      send([{ type: "Voice", file, duration_ms: 1000, waveform: [] }]),
+    ).rejects.toThrow(/Voice messages must be audio files/);
+    await expect(
+      send([{ type: "Voice", file: voice, duration_ms: 5 * 60 * 1000 + 1, waveform: [] }]),
+    ).rejects.toThrow(/Voice messages can be at most 300000 ms long/);
+    await expect(
+      send([{ type: "Voice", file: voice, duration_ms: 1000, waveform: new Array(129).fill(0) }]),
+    ).rejects.toThrow(/Waveforms can have at most 128 values/);
+  });
+});
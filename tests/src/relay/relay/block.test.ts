@@ -0,0 +1,109 @@
+import { assert, test } from "vitest";
+
+import { runScenario, dhtSync } from '@holochain/tryorama';
+import { ActionHash, AgentPubKey, Record } from '@holochain/client';
+
+import { appSignals, appSource, entryOf, sendMessage, textMessage, waitFor } from './common.js';
+
+test('blocking an agent hides their messages', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+    const signals = appSignals(alice);
+    const receivedMessages = (content: string) =>
+      signals.filter(signal => signal.type === "Message" && signal.message.content === content);
+
+    // Blocking twice keeps the one block
+    const blockHash: ActionHash = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "block_agent",
+      payload: bob.agentPubKey,
+    });
+    const sameBlockHash: ActionHash = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "block_agent",
+      payload: bob.agentPubKey,
+    });
+    assert.deepEqual(sameBlockHash, blockHash);
+    const blocked: AgentPubKey[] = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "get_blocked_agents",
+      payload: null,
+    });
+    assert.deepEqual(blocked, [bob.agentPubKey]);
+
+    // Signals still come through, with their sender for the UI to drop them
+    await sendMessage(bob.cells[0], textMessage("While blocked"), [alice.agentPubKey]);
+    await waitFor(() => receivedMessages("While blocked").length === 1);
+    assert.deepEqual(receivedMessages("While blocked")[0].from, bob.agentPubKey);
+    await dhtSync([alice, bob], alice.cells[0].cell_id[0]);
+
+    const getMessages = (include_blocked: boolean): Promise<any[]> =>
+      alice.cells[0].callZome({
+        zome_name: "relay",
+        fn_name: "get_messages_for_buckets",
+        payload: { buckets: [0], include_blocked },
+      });
+    assert.equal((await getMessages(false)).length, 0);
+    assert.equal((await getMessages(true)).length, 1);
+
+    // Once unblocked, Bob's messages show again
+    await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "unblock_agent",
+      payload: bob.agentPubKey,
+    });
+    await sendMessage(bob.cells[0], textMessage("After unblocking"), [alice.agentPubKey]);
+    await dhtSync([alice, bob], alice.cells[0].cell_id[0]);
+    assert.equal((await getMessages(false)).length, 2);
+  });
+});
+
+test('the inputs of earlier versions leave out blocked agents', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob, carol] = await scenario.addPlayersWithApps([appSource, appSource, appSource]);
+    await scenario.shareAllAgents();
+
+    await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "block_agent",
+      payload: bob.agentPubKey,
+    });
+    const fromBob: Record = await sendMessage(bob.cells[0], textMessage("From Bob"));
+    const fromCarol: Record = await sendMessage(carol.cells[0], textMessage("From Carol"));
+    await dhtSync([alice, bob, carol], alice.cells[0].cell_id[0]);
+    const contents = (records: Record[]) =>
+      records.map(record => entryOf(record).content).sort();
+
+    // Buckets and hashes alone, as taken before blocks were added
+    const inBuckets = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "get_messages_for_buckets",
+      payload: [0],
+    });
+    assert.deepEqual(
+      inBuckets.map((message: any) => message.message.content),
+      ["From Carol"],
+    );
+    const hashes = [fromBob, fromCarol].map(record => record.signed_action.hashed.hash);
+    const entries = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "get_message_entries",
+      payload: hashes,
+    });
+    assert.deepEqual(
+      entries.map((message: any) => message.message.content),
+      ["From Carol"],
+    );
+
+    const allEntries = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "get_message_entries",
+      payload: { hashes, include_blocked: true },
+    });
+    assert.deepEqual(
+      allEntries.map((message: any) => message.message.content).sort(),
+      contents([fromBob, fromCarol]),
+    );
+  });
+});
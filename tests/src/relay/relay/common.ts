@@ -1,5 +1,6 @@
-import { CallableCell } from '@holochain/tryorama';
-import { NewEntryAction, ActionHash, Record, AppBundleSource, fakeActionHash, fakeAgentPubKey, fakeEntryHash, fakeDnaHash } from '@holochain/client';
+import { CallableCell, pause } from '@holochain/tryorama';
+import { NewEntryAction, ActionHash, Record, AppBundleSource, fakeActionHash, fakeAgentPubKey, fakeEntryHash, fakeDnaHash, AgentPubKey, AppWebsocket, EntryHash, Signal, SignalType } from '@holochain/client';
+import { decode } from '@msgpack/msgpack';
 
 
 
@@ -39,3 +40,100 @@ export async function createMessage(cell: CallableCell, message = undefined): Pr
     });
 }
 
+
+export const appSource = { appBundleSource: { path: process.cwd() + '/../workdir/relay.happ' } };
+
+// The payloads of the app signals the player receives from now on
+export function appSignals(player: { appWs: AppWebsocket }): any[] {
+  const signals: any[] = [];
+  player.appWs.on("signal", (signal: Signal) => {
+    if (SignalType.App in signal) signals.push(signal[SignalType.App].payload);
+  });
+  return signals;
+}
+
+export function entryOf(record: Record): any {
+  return decode((record.entry as any).Present.entry);
+}
+
+export function sampleContact(publicKey: AgentPubKey, partialContact = {}) {
+  return {
+    public_key: publicKey,
+    first_name: "Lorem",
+    last_name: "Ipsum",
+    avatar: "",
+    ...partialContact,
+  };
+}
+
+export async function createContact(cell: CallableCell, contact: any): Promise<Record> {
+  return cell.callZome({
+    zome_name: "relay",
+    fn_name: "create_contact",
+    payload: contact,
+  });
+}
+
+export function textMessage(content: string, partialMessage = {}) {
+  return {
+    content,
+    bucket: 0,
+    attachments: [],
+    forwarded_from: null,
+    mentions: [],
+    link_preview: null,
+    ...partialMessage,
+  };
+}
+
+// Creates the message and signals it to the agents
+export async function sendMessage(cell: CallableCell, message: any, agents: AgentPubKey[] = []): Promise<Record> {
+  return cell.callZome({
+    zome_name: "relay",
+    fn_name: "create_message",
+    payload: { message, agents },
+  });
+}
+
+export async function waitFor(condition: () => boolean, timeout = 10_000) {
+  const start = Date.now();
+  while (!condition()) {
+    if (Date.now() - start > timeout) throw new Error("Timed out waiting for the condition");
+    await pause(100);
+  }
+}
+
+// Stores the bytes with the file_storage zome like the UI does, as a single chunk.
+// Returns the hash of the chunk and the file to attach to a message.
+export async function storeFile(cell: CallableCell, bytes: Uint8Array, partialMetadata = {}) {
+  const chunkHash: EntryHash = await cell.callZome({
+    zome_name: "file_storage",
+    fn_name: "create_file_chunk",
+    payload: bytes,
+  });
+  const metadata = {
+    name: "lorem.txt",
+    last_modified: Date.now() * 1000,
+    size: bytes.length,
+    file_type: "text/plain",
+    chunks_hashes: [chunkHash],
+    ...partialMetadata,
+  };
+  const storageEntryHash: EntryHash = await cell.callZome({
+    zome_name: "file_storage",
+    fn_name: "create_file_metadata",
+    payload: metadata,
+  });
+  return {
+    chunkHash,
+    file: {
+      name: metadata.name,
+      last_modified: metadata.last_modified,
+      size: metadata.size,
+      file_type: metadata.file_type,
+      storage_entry_hash: storageEntryHash,
+      thumbnail_storage_entry_hash: null,
+      content_hash: null,
+    },
+  };
+}
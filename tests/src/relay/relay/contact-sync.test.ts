@@ -0,0 +1,57 @@
+import { assert, test } from "vitest";
+
+import { runScenario, dhtSync } from '@holochain/tryorama';
+import { AgentPubKey } from '@holochain/client';
+
+import { appSource, createContact, sampleContact } from './common.js';
+
+test('applying profiles keeps the names and avatars they leave empty', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob, carol] = await scenario.addPlayersWithApps([appSource, appSource, appSource]);
+    await scenario.shareAllAgents();
+
+    // Like the UI writes it without a last name
+    await bob.cells[0].callZome({
+      zome_name: "profiles",
+      fn_name: "create_profile",
+      payload: { nickname: "Bob ", fields: { avatar: "", firstName: "Bob", lastName: "" } },
+    });
+    // Like other clients write it, with only a nickname
+    await carol.cells[0].callZome({
+      zome_name: "profiles",
+      fn_name: "create_profile",
+      payload: { nickname: "Carol Jones", fields: {} },
+    });
+    await createContact(
+      alice.cells[0],
+      sampleContact(bob.agentPubKey, { first_name: "Robert", last_name: "Smith", avatar: "bob.png" }),
+    );
+    await createContact(alice.cells[0], sampleContact(carol.agentPubKey));
+    await dhtSync([alice, bob, carol], alice.cells[0].cell_id[0]);
+
+    const changes: any[] = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "sync_contacts_from_profiles",
+      payload: { apply: true },
+    });
+    const changeOf = (agent: AgentPubKey) =>
+      changes.find(change => agent.toString() === change.updated_contact.public_key.toString());
+
+    const bobsContact = changeOf(bob.agentPubKey).updated_contact;
+    assert.equal(bobsContact.first_name, "Bob");
+    assert.equal(bobsContact.last_name, "Smith");
+    assert.equal(bobsContact.avatar, "bob.png");
+
+    const carolsContact = changeOf(carol.agentPubKey).updated_contact;
+    assert.equal(carolsContact.first_name, "Carol");
+    assert.equal(carolsContact.last_name, "Jones");
+
+    // Applied, so there is nothing left to change
+    const remaining: any[] = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "sync_contacts_from_profiles",
+      payload: { apply: true },
+    });
+    assert.equal(remaining.length, 0);
+  });
+});
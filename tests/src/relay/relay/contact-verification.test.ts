@@ -0,0 +1,131 @@
+import { assert, expect, test } from "vitest";
+
+import { runScenario } from '@holochain/tryorama';
+import { Record, fakeAgentPubKey } from '@holochain/client';
+
+import { appSignals, appSource, createContact, entryOf, sampleContact } from './common.js';
+
+test('safety numbers are the same for both agents and verify a contact', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+
+    const aliceNumber: string = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "get_safety_number",
+      payload: bob.agentPubKey,
+    });
+    const bobNumber: string = await bob.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "get_safety_number",
+      payload: alice.agentPubKey,
+    });
+    assert.equal(aliceNumber, bobNumber);
+    assert.match(aliceNumber, /^(\d{5} ){11}\d{5}$/);
+
+    // Alice verifies Bob after comparing the numbers
+    const contact = await createContact(alice.cells[0], sampleContact(bob.agentPubKey));
+    const contactHash = contact.signed_action.hashed.hash;
+    const verified: Record = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "verify_contact",
+      payload: { original_contact_hash: contactHash, previous_contact_hash: contactHash },
+    });
+    assert.equal(entryOf(verified).verification.safety_number, aliceNumber);
+  });
+});
+
+test('reject verifications that were not made for the contact key', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+
+    const contact = await createContact(alice.cells[0], sampleContact(bob.agentPubKey));
+    const contactHash = contact.signed_action.hashed.hash;
+    const verified: Record = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "verify_contact",
+      payload: { original_contact_hash: contactHash, previous_contact_hash: contactHash },
+    });
+    const verification = entryOf(verified).verification;
+
+    // Bob's verification copied onto a contact with another key
+    const carol = await fakeAgentPubKey();
+    await expect(
+      createContact(alice.cells[0], sampleContact(carol, { verification })),
+    ).rejects.toThrow(/Safety number does not match/);
+
+    // The right safety number for the other key, but signed for Bob's
+    const carolNumber: string = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "get_safety_number",
+      payload: carol,
+    });
+    await expect(
+      createContact(
+        alice.cells[0],
+        sampleContact(carol, {
+          verification: { safety_number: carolNumber, signature: verification.signature },
+        }),
+      ),
+    ).rejects.toThrow(/Contact verification signature is invalid/);
+  });
+});
+
+test('changing the key of a contact drops its verification and signals it', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+    const signals = appSignals(alice);
+
+    const contact = await createContact(alice.cells[0], sampleContact(bob.agentPubKey));
+    const contactHash = contact.signed_action.hashed.hash;
+    const verified: Record = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "verify_contact",
+      payload: { original_contact_hash: contactHash, previous_contact_hash: contactHash },
+    });
+
+    const newKey = await fakeAgentPubKey();
+    const updated: Record = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "update_contact",
+      payload: {
+        original_contact_hash: contactHash,
+        previous_contact_hash: verified.signed_action.hashed.hash,
+        updated_contact: { ...entryOf(verified), public_key: newKey },
+      },
+    });
+    assert.equal(entryOf(updated).verification, null);
+
+    const keyChanged = signals.find(signal => signal.type === "ContactKeyChanged");
+    assert.ok(keyChanged);
+    assert.deepEqual(keyChanged.previous_key, bob.agentPubKey);
+    assert.deepEqual(keyChanged.new_key, newKey);
+  });
+});
+
+test('contact codes only verify for the key that signed them', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+
+    const code = await bob.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "create_contact_code",
+      payload: { first_name: "Bob", last_name: "Ipsum" },
+    });
+    const verifyCode = (code: any): Promise<boolean> =>
+      alice.cells[0].callZome({
+        zome_name: "relay",
+        fn_name: "verify_contact_code",
+        payload: code,
+      });
+
+    assert.isTrue(await verifyCode(code));
+    assert.isFalse(
+      await verifyCode({ ...code, data: { ...code.data, public_key: alice.agentPubKey } }),
+    );
+    assert.isFalse(await verifyCode({ ...code, data: { ...code.data, first_name: "Mallory" } }));
+  });
+});
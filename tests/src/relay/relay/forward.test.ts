@@ -0,0 +1,112 @@
+import { assert, expect, test } from "vitest";
+
+import { runScenario, dhtSync } from '@holochain/tryorama';
+import { AppWebsocket, CellId, Record } from '@holochain/client';
+
+import { appSource, entryOf, sendMessage, textMessage } from './common.js';
+
+// Conversations are clone cells of the relay role
+async function createConversation(appWs: AppWebsocket, networkSeed: string): Promise<CellId> {
+  const cell = await appWs.createCloneCell({
+    role_name: "relay",
+    modifiers: { network_seed: networkSeed },
+  });
+  return cell.cell_id;
+}
+
+function forwardMessage(
+  appWs: AppWebsocket,
+  from: CellId,
+  originalMessageHash: Uint8Array,
+  targetCellId: CellId,
+): Promise<Record> {
+  return appWs.callZome({
+    cell_id: from,
+    zome_name: "relay",
+    fn_name: "forward_message",
+    payload: { originalMessageHash, targetCellId, bucket: 0, agents: [] },
+  });
+}
+
+// What a forwarded message carries of the record of the message it forwards
+function forwardedFrom(record: Record) {
+  return {
+    author: record.signed_action.hashed.content.author,
+    signed_action: record.signed_action,
+    entry: (record.entry as any).Present,
+  };
+}
+
+test('forwarded messages keep where they were first written', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+
+    const original: Record = await sendMessage(bob.cells[0], textMessage("Lorem ipsum"));
+    await dhtSync([alice, bob], alice.cells[0].cell_id[0]);
+
+    const first = await createConversation(alice.appWs, "first");
+    const second = await createConversation(alice.appWs, "second");
+    const forwarded = await forwardMessage(
+      alice.appWs,
+      alice.cells[0].cell_id,
+      original.signed_action.hashed.hash,
+      first,
+    );
+    assert.equal(entryOf(forwarded).content, "Lorem ipsum");
+    assert.deepEqual(entryOf(forwarded).forwarded_from.author, bob.agentPubKey);
+
+    // Forwarding it again still refers to Bob's message
+    const forwardedAgain = await forwardMessage(
+      alice.appWs,
+      first,
+      forwarded.signed_action.hashed.hash,
+      second,
+    );
+    const provenance = entryOf(forwardedAgain).forwarded_from;
+    assert.deepEqual(provenance.author, bob.agentPubKey);
+    assert.deepEqual(provenance.signed_action.hashed.hash, original.signed_action.hashed.hash);
+  });
+});
+
+test('reject forwarded messages whose provenance does not check out', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+
+    const original: Record = await sendMessage(bob.cells[0], textMessage("Lorem ipsum"));
+    const other: Record = await sendMessage(bob.cells[0], textMessage("Dolor sit amet"));
+    const provenance = forwardedFrom(original);
+    const forward = (content: string, forwarded_from: any) =>
+      sendMessage(alice.cells[0], textMessage(content, { forwarded_from }));
+
+    const forwarded = await forward("Lorem ipsum", provenance);
+    assert.ok(forwarded);
+
+    await expect(forward("Lorem ipsum, edited", provenance)).rejects.toThrow(
+      /must have the content and attachments of the original message/,
+    );
+    await expect(
+      forward("Lorem ipsum", { ...provenance, author: alice.agentPubKey }),
+    ).rejects.toThrow(/must be signed by their original author/);
+
+    const signature = new Uint8Array(provenance.signed_action.signature);
+    signature[0] ^= 1;
+    await expect(
+      forward("Lorem ipsum", {
+        ...provenance,
+        signed_action: { ...provenance.signed_action, signature },
+      }),
+    ).rejects.toThrow(/The signature of the original message is invalid/);
+
+    // Bob's signature, but over another of his messages
+    await expect(
+      forward("Dolor sit amet", { ...provenance, entry: (other.entry as any).Present }),
+    ).rejects.toThrow(/must refer to the entry of the original message/);
+
+    // Alice's forward is signed by her, but isn't where the message was first written
+    await expect(forward("Lorem ipsum", forwardedFrom(forwarded))).rejects.toThrow(
+      /must refer to the original message/,
+    );
+  });
+});
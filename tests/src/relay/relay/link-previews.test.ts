@@ -0,0 +1,28 @@
+import { assert, expect, test } from "vitest";
+
+import { runScenario } from '@holochain/tryorama';
+import { Record } from '@holochain/client';
+
+import { appSource, entryOf, sendMessage, textMessage } from './common.js';
+
+const preview = (url: string) => ({ url, title: "Lorem", description: "Ipsum", image: null });
+
+test('reject link previews of links that are not web pages', async () => {
+  await runScenario(async scenario => {
+    const [alice] = await scenario.addPlayersWithApps([appSource]);
+    const send = (content: string, url: string) =>
+      sendMessage(alice.cells[0], textMessage(content, { link_preview: preview(url) }));
+
+    const record: Record = await send("See https://example.com", "https://example.com");
+    assert.equal(entryOf(record).link_preview.url, "https://example.com");
+
+    await expect(send("See https://example.com", "https://example.org")).rejects.toThrow(
+      /Link previews must be of a link in the message/,
+    );
+    for (const url of ["javascript:alert(1)", "javascript://%0aalert(1)", "data:text/html,x"]) {
+      await expect(send(`See ${url}`, url)).rejects.toThrow(
+        /Link previews must be of an http or https link/,
+      );
+    }
+  });
+});
@@ -0,0 +1,84 @@
+import { assert, expect, test } from "vitest";
+
+import { runScenario, dhtSync } from '@holochain/tryorama';
+import { Record, fakeAgentPubKey } from '@holochain/client';
+
+import { appSignals, appSource, entryOf, sendMessage, textMessage, waitFor } from './common.js';
+
+test('mentioning a member proves they joined and notifies them', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+    await dhtSync([alice, bob], alice.cells[0].cell_id[0]);
+    const signals = appSignals(bob);
+
+    const record: Record = await sendMessage(
+      alice.cells[0],
+      textMessage("Hi @Bob", { mentions: [{ agent: bob.agentPubKey, start: 3, end: 7 }] }),
+      [bob.agentPubKey],
+    );
+    const [mention] = entryOf(record).mentions;
+    assert.deepEqual(mention.agent, bob.agentPubKey);
+    assert.ok(mention.membership);
+
+    await waitFor(() => signals.some(signal => signal.type === "Mentioned"));
+    const mentioned = signals.find(signal => signal.type === "Mentioned");
+    assert.equal(mentioned.message.content, "Hi @Bob");
+    assert.deepEqual(mentioned.from, alice.agentPubKey);
+  });
+});
+
+test('reject mentions that are not @ spans of the content', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+    await dhtSync([alice, bob], alice.cells[0].cell_id[0]);
+    const mention = (mentions: any[]) =>
+      sendMessage(alice.cells[0], textMessage("Hi @Bob @Bob", { mentions }));
+    const bobAt = (start: number, end: number) => ({ agent: bob.agentPubKey, start, end });
+
+    await mention([bobAt(3, 7), bobAt(8, 12)]);
+    await expect(mention([bobAt(2, 7)])).rejects.toThrow(
+      /Mentions must be separate @ spans of the message content/,
+    );
+    await expect(mention([bobAt(3, 12), bobAt(8, 12)])).rejects.toThrow(
+      /Mentions must be separate @ spans of the message content/,
+    );
+    // Spans must be in the order they appear in
+    await expect(mention([bobAt(8, 12), bobAt(3, 7)])).rejects.toThrow(
+      /Mentions must be separate @ spans of the message content/,
+    );
+    await expect(mention([bobAt(8, 13)])).rejects.toThrow(
+      /Mentions must be separate @ spans of the message content/,
+    );
+  });
+});
+
+test('reject mentions of agents who are not members', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+    await dhtSync([alice, bob], alice.cells[0].cell_id[0]);
+    const mention = (agent: Uint8Array, membership: Uint8Array | null = null) =>
+      sendMessage(
+        alice.cells[0],
+        textMessage("Hi @Carol", { mentions: [{ agent, start: 3, end: 9, membership }] }),
+      );
+
+    const carol = await fakeAgentPubKey();
+    await expect(mention(carol)).rejects.toThrow(/is not a member of this conversation/);
+
+    // Bob's proof doesn't prove anyone else joined
+    const record: Record = await mention(bob.agentPubKey);
+    const bobsMembership = entryOf(record).mentions[0].membership;
+    await expect(mention(carol, bobsMembership)).rejects.toThrow(
+      /Mentioned agents must be members of the conversation/,
+    );
+    // Neither does any other action of theirs
+    const bobsMessage: Record = await sendMessage(bob.cells[0], textMessage("Lorem ipsum"));
+    await dhtSync([alice, bob], alice.cells[0].cell_id[0]);
+    await expect(
+      mention(bob.agentPubKey, bobsMessage.signed_action.hashed.hash),
+    ).rejects.toThrow(/Mentioned agents must be members of the conversation/);
+  });
+});
@@ -0,0 +1,173 @@
+import { assert, expect, test } from "vitest";
+
+import { runScenario, dhtSync, CallableCell } from '@holochain/tryorama';
+import { EntryHash, Record } from '@holochain/client';
+
+import { appSource, entryOf, sendMessage, storeFile, textMessage } from './common.js';
+
+const bytes = new TextEncoder().encode("Lorem ipsum dolor sit amet");
+
+test('files no message refers to are orphaned', async () => {
+  await runScenario(async scenario => {
+    const [alice] = await scenario.addPlayersWithApps([appSource]);
+    const getOrphanedFiles = (): Promise<EntryHash[]> =>
+      alice.cells[0].callZome({
+        zome_name: "relay",
+        fn_name: "get_orphaned_files",
+        payload: null,
+      });
+
+    // A file being uploaded for a message that isn't sent yet
+    const { file } = await storeFile(alice.cells[0], bytes, { file_type: "image/png" });
+    const { file: thumbnail } = await storeFile(alice.cells[0], bytes, { file_type: "image/jpeg" });
+    const orphaned = await getOrphanedFiles();
+    assert.equal(orphaned.length, 2);
+    assert.deepInclude(orphaned, file.storage_entry_hash);
+    assert.deepInclude(orphaned, thumbnail.storage_entry_hash);
+
+    await sendMessage(
+      alice.cells[0],
+      textMessage("Image", {
+        attachments: [
+          {
+            type: "Image",
+            file: { ...file, thumbnail_storage_entry_hash: thumbnail.storage_entry_hash },
+            width: null,
+            height: null,
+          },
+        ],
+      }),
+    );
+    assert.deepEqual(await getOrphanedFiles(), []);
+  });
+});
+
+test('deleting a message deletes its files', async () => {
+  await runScenario(async scenario => {
+    const [alice] = await scenario.addPlayersWithApps([appSource]);
+    const { file } = await storeFile(alice.cells[0], bytes);
+    const record: Record = await sendMessage(
+      alice.cells[0],
+      textMessage("File", { attachments: [{ type: "Document", file }] }),
+    );
+
+    await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "delete_message",
+      payload: record.signed_action.hashed.hash,
+    });
+    // No message refers to the file anymore, so it would be orphaned had it not been deleted
+    const orphaned: EntryHash[] = await alice.cells[0].callZome({
+      zome_name: "relay",
+      fn_name: "get_orphaned_files",
+      payload: null,
+    });
+    assert.deepEqual(orphaned, []);
+  });
+});
+
+const contentHash = "ab".repeat(32);
+
+function getFileByContentHash(cell: CallableCell): Promise<any | null> {
+  return cell.callZome({
+    zome_name: "relay",
+    fn_name: "get_file_by_content_hash",
+    payload: contentHash,
+  });
+}
+
+// Stores the bytes as a new file, as the UI does when it doesn't know of a stored copy
+async function sendNewCopy(cell: CallableCell, name: string, partialMetadata = {}) {
+  const { file } = await storeFile(cell, bytes, { name, ...partialMetadata });
+  const record: Record = await sendMessage(
+    cell,
+    textMessage(name, {
+      attachments: [{ type: "Document", file: { ...file, content_hash: contentHash } }],
+    }),
+  );
+  return { file, record, sent: entryOf(record).attachments[0].file };
+}
+
+test('sending the same contents again reuses the stored file', async () => {
+  await runScenario(async scenario => {
+    const [alice] = await scenario.addPlayersWithApps([appSource]);
+
+    const first = await sendNewCopy(alice.cells[0], "first.txt");
+    assert.deepEqual(first.sent.storage_entry_hash, first.file.storage_entry_hash);
+    const second = await sendNewCopy(alice.cells[0], "second.txt");
+    assert.deepEqual(second.sent.storage_entry_hash, first.file.storage_entry_hash);
+    assert.deepEqual(
+      (await getFileByContentHash(alice.cells[0])).storage_entry_hash,
+      first.file.storage_entry_hash,
+    );
+
+    // A file of another type isn't the same file, even if it claims the same contents
+    const other = await sendNewCopy(alice.cells[0], "other.md", { file_type: "text/markdown" });
+    assert.deepEqual(other.sent.storage_entry_hash, other.file.storage_entry_hash);
+  });
+});
+
+test('files other agents linked to the same contents are not reused', async () => {
+  await runScenario(async scenario => {
+    const [alice, bob] = await scenario.addPlayersWithApps([appSource, appSource]);
+    await scenario.shareAllAgents();
+
+    const bobs = await sendNewCopy(bob.cells[0], "bob.txt");
+    await dhtSync([alice, bob], alice.cells[0].cell_id[0]);
+    assert.equal(await getFileByContentHash(alice.cells[0]), null);
+
+    const alices = await sendNewCopy(alice.cells[0], "alice.txt");
+    assert.deepEqual(alices.sent.storage_entry_hash, alices.file.storage_entry_hash);
+    assert.notDeepEqual(alices.sent.storage_entry_hash, bobs.file.storage_entry_hash);
+    await dhtSync([alice, bob], alice.cells[0].cell_id[0]);
+    assert.deepEqual(
+      (await getFileByContentHash(bob.cells[0])).storage_entry_hash,
+      bobs.file.storage_entry_hash,
+    );
+  });
+});
+
+test('a reused file is deleted with the last message that refers to it', async () => {
+  await runScenario(async scenario => {
+    const [alice] = await scenario.addPlayersWithApps([appSource]);
+    const deleteMessage = (record: Record) =>
+      alice.cells[0].callZome({
+        zome_name: "relay",
+        fn_name: "delete_message",
+        payload: record.signed_action.hashed.hash,
+      });
+
+    const first = await sendNewCopy(alice.cells[0], "first.txt");
+    const second = await sendNewCopy(alice.cells[0], "second.txt");
+
+    await deleteMessage(first.record);
+    assert.deepEqual(
+      (await getFileByContentHash(alice.cells[0])).storage_entry_hash,
+      first.file.storage_entry_hash,
+    );
+    await deleteMessage(second.record);
+    assert.equal(await getFileByContentHash(alice.cells[0]), null);
+  });
+});
+
+test('reject linking contents to what is not a stored file', async () => {
+  await runScenario(async scenario => {
+    const [alice] = await scenario.addPlayersWithApps([appSource]);
+    const { file, chunkHash } = await storeFile(alice.cells[0], bytes);
+
+    await expect(
+      sendMessage(
+        alice.cells[0],
+        textMessage("Chunk", {
+          attachments: [
+            {
+              type: "Document",
+              file: { ...file, storage_entry_hash: chunkHash, content_hash: contentHash },
+            },
+          ],
+        }),
+      ),
+    ).rejects.toThrow(/must (be linked|point) to a file_storage entry/);
+    assert.equal(await getFileByContentHash(alice.cells[0]), null);
+  });
+});
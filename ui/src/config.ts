@@ -10,3 +10,11 @@ export const TARGET_MESSAGES_COUNT = 20;
 // Minimum length of profile first name
 // This is not enforced by DNA validation, so is only softly required in the frontend.
 export const MIN_FIRST_NAME_LENGTH = 3;
+
+// Name of the clone cell linked devices use to find each other and exchange invites.
+// It is hidden from the conversation list.
+export const DEVICE_SYNC_CELL_NAME = "device-sync";
+
+// Attachment limits of conversations that don't set their own, as enforced by relay_integrity
+export const MAX_ATTACHMENT_SIZE = 100 * 1024 * 1024; // 100 MB
+export const MAX_ATTACHMENTS = 20;
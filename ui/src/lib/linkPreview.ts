@@ -0,0 +1,28 @@
+import { invoke } from "@tauri-apps/api/core";
+import type { LinkPreviewStruct } from "../types";
+
+// Punctuation that ends a sentence rather than the link before it
+const TRAILING_PUNCTUATION = /[.,;:!?)\]'"]+$/;
+
+// The first web link in a message, which is the one previewed
+export function firstLink(text: string): string | undefined {
+  return text.match(/https?:\/\/[^\s<>"]+/)?.[0].replace(TRAILING_PUNCTUATION, "");
+}
+
+// Whether a link is safe to open, as previews come from other agents
+export function isWebLink(url: string): boolean {
+  try {
+    return ["http:", "https:"].includes(new URL(url).protocol);
+  } catch {
+    return false;
+  }
+}
+
+// Fetches the page's Open Graph metadata from this device, and stores its image in the
+// conversation's cell. Null for pages that can't be previewed.
+export function fetchLinkPreview(
+  conversationId: string,
+  url: string,
+): Promise<LinkPreviewStruct | null> {
+  return invoke("fetch_link_preview", { conversationId, url });
+}
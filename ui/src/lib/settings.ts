@@ -0,0 +1,107 @@
+import { invoke } from "@tauri-apps/api/core";
+
+// Mirrors NetworkSettings in src-tauri/src/settings.rs
+export interface NetworkSettings {
+  signal_url: string;
+  bootstrap_url: string;
+  ice_urls: string[];
+}
+
+// Returns null when the built-in endpoints are in use
+export function getNetworkSettings(): Promise<NetworkSettings | null> {
+  return invoke("get_network_settings");
+}
+
+export function getDefaultNetworkSettings(): Promise<NetworkSettings> {
+  return invoke("get_default_network_settings");
+}
+
+// Saving restarts the app so the conductor is started with the new profile and endpoints.
+// Pass null as network to go back to the built-in endpoints.
+export function setNetworkSettings(
+  profile: NetworkProfile,
+  network: NetworkSettings | null,
+): Promise<void> {
+  return invoke("set_network_settings", { profile, network });
+}
+
+// Mirrors NetworkProfile in src-tauri/src/settings.rs
+export type NetworkProfile =
+  | { type: "Internet" }
+  | { type: "Mdns" }
+  | { type: "LanServer"; signal_url: string; bootstrap_url: string }
+  | { type: "LanHost"; bootstrap_port: number; signal_port: number };
+
+export interface LocalServicesUrls {
+  signal_url: string;
+  bootstrap_url: string;
+}
+
+export function getNetworkProfile(): Promise<NetworkProfile> {
+  return invoke("get_network_profile");
+}
+
+// Only set when this device hosts the bootstrap and signal server for the LAN
+export function getLocalServicesUrls(): Promise<LocalServicesUrls | null> {
+  return invoke("get_local_services_urls");
+}
+
+// Mirrors GossipArc in src-tauri/src/settings.rs
+export type GossipArc = "Full" | "Dynamic" | "Empty";
+
+export interface GossipArcSettings {
+  selected: GossipArc | null;
+  platform_default: GossipArc;
+}
+
+export interface StorageUsage {
+  gossip_arc: GossipArc;
+  bytes: number;
+}
+
+export function getGossipArcSettings(): Promise<GossipArcSettings> {
+  return invoke("get_gossip_arc_settings");
+}
+
+// Saving restarts the app so the conductor is started with the new arc.
+// Pass null to go back to the platform default.
+export function setGossipArc(gossipArc: GossipArc | null): Promise<void> {
+  return invoke("set_gossip_arc", { gossipArc });
+}
+
+export function getLinkPreviews(): Promise<boolean> {
+  return invoke("get_link_previews");
+}
+
+export function setLinkPreviews(enabled: boolean): Promise<void> {
+  return invoke("set_link_previews", { enabled });
+}
+
+export function getStorageUsage(): Promise<StorageUsage> {
+  return invoke("get_storage_usage");
+}
+
+// Re-encrypts the keystore and restarts the app. Remembering the passphrase
+// stores it in the OS keychain, which is only available on desktop.
+export function changeKeystorePassphrase(
+  oldPassphrase: string,
+  newPassphrase: string,
+  remember: boolean,
+): Promise<void> {
+  return invoke("change_keystore_passphrase", { oldPassphrase, newPassphrase, remember });
+}
+
+// Writes the keystore, conversations and contacts to `path`, encrypted with `passphrase`
+export function exportBackup(path: string, passphrase: string): Promise<void> {
+  return invoke("export_backup", { path, passphrase });
+}
+
+// The earlier version whose data was migrated into this one, while its directory is still kept
+export function getMigratedFrom(): Promise<string | null> {
+  return invoke("get_migrated_from");
+}
+
+// Deletes the data directory of the version that was migrated from
+export function confirmMigration(): Promise<void> {
+  return invoke("confirm_migration");
+}
@@ -0,0 +1,67 @@
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { writable } from "svelte/store";
+
+// Mirrors TransferProgress in src-tauri/src/transfer.rs
+export interface TransferProgress {
+  id: string;
+  conversation_id: string;
+  name: string;
+  upload: boolean;
+  done: number; // chunks
+  total: number;
+}
+
+// The progress of the transfers in progress, by id
+export const transferProgress = writable<Record<string, TransferProgress>>({});
+
+listen<TransferProgress>("transfer://progress", (event) => {
+  transferProgress.update((transfers) => {
+    const { [event.payload.id]: _, ...others } = transfers;
+    return event.payload.done < event.payload.total
+      ? { ...others, [event.payload.id]: event.payload }
+      : others;
+  });
+});
+
+// Transfer ids are hex, see src-tauri/src/transfer.rs
+export function downloadTransferId(storageEntryHash: Uint8Array): string {
+  return Array.from(storageEntryHash)
+    .map((byte) => byte.toString(16).padStart(2, "0"))
+    .join("");
+}
+
+// Stores a file in the conversation's cell and returns the base64 hash of its metadata.
+// Uploading the same file again with the same id resumes an upload that didn't finish.
+export async function uploadFile(
+  conversationId: string,
+  file: File,
+  transferId: string,
+): Promise<string> {
+  return invoke("upload_file", new Uint8Array(await file.arrayBuffer()), {
+    headers: {
+      "conversation-id": conversationId,
+      "transfer-id": transferId,
+      // Headers can only be ASCII
+      "file-name": encodeURIComponent(file.name),
+      "file-type": file.type,
+      "last-modified": String(file.lastModified),
+    },
+  });
+}
+
+export function downloadFile(
+  conversationId: string,
+  storageEntryHash: string,
+): Promise<ArrayBuffer> {
+  return invoke("download_file", { conversationId, storageEntryHash });
+}
+
+// Transfers that didn't finish, e.g. before the app was restarted
+export function getPendingTransfers(): Promise<TransferProgress[]> {
+  return invoke("get_pending_transfers");
+}
+
+export function cancelTransfer(id: string): Promise<void> {
+  return invoke("cancel_transfer", { id });
+}
@@ -9,6 +9,18 @@ import { platform } from "@tauri-apps/plugin-os";
 import { setModeCurrent } from "@skeletonlabs/skeleton";
 import { open } from "@tauri-apps/plugin-shell";
 import { writeText } from "@tauri-apps/plugin-clipboard-manager";
+import { invoke } from "@tauri-apps/api/core";
+import linkifyStr from "linkify-string";
+import { type AgentPubKeyB64, decodeHashFromBase64, encodeHashToBase64 } from "@holochain/client";
+import type {
+  Attachment,
+  AttachmentKind,
+  AttachmentStruct,
+  LinkPreview,
+  LinkPreviewStruct,
+  Mention,
+  MentionStruct,
+} from "../types";
 
 /**
  * Share text via sharesheet
@@ -91,6 +103,193 @@ export async function fileToDataUrl(file: File): Promise<string> {
   });
 }
 
+/**
+ * Kind of attachment a file is sent as, by its MIME type
+ *
+ * @param fileType
+ * @returns
+ */
+export function attachmentKind(fileType: string): AttachmentKind {
+  if (fileType.startsWith("image/")) return "Image";
+  if (fileType.startsWith("audio/")) return "Audio";
+  if (fileType.startsWith("video/")) return "Video";
+  return "Document";
+}
+
+/**
+ * Convert an attachment of a message entry to the one displayed, before its file is loaded
+ *
+ * @param attachment
+ * @returns
+ */
+export function attachmentFromStruct(attachment: AttachmentStruct): Attachment {
+  return {
+    kind: attachment.type,
+    fileType: attachment.file.file_type,
+    lastModified: attachment.file.last_modified,
+    name: attachment.file.name,
+    size: attachment.file.size,
+    storageEntryHash: attachment.file.storage_entry_hash,
+    thumbnailStorageEntryHash: attachment.file.thumbnail_storage_entry_hash ?? undefined,
+    width: attachment.width ?? undefined,
+    height: attachment.height ?? undefined,
+    durationMs: attachment.duration_ms ?? undefined,
+    waveform: attachment.waveform ?? undefined,
+    status: "loading",
+  };
+}
+
+export function linkPreviewFromStruct(preview: LinkPreviewStruct): LinkPreview {
+  return {
+    url: preview.url,
+    title: preview.title,
+    description: preview.description,
+    image: preview.image ?? undefined,
+  };
+}
+
+export function mentionFromStruct(mention: MentionStruct): Mention {
+  return { agent: encodeHashToBase64(mention.agent), start: mention.start, end: mention.end };
+}
+
+export function mentionToStruct(mention: Mention): MentionStruct {
+  return { agent: decodeHashFromBase64(mention.agent), start: mention.start, end: mention.end };
+}
+
+/**
+ * Find where the members mentioned while writing a message are in its text, as `@name`.
+ * Offsets are in characters, which is how the zome counts them.
+ *
+ * @param text
+ * @param mentioned
+ * @returns
+ */
+export function findMentions(
+  text: string,
+  mentioned: { agent: AgentPubKeyB64; name: string }[],
+): Mention[] {
+  const found: Mention[] = [];
+  for (const { agent, name } of mentioned) {
+    const needle = `@${name}`;
+    for (let i = text.indexOf(needle); i !== -1; i = text.indexOf(needle, i + needle.length)) {
+      const start = Array.from(text.slice(0, i)).length;
+      found.push({ agent, start, end: start + Array.from(needle).length });
+    }
+  }
+  found.sort((a, b) => a.start - b.start || b.end - a.end);
+  return found.filter((mention, i) => i === 0 || mention.start >= found[i - 1].end);
+}
+
+function escapeHtml(text: string): string {
+  return text
+    .replace(/&/g, "&amp;")
+    .replace(/</g, "&lt;")
+    .replace(/>/g, "&gt;")
+    .replace(/"/g, "&quot;");
+}
+
+// The Markdown that messages are shown with: `code`, **bold**, *italic* and ~~strikethrough~~
+function inlineMarkdown(html: string): string {
+  return html
+    .replace(/`([^`]+)`/g, "<code>$1</code>")
+    .replace(/\*\*([^*]+)\*\*/g, "<strong>$1</strong>")
+    .replace(/\*([^*\s][^*]*)\*/g, "<em>$1</em>")
+    .replace(/~~([^~]+)~~/g, "<del>$1</del>");
+}
+
+/**
+ * Render the Markdown content of a message as HTML, with its links and mentions.
+ * The result still needs to be sanitized.
+ *
+ * @param content
+ * @param mentions
+ * @returns
+ */
+export function renderMessageContent(content: string, mentions: Mention[]): string {
+  const format = (text: string) =>
+    inlineMarkdown(
+      linkifyStr(text, {
+        defaultProtocol: "https",
+        rel: { url: "noopener noreferrer" },
+        target: "_blank",
+      }),
+    );
+  const chars = Array.from(content);
+  let html = "";
+  let position = 0;
+  for (const mention of [...mentions].sort((a, b) => a.start - b.start)) {
+    if (mention.start < position || mention.end > chars.length) continue;
+    html += format(chars.slice(position, mention.start).join(""));
+    const name = escapeHtml(chars.slice(mention.start, mention.end).join(""));
+    html += `<span class="mention" data-agent="${mention.agent}">${name}</span>`;
+    position = mention.end;
+  }
+  return html + format(chars.slice(position).join(""));
+}
+
+/**
+ * Hex encoded SHA-256 of a file's bytes, which stored files are looked up by
+ *
+ * @param file
+ * @returns
+ */
+export async function contentHash(file: File): Promise<string> {
+  const digest = await crypto.subtle.digest("SHA-256", await file.arrayBuffer());
+  return Array.from(new Uint8Array(digest))
+    .map((byte) => byte.toString(16).padStart(2, "0"))
+    .join("");
+}
+
+/**
+ * Generate a JPEG thumbnail of an image natively, see src-tauri/src/thumbnail.rs
+ *
+ * @param file
+ * @returns
+ */
+export async function generateThumbnail(file: File): Promise<File> {
+  const bytes: ArrayBuffer = await invoke(
+    "generate_thumbnail",
+    new Uint8Array(await file.arrayBuffer()),
+  );
+  return new File([bytes], `${file.name}.thumbnail.jpg`, {
+    type: "image/jpeg",
+    lastModified: file.lastModified,
+  });
+}
+
+/**
+ * Read the dimensions of an image or video, and the duration of audio or video
+ *
+ * @param kind
+ * @param dataURL
+ * @returns
+ */
+export function mediaMetadata(
+  kind: AttachmentKind,
+  dataURL: string,
+): Promise<{ width?: number; height?: number; durationMs?: number }> {
+  return new Promise((resolve) => {
+    if (kind === "Image") {
+      const img = new Image();
+      img.onload = () => resolve({ width: img.naturalWidth, height: img.naturalHeight });
+      img.onerror = () => resolve({});
+      img.src = dataURL;
+    } else if (kind === "Audio" || kind === "Video") {
+      const media = document.createElement(kind === "Audio" ? "audio" : "video");
+      media.onloadedmetadata = () =>
+        resolve({
+          width: media instanceof HTMLVideoElement ? media.videoWidth : undefined,
+          height: media instanceof HTMLVideoElement ? media.videoHeight : undefined,
+          durationMs: isFinite(media.duration) ? Math.round(media.duration * 1000) : undefined,
+        });
+      media.onerror = () => resolve({});
+      media.src = dataURL;
+    } else {
+      resolve({});
+    }
+  });
+}
+
 function setLightDarkMode(value: boolean) {
   const elemHtmlClasses = document.documentElement.classList;
   const classDark = `dark`;
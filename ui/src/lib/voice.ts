@@ -0,0 +1,29 @@
+import { invoke } from "@tauri-apps/api/core";
+import type { FileStruct } from "../types";
+
+// Mirrors VoiceRecording in src-tauri/src/voice.rs
+export interface VoiceRecording {
+  file: FileStruct;
+  duration_ms: number;
+  waveform: number[];
+}
+
+// Android only asks for the microphone on the first attempt, which then fails with this
+export class MicrophonePermissionError extends Error {}
+
+// Resolves to the longest voice message the conversation allows in milliseconds
+export async function startVoiceRecording(conversationId: string): Promise<number> {
+  if (!(await invoke<boolean>("request_microphone_permission"))) {
+    throw new MicrophonePermissionError();
+  }
+  return invoke("start_voice_recording", { conversationId });
+}
+
+// Stores the recording in the conversation's cell, ready to be posted
+export function stopVoiceRecording(conversationId: string): Promise<VoiceRecording> {
+  return invoke("stop_voice_recording", { conversationId });
+}
+
+export function cancelVoiceRecording(): Promise<void> {
+  return invoke("cancel_voice_recording");
+}
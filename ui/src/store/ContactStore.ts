@@ -16,6 +16,7 @@ export class ContactStore {
     public originalActionHash: ActionHash | undefined,
     public publicKeyB64: AgentPubKeyB64,
     public conversationId?: string | undefined,
+    public verified: boolean = false,
   ) {
     const privateConversationId = get(
       LocalStorageStore(`contact_${publicKeyB64}_private_conversation`, conversationId),
@@ -29,6 +30,7 @@ export class ContactStore {
       originalActionHash,
       publicKeyB64,
       privateConversationId,
+      verified,
     });
   }
 
@@ -3,13 +3,13 @@ import { encode } from "@msgpack/msgpack";
 import { Base64 } from "js-base64";
 import {
   type AgentPubKey,
+  type AgentPubKeyB64,
   type CellId,
   decodeHashFromBase64,
   encodeHashToBase64,
   type ActionHashB64,
   type ActionHash,
 } from "@holochain/client";
-import { FileStorageClient } from "@holochain-open-dev/file-storage";
 import { derived, get, writable, type Writable } from "svelte/store";
 import { v4 as uuidv4 } from "uuid";
 import { t } from "$translations";
@@ -19,9 +19,13 @@ import {
   type Config,
   type Contact,
   type Conversation,
-  type Image,
+  type DeviceLink,
+  type Attachment,
+  type AttachmentStruct,
   type Invitation,
+  type LinkPreviewStruct,
   type LocalConversationData,
+  type Mention,
   type Message,
   type MessageRecord,
   Privacy,
@@ -29,10 +33,21 @@ import {
 } from "../types";
 import { MessageHistoryStore } from "./MessageHistoryStore";
 import pRetry from "p-retry";
-import { fileToDataUrl } from "$lib/utils";
+import {
+  attachmentFromStruct,
+  contentHash,
+  linkPreviewFromStruct,
+  mentionFromStruct,
+  mentionToStruct,
+  fileToDataUrl,
+  generateThumbnail,
+} from "$lib/utils";
 import toast from "svelte-french-toast";
 import { BUCKET_RANGE_MS, TARGET_MESSAGES_COUNT } from "$config";
 import { page } from "$app/stores";
+import type { VoiceRecording } from "$lib/voice";
+import { downloadFile, uploadFile } from "$lib/transfer";
+import { fetchLinkPreview, firstLink } from "$lib/linkPreview";
 
 export class ConversationStore {
   public conversation: Writable<Conversation>;
@@ -41,7 +56,6 @@ export class ConversationStore {
   public lastMessage: Writable<Message | null>;
   public localDataStore: Writable<LocalConversationData>;
   private client;
-  private fileStorageClient: FileStorageClient;
 
   constructor(
     public relayStore: RelayStore,
@@ -64,6 +78,7 @@ export class ConversationStore {
       privacy,
       progenitor,
       agentProfiles: {},
+      linkedDevices: {},
       messages,
     });
     this.localDataStore = LocalStorageStore<LocalConversationData>(`conversation_${this.data.id}`, {
@@ -73,12 +88,6 @@ export class ConversationStore {
     });
     this.lastMessage = writable(null);
     this.client = relayStore.client;
-    this.fileStorageClient = new FileStorageClient(
-      this.client.client,
-      "UNUSED ROLE NAME", // this is not used when cellId is specified, but the FileStorageClient still requires the parameter
-      "file_storage",
-      cellId,
-    );
   }
 
   async initialize() {
@@ -114,13 +123,41 @@ export class ConversationStore {
 
   async fetchAgents() {
     const agentProfiles = await this.client.getAllAgents(this.data.id);
+    const linkedDevices = await this.fetchLinkedDevices();
     this.conversation.update((c) => {
       c.agentProfiles = { ...agentProfiles };
+      c.linkedDevices = linkedDevices;
       return c;
     });
     return agentProfiles;
   }
 
+  // Groups the devices of each member that linked them, following links between devices
+  async fetchLinkedDevices() {
+    const linkedDevices: { [key: AgentPubKeyB64]: AgentPubKeyB64[] } = {};
+    try {
+      const deviceLinks = await this.client.getAllDeviceLinks(this.data.id);
+      const pairs = deviceLinks.map((l) => [
+        encodeHashToBase64(l.device),
+        encodeHashToBase64(l.linked_to),
+      ]);
+      for (const [device] of pairs.concat(pairs.map(([a, b]) => [b, a]))) {
+        if (linkedDevices[device]) continue;
+        const devices = [device];
+        for (let i = 0; i < devices.length; i++) {
+          for (const [a, b] of pairs) {
+            if (a === devices[i] && !devices.includes(b)) devices.push(b);
+            if (b === devices[i] && !devices.includes(a)) devices.push(a);
+          }
+        }
+        devices.forEach((d) => (linkedDevices[d] = devices.filter((other) => other !== d)));
+      }
+    } catch (e) {
+      console.error("Error getting device links", e);
+    }
+    return linkedDevices;
+  }
+
   subscribe(run: any) {
     return this.conversation.subscribe(run);
   }
@@ -146,6 +183,24 @@ export class ConversationStore {
     }
   }
 
+  // Invite code for another device of this user, with a membrane proof backed by the device link
+  async inviteCodeForLinkedDevice(deviceLink: DeviceLink) {
+    if (this.data.privacy === Privacy.Public) {
+      return this.publicInviteCode;
+    }
+    const proof = await this.client.generateLinkedDeviceMembraneProof(this.data.id, deviceLink);
+    const invitation: Invitation = {
+      created: this.created,
+      progenitor: this.data.progenitor,
+      privacy: this.data.privacy,
+      proof,
+      networkSeed: this.data.id,
+      title: this.data.config.title,
+    };
+    const msgpck = encode(invitation);
+    return Base64.fromUint8Array(msgpck);
+  }
+
   async inviteCodeForAgent(publicKeyB64: string) {
     if (this.data.privacy === Privacy.Public) {
       return this.publicInviteCode;
@@ -192,6 +247,16 @@ export class ConversationStore {
     );
   }
 
+  // Whether two keys are devices of the same user
+  sameUser(a: AgentPubKeyB64, b: AgentPubKeyB64) {
+    return a === b || (this.data.linkedDevices[a] || []).includes(b);
+  }
+
+  // The contact for an agent, which may have been added with the key of another of their devices
+  contactFor(agentKey: AgentPubKeyB64, contacts = get(this.relayStore.contacts)) {
+    return contacts.find((contact) => this.sameUser(contact.publicKeyB64, agentKey));
+  }
+
   get archived() {
     return get(this.localDataStore).archived;
   }
@@ -219,13 +284,15 @@ export class ConversationStore {
     );
 
     // Filter out progenitor, as they are always in the list,
+    // list each user once even if they joined from several linked devices,
     // use contact data for each agent if it exists locally, otherwise use their profile
     // sort by first name (for now)
     return keys
-      .filter((k) => k !== this.client.myPubKeyB64)
+      .filter((k) => !this.sameUser(k, this.client.myPubKeyB64))
+      .filter((k, i) => !keys.slice(0, i).some((earlier) => this.sameUser(earlier, k)))
       .map((agentKey) => {
         const agentProfile = joinedAgents[agentKey];
-        const contactProfile = contacts.find((contact) => contact.publicKeyB64 === agentKey);
+        const contactProfile = this.contactFor(agentKey, contacts);
 
         return {
           publicKeyB64: agentKey,
@@ -243,7 +310,7 @@ export class ConversationStore {
     const joinedAgents = this.data.agentProfiles;
     const contacts = get(this.relayStore.contacts);
     return this.invitedContactKeys
-      .filter((contactKey) => !joinedAgents[contactKey]) // filter out already joined agents
+      .filter((contactKey) => !Object.keys(joinedAgents).some((k) => this.sameUser(k, contactKey))) // filter out already joined agents
       .map((contactKey) => {
         const contactProfile = contacts.find((contact) => contact.publicKeyB64 === contactKey);
 
@@ -335,18 +402,20 @@ export class ConversationStore {
               message.authorKey = encodeHashToBase64(
                 messageRecord.signed_action.hashed.content.author,
               );
-              message.images = ((message.images as any[]) || []).map((i) => ({
-                fileType: i.file_type,
-                lastModified: i.last_modified,
-                name: i.name,
-                size: i.size,
-                storageEntryHash: i.storage_entry_hash,
-                status: "loading",
-              }));
+              message.attachments = ((message.attachments as any[]) || []).map(
+                attachmentFromStruct,
+              );
+              message.mentions = ((message.mentions as any[]) || []).map(mentionFromStruct);
+              const linkPreview = (message as any).link_preview;
+              message.linkPreview = linkPreview ? linkPreviewFromStruct(linkPreview) : undefined;
+              const forwardedFrom = (message as any).forwarded_from;
+              if (forwardedFrom) {
+                message.forwardedFrom = encodeHashToBase64(forwardedFrom.author);
+              }
               message.status = "confirmed";
 
-              // Async load the images
-              this.loadImagesForMessage(message);
+              // Async load the attachments
+              this.loadAttachmentsForMessage(message);
 
               if (!newMessages[message.hash]) {
                 const matchesPending = Object.values(this.data.messages).find(
@@ -400,11 +469,20 @@ export class ConversationStore {
 
   /***** Setters & actions ******/
 
-  async sendMessage(authorKey: string, content: string, images: Image[]) {
+  async sendMessage(
+    authorKey: string,
+    content: string,
+    attachments: Attachment[],
+    mentions: Mention[] = [],
+  ) {
     // Use temporary uuid as the hash until we get the real one back from the network
     const now = new Date();
     const bucket = this.bucketFromDate(now);
     const id = uuidv4();
+    // The uploads are identified by the contents, so sending the same file again resumes them
+    const contentHashes = await Promise.all(
+      attachments.map((attachment) => (attachment.file ? contentHash(attachment.file) : "")),
+    );
     const oldMessage: Message = {
       authorKey,
       content,
@@ -412,42 +490,168 @@ export class ConversationStore {
       status: "pending",
       timestamp: now,
       bucket,
-      images,
+      attachments: attachments.map((a, i) => ({ ...a, transferId: contentHashes[i] })),
+      mentions,
     };
     this.addMessage(oldMessage);
-    const imageStructs = await Promise.all(
-      images
-        .filter((i) => !!i.file)
-        .map(async (image) => {
-          const hash = await this.fileStorageClient.uploadFile(image.file!);
+    const attachmentStructs: AttachmentStruct[] = await Promise.all(
+      attachments
+        .map((attachment, i) => ({ attachment, fileContentHash: contentHashes[i] }))
+        .filter(({ attachment }) => !!attachment.file)
+        .map(async ({ attachment, fileContentHash }) => {
+          const hash = await this.uploadFile(attachment.file!, fileContentHash);
+          const thumbnailHash =
+            attachment.kind === "Image" ? await this.uploadThumbnail(attachment.file!) : null;
           return {
-            last_modified: image.file!.lastModified,
-            name: image.file!.name,
-            size: image.file!.size,
-            storage_entry_hash: hash,
-            file_type: image.file!.type,
+            type: attachment.kind,
+            file: {
+              last_modified: attachment.file!.lastModified,
+              name: attachment.file!.name,
+              size: attachment.file!.size,
+              storage_entry_hash: hash,
+              file_type: attachment.file!.type,
+              thumbnail_storage_entry_hash: thumbnailHash,
+              content_hash: fileContentHash,
+            },
+            ...this.attachmentMetadata(attachment),
           };
         }),
     );
+    const linkPreview = await this.previewLink(content);
     const newMessageEntry = await this.client.sendMessage(
       this.data.id,
       content,
       bucket,
-      imageStructs,
+      attachmentStructs,
       Object.keys(this.data.agentProfiles).map((k) => decodeHashFromBase64(k)),
+      mentions.map(mentionToStruct),
+      linkPreview,
     );
     const newMessage: Message = {
       ...oldMessage,
       hash: encodeHashToBase64(newMessageEntry.actionHash),
       status: "confirmed",
-      images: images.map((i) => ({ ...i, status: "loaded" })),
+      attachments: attachments.map((a) => ({ ...a, status: "loaded" })),
+      linkPreview: linkPreview ? linkPreviewFromStruct(linkPreview) : undefined,
     };
     this.updateMessage(oldMessage, newMessage);
+    this.loadLinkPreviewImage(newMessage);
+  }
+
+  // Messages are sent without a preview when previews are turned off in the settings, which
+  // fetch_link_preview checks, or when the link can't be fetched
+  async previewLink(content: string): Promise<LinkPreviewStruct | null> {
+    const url = firstLink(content);
+    if (!url) return null;
+    try {
+      return await fetchLinkPreview(this.data.id, url);
+    } catch (e) {
+      console.error("Error fetching link preview:", e);
+      return null;
+    }
+  }
+
+  // The voice message's file is already stored by the recorder
+  async sendVoiceMessage(authorKey: string, recording: VoiceRecording) {
+    const now = new Date();
+    const bucket = this.bucketFromDate(now);
+    const attachment: Attachment = {
+      kind: "Voice",
+      fileType: recording.file.file_type,
+      name: recording.file.name,
+      lastModified: recording.file.last_modified,
+      size: recording.file.size,
+      storageEntryHash: recording.file.storage_entry_hash,
+      durationMs: recording.duration_ms,
+      waveform: recording.waveform,
+      status: "loading",
+    };
+    const oldMessage: Message = {
+      authorKey,
+      content: "",
+      hash: uuidv4(),
+      status: "pending",
+      timestamp: now,
+      bucket,
+      attachments: [attachment],
+    };
+    this.addMessage(oldMessage);
+    const newMessageEntry = await this.client.sendVoiceMessage(
+      this.data.id,
+      bucket,
+      recording,
+      Object.keys(this.data.agentProfiles).map((k) => decodeHashFromBase64(k)),
+    );
+    const newMessage: Message = {
+      ...oldMessage,
+      hash: encodeHashToBase64(newMessageEntry.actionHash),
+      status: "confirmed",
+    };
+    this.updateMessage(oldMessage, newMessage);
+    await this.loadAttachmentsForMessage(newMessage);
+  }
+
+  // Files this agent stored in this conversation already are reused instead of uploaded again
+  async uploadFile(file: File, fileContentHash: string): Promise<Uint8Array> {
+    const existing = await this.client.getFileByContentHash(this.data.id, fileContentHash);
+    if (existing && existing.size === file.size && existing.file_type === file.type) {
+      return existing.storage_entry_hash;
+    }
+    return decodeHashFromBase64(await uploadFile(this.data.id, file, fileContentHash));
+  }
+
+  // Copies a message with its attachments into another conversation
+  async forwardMessage(message: Message, target: ConversationStore) {
+    const bucket = target.currentBucket();
+    await this.client.forwardMessage(
+      this.data.id,
+      decodeHashFromBase64(message.hash),
+      target.data.id,
+      bucket,
+      Object.keys(target.data.agentProfiles).map((k) => decodeHashFromBase64(k)),
+    );
+    await target.getMessagesForBucket(bucket);
+  }
+
+  // Images the thumbnail can't be generated for are sent without one
+  async uploadThumbnail(file: File): Promise<Uint8Array | null> {
+    try {
+      const thumbnail = await generateThumbnail(file);
+      return decodeHashFromBase64(
+        await uploadFile(this.data.id, thumbnail, await contentHash(thumbnail)),
+      );
+    } catch (e) {
+      console.error("Error generating thumbnail:", e);
+      return null;
+    }
+  }
+
+  // The fields of an attachment entry that depend on its kind
+  attachmentMetadata(attachment: Attachment) {
+    switch (attachment.kind) {
+      case "Image":
+        return {
+          width: attachment.width ?? null,
+          height: attachment.height ?? null,
+        };
+      case "Audio":
+        return { duration_ms: attachment.durationMs ?? null };
+      case "Voice":
+        return { duration_ms: attachment.durationMs ?? 0, waveform: attachment.waveform ?? [] };
+      case "Video":
+        return {
+          width: attachment.width ?? null,
+          height: attachment.height ?? null,
+          duration_ms: attachment.durationMs ?? null,
+        };
+      default:
+        return {};
+    }
   }
 
   addMessage(message: Message): void {
     this.conversation.update((conversation) => {
-      message.images = message.images || [];
+      message.attachments = message.attachments || [];
       const lastMessage = get(this.lastMessage);
       if (!lastMessage || message.timestamp > lastMessage.timestamp) {
         this.lastMessage.set(message);
@@ -473,45 +677,86 @@ export class ConversationStore {
     this.history.add(newMessage);
   }
 
-  async loadImagesForMessage(message: Message) {
-    if (message.images?.length === 0) return;
+  async loadAttachmentsForMessage(message: Message) {
+    this.loadLinkPreviewImage(message);
+    if (message.attachments?.length === 0) return;
 
-    const images = await Promise.all(message.images.map((image) => this.loadImage(image)));
+    const attachments = await Promise.all(
+      message.attachments.map((attachment) => this.loadAttachment(attachment)),
+    );
     this.conversation.update((conversation) => {
-      conversation.messages[message.hash].images = images;
+      conversation.messages[message.hash].attachments = attachments;
       return conversation;
     });
   }
 
-  async loadImage(image: Image): Promise<Image> {
+  async loadAttachment(attachment: Attachment): Promise<Attachment> {
     try {
-      if (image.status === "loaded") return image;
-      if (image.storageEntryHash === undefined) return image;
-
-      // Download image file, retrying up to 10 times if download fails
-      const file = await pRetry(
-        () => this.fileStorageClient.downloadFile(image.storageEntryHash as Uint8Array),
-        {
-          retries: 10,
-          minTimeout: 1000,
-          factor: 2,
-          onFailedAttempt: (e) => {
-            console.error(
-              `Failed to download file from hash ${encodeHashToBase64(image.storageEntryHash as Uint8Array)}`,
-              e,
-            );
-          },
-        },
-      );
+      if (attachment.status === "loaded" || attachment.status === "preview") return attachment;
+      if (attachment.storageEntryHash === undefined) return attachment;
+
+      // Only the thumbnail of an image is loaded, the original when it is opened
+      if (attachment.thumbnailStorageEntryHash) {
+        const thumbnailDataURL = await this.downloadDataURL(
+          attachment.thumbnailStorageEntryHash,
+          "image/jpeg",
+        );
+        return { ...attachment, status: "preview", thumbnailDataURL } as Attachment;
+      }
 
-      // Convert image blob to data url
-      const dataURL = await fileToDataUrl(file);
+      const dataURL = await this.downloadDataURL(attachment.storageEntryHash, attachment.fileType);
+      return { ...attachment, status: "loaded", dataURL } as Attachment;
+    } catch (e) {
+      console.error("Error loading attachment after 10 retries:", e);
+      return { ...attachment, status: "error", dataURL: "" } as Attachment;
+    }
+  }
 
-      return { ...image, status: "loaded", dataURL } as Image;
+  async loadLinkPreviewImage(message: Message) {
+    const image = message.linkPreview?.image;
+    if (!image || message.linkPreview?.imageDataURL) return;
+    try {
+      const imageDataURL = await this.downloadDataURL(image.storage_entry_hash, image.file_type);
+      this.conversation.update((conversation) => {
+        const loaded = conversation.messages[message.hash];
+        if (loaded?.linkPreview) loaded.linkPreview = { ...loaded.linkPreview, imageDataURL };
+        return conversation;
+      });
     } catch (e) {
-      console.error("Error loading image after 10 retries:", e);
-      return { ...image, status: "error", dataURL: "" } as Image;
+      console.error("Error loading link preview image:", e);
+    }
+  }
+
+  // Fetch the original of an attachment that is only shown as its thumbnail
+  async loadFullAttachment(messageHash: string, index: number): Promise<Attachment> {
+    const attachment = this.data.messages[messageHash].attachments[index];
+    if (attachment.status !== "preview" || attachment.storageEntryHash === undefined) {
+      return attachment;
     }
+
+    const dataURL = await this.downloadDataURL(attachment.storageEntryHash, attachment.fileType);
+    const loaded = { ...attachment, status: "loaded", dataURL } as Attachment;
+    this.conversation.update((conversation) => {
+      conversation.messages[messageHash].attachments[index] = loaded;
+      return conversation;
+    });
+    return loaded;
+  }
+
+  async downloadDataURL(hash: Uint8Array, fileType: string): Promise<string> {
+    // Download the file, retrying up to 10 times if download fails.
+    // The chunks downloaded by a failed attempt are kept, so the next one only fetches the rest.
+    const bytes = await pRetry(() => downloadFile(this.data.id, encodeHashToBase64(hash)), {
+      retries: 10,
+      minTimeout: 1000,
+      factor: 2,
+      onFailedAttempt: (e) => {
+        console.error(`Failed to download file from hash ${encodeHashToBase64(hash)}`, e);
+      },
+    });
+
+    // Convert file blob to data url
+    return fileToDataUrl(new File([bytes], encodeHashToBase64(hash), { type: fileType }));
   }
 
   async updateConfig(config: Config) {
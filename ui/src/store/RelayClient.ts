@@ -10,26 +10,41 @@ import {
   type MembraneProof,
   type AgentPubKeyB64,
   type ActionHash,
+  type ClonedCell,
 } from "@holochain/client";
 import { decode } from "@msgpack/msgpack";
 import { EntryRecord } from "@holochain-open-dev/utils";
 import type { Profile, ProfilesStore } from "@holochain-open-dev/profiles";
 import { get } from "svelte/store";
+import { DEVICE_SYNC_CELL_NAME } from "$config";
 import type {
   Config,
   Contact,
+  ContactCode,
+  ContactGroup,
+  ContactProfileChange,
   ConversationCellAndConfig,
-  ImageStruct,
+  ConversationInvite,
+  DeviceLink,
+  DeviceLinkRequestRecord,
+  FileStruct,
+  AttachmentStruct,
+  LinkPreviewStruct,
+  MentionStruct,
   Invitation,
   MembraneProofData,
   Message,
   MessageRecord,
-  Privacy,
+  Properties,
 } from "../types";
+import { Privacy } from "../types";
+import type { VoiceRecording } from "$lib/voice";
 
 export class RelayClient {
   // conversations is a map of string to ClonedCell
   conversations: { [key: string]: ConversationCellAndConfig } = {};
+  // the cell shared with this user's other devices, if any are linked
+  deviceSyncCell: ClonedCell | null = null;
   myPubKeyB64: AgentPubKeyB64;
 
   constructor(
@@ -88,6 +103,11 @@ export class RelayClient {
         // @ts-ignore
         const cell = c[CellType.Cloned];
 
+        if (cell.name === DEVICE_SYNC_CELL_NAME) {
+          this.deviceSyncCell = cell;
+          continue;
+        }
+
         try {
           const configRecord = await this._getConfig(cell.cell_id);
 
@@ -174,12 +194,13 @@ export class RelayClient {
   public async getAllMessages(
     conversationId: string,
     buckets: Array<number>,
+    includeBlocked = false,
   ): Promise<Array<MessageRecord>> {
     return this.client.callZome({
       cell_id: this.conversations[conversationId].cell.cell_id,
       zome_name: this.zomeName,
       fn_name: "get_messages_for_buckets",
-      payload: buckets,
+      payload: { buckets, include_blocked: includeBlocked },
     });
   }
 
@@ -199,12 +220,13 @@ export class RelayClient {
   public async getMessageEntries(
     conversationId: string,
     hashes: Array<ActionHash>,
+    includeBlocked = false,
   ): Promise<Array<MessageRecord>> {
     return this.client.callZome({
       cell_id: this.conversations[conversationId].cell.cell_id,
       zome_name: this.zomeName,
       fn_name: "get_message_entries",
-      payload: hashes,
+      payload: { hashes, include_blocked: includeBlocked },
     });
   }
 
@@ -265,15 +287,72 @@ export class RelayClient {
     conversationId: string,
     content: string,
     bucket: number,
-    images: ImageStruct[],
+    attachments: AttachmentStruct[],
     agents: AgentPubKey[],
+    mentions: MentionStruct[] = [],
+    linkPreview: LinkPreviewStruct | null = null,
   ): Promise<EntryRecord<Message>> {
     const message = await this.client.callZome({
       cell_id: this.conversations[conversationId].cell.cell_id,
       zome_name: this.zomeName,
       fn_name: "create_message",
       payload: {
-        message: { content, bucket, images },
+        message: { content, bucket, attachments, mentions, link_preview: linkPreview },
+        agents,
+      },
+    });
+    return new EntryRecord(message);
+  }
+
+  public async sendVoiceMessage(
+    conversationId: string,
+    bucket: number,
+    recording: VoiceRecording,
+    agents: AgentPubKey[],
+  ): Promise<EntryRecord<Message>> {
+    const message = await this.client.callZome({
+      cell_id: this.conversations[conversationId].cell.cell_id,
+      zome_name: this.zomeName,
+      fn_name: "create_voice_message",
+      payload: {
+        bucket,
+        file: recording.file,
+        durationMs: recording.duration_ms,
+        waveform: recording.waveform,
+        agents,
+      },
+    });
+    return new EntryRecord(message);
+  }
+
+  // A file with the same contents stored in the conversation already, see `contentHash`
+  public async getFileByContentHash(
+    conversationId: string,
+    contentHash: string,
+  ): Promise<FileStruct | null> {
+    return this.client.callZome({
+      cell_id: this.conversations[conversationId].cell.cell_id,
+      zome_name: this.zomeName,
+      fn_name: "get_file_by_content_hash",
+      payload: contentHash,
+    });
+  }
+
+  public async forwardMessage(
+    conversationId: string,
+    originalMessageHash: ActionHash,
+    targetConversationId: string,
+    bucket: number,
+    agents: AgentPubKey[],
+  ): Promise<EntryRecord<Message>> {
+    const message = await this.client.callZome({
+      cell_id: this.conversations[conversationId].cell.cell_id,
+      zome_name: this.zomeName,
+      fn_name: "forward_message",
+      payload: {
+        originalMessageHash,
+        targetCellId: this.conversations[targetConversationId].cell.cell_id,
+        bucket,
         agents,
       },
     });
@@ -332,6 +411,16 @@ export class RelayClient {
     });
   }
 
+  // Moves contacts published by earlier versions into private entries
+  public async migratePublicContacts(): Promise<number> {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "migrate_public_contacts",
+      payload: null,
+    });
+  }
+
   public async createContact(contact: Contact) {
     return this.client.callZome({
       role_name: this.roleName,
@@ -363,4 +452,266 @@ export class RelayClient {
       },
     });
   }
+
+  public async verifyContact(contact: Contact) {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "verify_contact",
+      payload: {
+        original_contact_hash: contact.originalActionHash,
+        previous_contact_hash: contact.currentActionHash,
+      },
+    });
+  }
+
+  public async getSafetyNumber(publicKeyB64: AgentPubKeyB64): Promise<string> {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "get_safety_number",
+      payload: decodeHashFromBase64(publicKeyB64),
+    });
+  }
+
+  public async createContactCode(firstName: string, lastName: string): Promise<ContactCode> {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "create_contact_code",
+      payload: { first_name: firstName, last_name: lastName },
+    });
+  }
+
+  public async verifyContactCode(code: ContactCode): Promise<boolean> {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "verify_contact_code",
+      payload: code,
+    });
+  }
+
+  public async syncContactsFromProfiles(apply: boolean): Promise<ContactProfileChange[]> {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "sync_contacts_from_profiles",
+      payload: { apply },
+    });
+  }
+
+  public async exportContactsVcard(): Promise<string> {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "export_contacts_vcard",
+      payload: null,
+    });
+  }
+
+  public async importContactsVcard(
+    vcard: string,
+  ): Promise<{ imported: number; skipped: number }> {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "import_contacts_vcard",
+      payload: vcard,
+    });
+  }
+
+  /********* Blocked agents **********/
+
+  public async blockAgent(publicKeyB64: AgentPubKeyB64) {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "block_agent",
+      payload: decodeHashFromBase64(publicKeyB64),
+    });
+  }
+
+  public async unblockAgent(publicKeyB64: AgentPubKeyB64) {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "unblock_agent",
+      payload: decodeHashFromBase64(publicKeyB64),
+    });
+  }
+
+  public async getBlockedAgents(): Promise<AgentPubKey[]> {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "get_blocked_agents",
+      payload: null,
+    });
+  }
+
+  /********* Contact groups **********/
+
+  public async getAllContactGroups() {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "get_all_contact_groups",
+      payload: null,
+    });
+  }
+
+  public async createContactGroup(name: string, members: ActionHash[]) {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "create_contact_group",
+      payload: { name, members },
+    });
+  }
+
+  public async updateContactGroup(group: ContactGroup) {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "update_contact_group",
+      payload: {
+        previous_group_hash: group.currentActionHash,
+        updated_group: { name: group.name, members: group.members },
+      },
+    });
+  }
+
+  public async deleteContactGroup(group: ContactGroup) {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "delete_contact_group",
+      payload: group.originalActionHash,
+    });
+  }
+
+  public async getContactsInGroup(group: ContactGroup) {
+    return this.client.callZome({
+      role_name: this.roleName,
+      zome_name: this.zomeName,
+      fn_name: "get_contacts_in_group",
+      payload: group.originalActionHash,
+    });
+  }
+
+  /********* Linked devices **********/
+
+  // Invitation for another device of this user to join the device sync cell, created on first use
+  async deviceSyncInvitation(): Promise<Invitation> {
+    if (!this.deviceSyncCell) {
+      this.deviceSyncCell = await this.client.createCloneCell({
+        role_name: this.roleName,
+        name: DEVICE_SYNC_CELL_NAME,
+        modifiers: {
+          network_seed: uuidv4(),
+          properties: {
+            created: new Date().getTime(),
+            privacy: Privacy.Public,
+            progenitor: this.myPubKeyB64,
+          },
+        },
+      });
+    }
+    const properties = decode(this.deviceSyncCell.dna_modifiers.properties) as Properties;
+    return {
+      created: properties.created,
+      networkSeed: this.deviceSyncCell.dna_modifiers.network_seed,
+      privacy: Privacy.Public,
+      progenitor: decodeHashFromBase64(properties.progenitor),
+      title: DEVICE_SYNC_CELL_NAME,
+    };
+  }
+
+  // Joins the device sync cell of an existing device and asks it to link to this one
+  async requestDeviceLink(invitation: Invitation) {
+    if (!this.deviceSyncCell) {
+      this.deviceSyncCell = await this.client.createCloneCell({
+        role_name: this.roleName,
+        name: DEVICE_SYNC_CELL_NAME,
+        modifiers: {
+          network_seed: invitation.networkSeed,
+          properties: {
+            created: invitation.created,
+            privacy: invitation.privacy,
+            progenitor: encodeHashToBase64(invitation.progenitor),
+          },
+        },
+      });
+    }
+    return this._callDeviceSync("request_device_link", invitation.progenitor);
+  }
+
+  async getDeviceLinkRequests(): Promise<DeviceLinkRequestRecord[]> {
+    if (!this.deviceSyncCell) return [];
+    return this._callDeviceSync("get_device_link_requests", null);
+  }
+
+  async acceptDeviceLink(requestHash: ActionHash): Promise<DeviceLink> {
+    return this._callDeviceSync("accept_device_link", requestHash);
+  }
+
+  async getMyDeviceLinks(): Promise<DeviceLink[]> {
+    if (!this.deviceSyncCell) return [];
+    return this._callDeviceSync("get_my_device_links", null);
+  }
+
+  async getLinkedDevices(agent: AgentPubKey): Promise<AgentPubKey[]> {
+    if (!this.deviceSyncCell) return [];
+    return this._callDeviceSync("get_linked_devices", agent);
+  }
+
+  async inviteLinkedDevice(invite: ConversationInvite): Promise<ActionHash> {
+    return this._callDeviceSync("invite_linked_device", invite);
+  }
+
+  async getConversationInvites(forDevice: AgentPubKey): Promise<ConversationInvite[]> {
+    if (!this.deviceSyncCell) return [];
+    return this._callDeviceSync("get_conversation_invites", forDevice);
+  }
+
+  async _callDeviceSync(fnName: string, payload: any) {
+    return this.client.callZome({
+      cell_id: this.deviceSyncCell!.cell_id,
+      zome_name: this.zomeName,
+      fn_name: fnName,
+      payload,
+    });
+  }
+
+  // Makes a device link known to the other members of a conversation
+  async publishDeviceLink(conversationId: string, deviceLink: DeviceLink) {
+    return this.client.callZome({
+      cell_id: this.conversations[conversationId].cell.cell_id,
+      zome_name: this.zomeName,
+      fn_name: "publish_device_link",
+      payload: deviceLink,
+    });
+  }
+
+  async getAllDeviceLinks(conversationId: string): Promise<DeviceLink[]> {
+    return this.client.callZome({
+      cell_id: this.conversations[conversationId].cell.cell_id,
+      zome_name: this.zomeName,
+      fn_name: "get_all_device_links",
+      payload: null,
+    });
+  }
+
+  async generateLinkedDeviceMembraneProof(
+    conversationId: string,
+    deviceLink: DeviceLink,
+  ): Promise<MembraneProof> {
+    return this.client.callZome({
+      cell_id: this.conversations[conversationId].cell.cell_id,
+      zome_name: this.zomeName,
+      fn_name: "generate_linked_device_membrane_proof",
+      payload: deviceLink,
+    });
+  }
 }
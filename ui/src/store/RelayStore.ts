@@ -1,5 +1,6 @@
 import { decode } from "@msgpack/msgpack";
-import { isEqual, camelCase, mapKeys } from "lodash-es";
+import { Base64 } from "js-base64";
+import { isEqual } from "lodash-es";
 import { writable, get, type Writable } from "svelte/store";
 import {
   type AgentPubKey,
@@ -11,27 +12,75 @@ import {
   SignalType,
 } from "@holochain/client";
 import { ContactStore } from "./ContactStore";
+import LocalStorageStore from "$store/LocalStorageStore";
 import { ConversationStore } from "./ConversationStore";
 import { RelayClient } from "$store/RelayClient";
 import type {
   Contact,
-  Image,
+  ContactGroup,
+  ContactProfileChange,
+  ContactProfileSync,
   ConversationCellAndConfig,
+  DeviceLink,
   Invitation,
   Message,
   Properties,
   RelaySignal,
 } from "../types";
 import { Privacy } from "../types";
-import { enqueueNotification, isMobile } from "$lib/utils";
+import {
+  attachmentFromStruct,
+  enqueueNotification,
+  isMobile,
+  linkPreviewFromStruct,
+  mentionFromStruct,
+} from "$lib/utils";
+import { t } from "$translations";
+import toast from "svelte-french-toast";
+
+// Silly thing to get around typescript issues with sveltekit-i18n
+const tAny = t as any;
+
+// Links in messages being sent are previewed unless turned off in Settings,
+// as it lets the linked site know this device's address
+
+// Off unless chosen in Settings, since it overrides names given to contacts
+export const contactProfileSync = LocalStorageStore<ContactProfileSync>(
+  "contact_profile_sync",
+  "off",
+);
+
+// Conversations each device link was published in and its other device invited to,
+// so they are only synced again for new conversations and links
+const syncedDeviceLinks = LocalStorageStore<string[]>("synced_device_links", []);
+
+function deviceLinkKey(conversationId: string, deviceLink: DeviceLink) {
+  return [
+    conversationId,
+    encodeHashToBase64(deviceLink.device),
+    encodeHashToBase64(deviceLink.linked_to),
+  ].join("/");
+}
 
 export class RelayStore {
   public contacts: Writable<ContactStore[]>;
   public conversations: Writable<ConversationStore[]>;
+  public contactGroups: Writable<ContactGroup[]>;
+  public blockedAgents: Writable<AgentPubKeyB64[]>;
+  // Contacts whose profile changed, when they are only proposed to be updated
+  public profileChanges: Writable<ContactProfileChange[]>;
+  // this user's other devices
+  public linkedDevices: AgentPubKeyB64[] = [];
+  private syncingDevices = false;
+  // Set when a sync is requested while one is running, which then runs again
+  private deviceSyncPending = false;
 
   constructor(public client: RelayClient) {
     this.contacts = writable([]);
     this.conversations = writable([]);
+    this.contactGroups = writable([]);
+    this.blockedAgents = writable([]);
+    this.profileChanges = writable([]);
   }
 
   get contactData() {
@@ -49,7 +98,16 @@ export class RelayStore {
       await this._addConversation(conversation);
     }
 
+    try {
+      await this.client.migratePublicContacts();
+    } catch (e) {
+      console.error("Error migrating public contacts", e);
+    }
     await this.fetchAllContacts();
+    await this.fetchContactGroups();
+    await this.fetchBlockedAgents();
+
+    this.syncLinkedDevices();
 
     this.client.client.on("signal", async (signal: Signal) => {
       if (!(SignalType.App in signal)) return;
@@ -58,7 +116,36 @@ export class RelayStore {
 
       const payload: RelaySignal = signal[SignalType.App].payload as RelaySignal;
 
-      if (payload.type == "Message") {
+      if (payload.type == "DeviceSyncRequested") {
+        this.syncLinkedDevices();
+        return;
+      }
+
+      if (payload.type == "ContactKeyChanged") {
+        const contact = this.contactData.find((c) =>
+          isEqual(c.data.originalActionHash, payload.original_contact_hash),
+        );
+        toast.error(
+          get(tAny)("contacts.contact_key_changed", { name: contact ? contact.name : "" }),
+        );
+        return;
+      }
+
+      if (payload.type == "ContactProfileChanged") {
+        const contact = this.contactData.find((c) =>
+          isEqual(c.data.originalActionHash, payload.original_contact_hash),
+        );
+        const key =
+          get(contactProfileSync) === "apply"
+            ? "contacts.contact_profile_applied"
+            : "contacts.contact_profile_changed";
+        toast.success(get(tAny)(key, { name: contact ? contact.name : "" }));
+        return;
+      }
+
+      if (payload.type == "Message" || payload.type == "Mentioned") {
+        // The zome doesn't check blocks on signals, as they are kept in another cell
+        if (this.isBlocked(encodeHashToBase64(payload.from))) return;
         const conversation = this.getConversationByCellDnaHash(signal[SignalType.App].cell_id[0]);
 
         const from: AgentPubKey = payload.from;
@@ -67,21 +154,37 @@ export class RelayStore {
           authorKey: encodeHashToBase64(from),
           content: payload.message.content,
           bucket: payload.message.bucket,
-          images: payload.message.images.map(
-            (i: any) =>
-              ({ ...(mapKeys(i, (v, k) => camelCase(k)) as Image), status: "loading" }) as Image,
-          ), // convert snake_case to camelCase
+          attachments: (payload.message.attachments as any[]).map(attachmentFromStruct),
+          forwardedFrom: payload.message.forwarded_from
+            ? encodeHashToBase64(payload.message.forwarded_from.author)
+            : undefined,
+          mentions: (payload.message.mentions || []).map(mentionFromStruct),
+          linkPreview: payload.message.link_preview
+            ? linkPreviewFromStruct(payload.message.link_preview)
+            : undefined,
           status: "confirmed",
           timestamp: new Date(payload.action.hashed.content.timestamp / 1000),
         };
 
         if (conversation && message.authorKey !== this.client.myPubKeyB64) {
-          const sender = conversation.allMembers.find((m) => m.publicKeyB64 == message.authorKey);
+          const sender = conversation.allMembers.find((m) =>
+            conversation.sameUser(m.publicKeyB64, message.authorKey),
+          );
           conversation.addMessage(message);
-          if (!conversation.archived) {
+          // Messages sent from my other devices are shown without a notification,
+          // and ones in archived conversations too unless they mention me
+          const mentioned = payload.type == "Mentioned";
+          if ((!conversation.archived || mentioned) && !this.isMyDevice(message.authorKey)) {
             const msgShort =
               message.content.length > 125 ? message.content.slice(0, 50) + "..." : message.content;
-            if (isMobile()) {
+            if (mentioned) {
+              enqueueNotification(
+                get(tAny)("conversations.mentioned_you", {
+                  name: sender ? sender.firstName + " " + sender.lastName : message.authorKey,
+                }),
+                msgShort,
+              );
+            } else if (isMobile()) {
               enqueueNotification(
                 `${sender ? sender.firstName + " " + sender.lastName : message.authorKey}: ${msgShort}`,
                 message.content,
@@ -92,11 +195,13 @@ export class RelayStore {
                 message.content,
               );
             }
-            conversation.loadImagesForMessage(message); // async load images
+            conversation.loadAttachmentsForMessage(message); // async load attachments
           }
         }
       }
     });
+
+    this.syncContactsFromProfiles();
   }
 
   async _addConversation(convoCellAndConfig: ConversationCellAndConfig) {
@@ -148,6 +253,7 @@ export class RelayStore {
     if (convoCellAndConfig) {
       const conversationStore = await this._addConversation(convoCellAndConfig);
       if (conversationStore) {
+        this.syncLinkedDevices();
         if (initialContacts.length > 0) {
           conversationStore.addContacts(initialContacts);
         }
@@ -161,7 +267,9 @@ export class RelayStore {
     if (!this.client) return null;
     const convoCellAndConfig = await this.client.joinConversation(invitation);
     if (convoCellAndConfig) {
-      return await this._addConversation(convoCellAndConfig);
+      const conversationStore = await this._addConversation(convoCellAndConfig);
+      this.syncLinkedDevices();
+      return conversationStore;
     }
     return null;
   }
@@ -171,6 +279,74 @@ export class RelayStore {
     return await this.client.inviteAgentToConversation(conversationId, agent, role);
   }
 
+  /***** Linked devices ******/
+
+  isMyDevice(agentKey: AgentPubKeyB64) {
+    return agentKey === this.client.myPubKeyB64 || this.linkedDevices.includes(agentKey);
+  }
+
+  // Runs on startup, when a conversation is added and when a linked device signals it linked to
+  // or invited this one, rather than polling every conversation
+  async syncLinkedDevices() {
+    if (this.syncingDevices) {
+      this.deviceSyncPending = true;
+      return;
+    }
+    this.syncingDevices = true;
+    try {
+      do {
+        this.deviceSyncPending = false;
+        await this._syncLinkedDevices();
+      } while (this.deviceSyncPending);
+    } catch (e) {
+      console.error("Error syncing with linked devices", e);
+    } finally {
+      this.syncingDevices = false;
+    }
+  }
+
+  // Publishes device links into the conversations they aren't published in yet, invites the
+  // linked device to those, and joins the conversations linked devices invited this one to
+  async _syncLinkedDevices() {
+    const deviceLinks = await this.client.getMyDeviceLinks();
+    if (deviceLinks.length === 0) return;
+    this.linkedDevices = (await this.client.getLinkedDevices(this.client.myPubKey)).map((k) =>
+      encodeHashToBase64(k),
+    );
+
+    for (const deviceLink of deviceLinks) {
+      const device = isEqual(deviceLink.device, this.client.myPubKey)
+        ? deviceLink.linked_to
+        : deviceLink.device;
+      const synced = get(syncedDeviceLinks);
+      const unsynced = this.conversationsData.filter(
+        (conversation) => !synced.includes(deviceLinkKey(conversation.id, deviceLink)),
+      );
+      if (unsynced.length === 0) continue;
+
+      const invites = await this.client.getConversationInvites(device);
+      for (const conversation of unsynced) {
+        await this.client.publishDeviceLink(conversation.id, deviceLink);
+        if (!invites.some((i) => i.network_seed === conversation.id)) {
+          const inviteCode = await conversation.inviteCodeForLinkedDevice(deviceLink);
+          await this.client.inviteLinkedDevice({
+            for_device: device,
+            network_seed: conversation.id,
+            invite_code: inviteCode,
+          });
+        }
+        syncedDeviceLinks.update((keys) => [...keys, deviceLinkKey(conversation.id, deviceLink)]);
+      }
+    }
+
+    const invites = await this.client.getConversationInvites(this.client.myPubKey);
+    for (const invite of invites) {
+      if (this.getConversation(invite.network_seed)) continue;
+      const invitation = decode(Base64.toUint8Array(invite.invite_code)) as Invitation;
+      await this.joinConversation(invitation);
+    }
+  }
+
   getConversation(id: string): ConversationStore | undefined {
     let foundConversation;
     this.conversations.subscribe((conversations) => {
@@ -205,6 +381,8 @@ export class RelayStore {
           contact.last_name,
           contactRecord.original_action,
           encodeHashToBase64(contact.public_key),
+          undefined,
+          !!contact.verification,
         );
       }),
     );
@@ -246,6 +424,9 @@ export class RelayStore {
 
   async updateContact(contact: Contact) {
     if (!this.client) return false;
+    const previous = this.contactData.find((c) =>
+      isEqual(c.data.originalActionHash, contact.originalActionHash),
+    );
     const contactResult = await this.client.updateContact(contact);
     if (contactResult) {
       const contactStore = new ContactStore(
@@ -256,9 +437,12 @@ export class RelayStore {
         contact.lastName,
         contact.originalActionHash,
         contact.publicKeyB64,
+        undefined,
+        // A verification is dropped when the key changes
+        !!previous?.data.verified && previous.publicKeyB64 === contact.publicKeyB64,
       );
       this.contacts.update((contacts) => [
-        ...contacts.filter((c) => c.publicKeyB64 !== contact.publicKeyB64),
+        ...contacts.filter((c) => c !== previous && c.publicKeyB64 !== contact.publicKeyB64),
         contactStore,
       ]);
       return contactStore;
@@ -266,6 +450,145 @@ export class RelayStore {
     return false;
   }
 
+  async verifyContact(contact: Contact) {
+    const contactResult = await this.client.verifyContact(contact);
+    const contactStore = this.getContact(contact.publicKeyB64);
+    contactStore?.update({
+      currentActionHash: contactResult.signed_action.hashed.hash,
+      verified: true,
+    });
+    return contactStore;
+  }
+
+  async importContactsVcard(vcard: string) {
+    const result = await this.client.importContactsVcard(vcard);
+    if (result.imported > 0) await this.fetchAllContacts();
+    return result;
+  }
+
+  /***** Contact profile sync ******/
+  async syncContactsFromProfiles() {
+    const sync = get(contactProfileSync);
+    if (sync === "off") return;
+    try {
+      const changes = await this.client.syncContactsFromProfiles(sync === "apply");
+      if (sync === "apply") {
+        if (changes.length > 0) await this.fetchAllContacts();
+      } else {
+        this.profileChanges.set(changes);
+      }
+    } catch (e) {
+      console.error("Error syncing contacts from profiles", e);
+    }
+  }
+
+  profileChangeFor(contact: Contact) {
+    return get(this.profileChanges).find((change) =>
+      isEqual(change.original_contact_hash, contact.originalActionHash),
+    );
+  }
+
+  async applyProfileChange(contact: Contact) {
+    const change = this.profileChangeFor(contact);
+    if (!change) return false;
+    const contactStore = await this.updateContact({
+      ...contact,
+      firstName: change.updated_contact.first_name,
+      lastName: change.updated_contact.last_name,
+      avatar: change.updated_contact.avatar,
+    });
+    this.profileChanges.update((changes) => changes.filter((c) => c !== change));
+    return contactStore;
+  }
+
+  /***** Blocked agents ******/
+  async fetchBlockedAgents() {
+    const blocked = await this.client.getBlockedAgents();
+    this.blockedAgents.set(blocked.map((k) => encodeHashToBase64(k)));
+  }
+
+  async blockAgent(publicKeyB64: AgentPubKeyB64) {
+    await this.client.blockAgent(publicKeyB64);
+    this.blockedAgents.update((blocked) => [
+      ...blocked.filter((k) => k !== publicKeyB64),
+      publicKeyB64,
+    ]);
+  }
+
+  async unblockAgent(publicKeyB64: AgentPubKeyB64) {
+    await this.client.unblockAgent(publicKeyB64);
+    this.blockedAgents.update((blocked) => blocked.filter((k) => k !== publicKeyB64));
+  }
+
+  isBlocked(publicKeyB64: AgentPubKeyB64) {
+    return get(this.blockedAgents).includes(publicKeyB64);
+  }
+
+  /***** Contact groups ******/
+  async fetchContactGroups() {
+    const groupRecords = await this.client.getAllContactGroups();
+    this.contactGroups.set(
+      groupRecords
+        .filter((groupRecord: any) => groupRecord.group)
+        .map((groupRecord: any) => ({
+          originalActionHash: groupRecord.original_action,
+          currentActionHash: groupRecord.signed_action.hashed.hash,
+          name: groupRecord.group.name,
+          members: groupRecord.group.members,
+        })),
+    );
+  }
+
+  async createContactGroup(name: string, contacts: Contact[]) {
+    const members = contacts
+      .map((c) => c.originalActionHash)
+      .filter((hash): hash is Uint8Array => !!hash);
+    const record = await this.client.createContactGroup(name, members);
+    const group: ContactGroup = {
+      originalActionHash: record.signed_action.hashed.hash,
+      currentActionHash: record.signed_action.hashed.hash,
+      name,
+      members,
+    };
+    this.contactGroups.update((groups) => [...groups, group]);
+    return group;
+  }
+
+  async updateContactGroup(group: ContactGroup) {
+    const record = await this.client.updateContactGroup(group);
+    const updated = { ...group, currentActionHash: record.signed_action.hashed.hash };
+    this.contactGroups.update((groups) =>
+      groups.map((g) => (isEqual(g.originalActionHash, group.originalActionHash) ? updated : g)),
+    );
+    return updated;
+  }
+
+  async deleteContactGroup(group: ContactGroup) {
+    await this.client.deleteContactGroup(group);
+    this.contactGroups.update((groups) =>
+      groups.filter((g) => !isEqual(g.originalActionHash, group.originalActionHash)),
+    );
+  }
+
+  // Creates a private conversation with every member of a group and generates their invite codes
+  async createConversationFromGroup(group: ContactGroup) {
+    const contactRecords = await this.client.getContactsInGroup(group);
+    const contacts = contactRecords
+      .map((contactRecord: any) =>
+        this.getContact(encodeHashToBase64(contactRecord.contact.public_key)),
+      )
+      .filter((contact: ContactStore | undefined) => !!contact)
+      .map((contact: ContactStore) => contact.data);
+    const conversation = await this.createConversation(group.name, "", Privacy.Private, contacts);
+    if (!conversation) return null;
+    const inviteCodes: { [key: AgentPubKeyB64]: string } = {};
+    for (const contact of contacts) {
+      const inviteCode = await conversation.inviteCodeForAgent(contact.publicKeyB64);
+      if (inviteCode) inviteCodes[contact.publicKeyB64] = inviteCode;
+    }
+    return { conversation, inviteCodes };
+  }
+
   getContact(publicKey: AgentPubKeyB64): ContactStore | undefined {
     let foundContact;
     this.contacts.subscribe((contacts) => {
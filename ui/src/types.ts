@@ -12,6 +12,7 @@ import type {
   DeleteLink,
   MembraneProof,
   ClonedCell,
+  Signature,
 } from "@holochain/client";
 
 import type { Profile } from "@holochain-open-dev/profiles";
@@ -48,8 +49,41 @@ export type RelaySignal =
       type: "LinkDeleted";
       action: SignedActionHashed<DeleteLink>;
       link_type: string;
+    }
+  | {
+      type: "ContactKeyChanged";
+      original_contact_hash: ActionHash;
+      previous_key: AgentPubKey;
+      new_key: AgentPubKey;
+    }
+  | {
+      type: "ContactProfileChanged";
+      original_contact_hash: ActionHash;
+      updated_contact: ContactEntry;
+    }
+  | {
+      type: "DeviceSyncRequested";
+      from: AgentPubKey;
     };
 
+// A contact as stored by the relay zome
+export interface ContactEntry {
+  public_key: AgentPubKey;
+  first_name: string;
+  last_name: string;
+  avatar: string;
+}
+
+// Whether contacts follow the profiles of their agents
+export type ContactProfileSync = "off" | "propose" | "apply";
+
+// A proposed update of a contact to its agent's latest profile
+export interface ContactProfileChange {
+  original_contact_hash: ActionHash;
+  previous_contact_hash: ActionHash;
+  updated_contact: ContactEntry;
+}
+
 export enum Privacy {
   Private,
   Public,
@@ -60,6 +94,10 @@ export interface Properties {
   created: number;
   privacy: Privacy;
   progenitor: AgentPubKeyB64;
+  max_attachment_size?: number;
+  max_attachments?: number;
+  max_voice_duration_ms?: number;
+  max_voice_size?: number;
 }
 
 export type EntryTypes = { type: "Message" } & MessageInput;
@@ -72,6 +110,25 @@ export interface Contact {
   firstName: string;
   lastName: string;
   publicKeyB64: AgentPubKeyB64;
+  verified?: boolean; // whether the safety numbers were compared with this contact
+}
+
+// A named set of contacts, referenced by the actions that created them
+export interface ContactGroup {
+  originalActionHash: ActionHash;
+  currentActionHash: ActionHash;
+  name: string;
+  members: ActionHash[];
+}
+
+// A contact code signed by the key it is for, see `create_contact_code`
+export interface ContactCode {
+  data: {
+    public_key: AgentPubKey;
+    first_name: string;
+    last_name: string;
+  };
+  signature: Signature;
 }
 
 export interface MessageInput {
@@ -90,6 +147,7 @@ export interface Conversation {
   progenitor: AgentPubKey;
   messages: Messages;
   agentProfiles: { [key: AgentPubKeyB64]: Profile };
+  linkedDevices: { [key: AgentPubKeyB64]: AgentPubKeyB64[] }; // other devices of the same user, for each member
 }
 
 export interface LocalConversationData {
@@ -104,6 +162,31 @@ export interface MembraneProofData {
   as_role: number;
 }
 
+// Ties two devices of one user together, signed by both
+export interface DeviceLink {
+  device: AgentPubKey;
+  linked_to: AgentPubKey;
+  device_signature: Signature;
+  linked_to_signature: Signature;
+}
+
+export interface DeviceLinkRequestRecord {
+  action_hash: ActionHash;
+  request: {
+    device: AgentPubKey;
+    linked_to: AgentPubKey;
+    device_signature: Signature;
+  };
+}
+
+// An invite code one device leaves in the device sync cell for another
+export interface ConversationInvite {
+  for_device: AgentPubKey;
+  network_seed: string;
+  invite_code: string;
+  device_link?: ActionHash | null; // Filled in by the zome
+}
+
 export interface Invitation {
   created: number;
   networkSeed: string;
@@ -114,15 +197,30 @@ export interface Invitation {
 }
 
 // Holochain Type
-export interface ImageStruct {
+export interface FileStruct {
   last_modified: number;
   name: string;
   size: number;
   storage_entry_hash: EntryHash;
   file_type: string;
+  thumbnail_storage_entry_hash?: EntryHash | null;
+  content_hash?: string | null;
+}
+
+export type AttachmentKind = "Image" | "Audio" | "Voice" | "Video" | "Document";
+
+// Holochain Type
+export interface AttachmentStruct {
+  type: AttachmentKind;
+  file: FileStruct;
+  width?: number | null;
+  height?: number | null;
+  duration_ms?: number | null;
+  waveform?: number[] | null;
 }
 
-export interface Image {
+export interface Attachment {
+  kind: AttachmentKind;
   dataURL?: string;
   fileType: string;
   file?: File;
@@ -130,7 +228,46 @@ export interface Image {
   lastModified: number;
   size: number;
   storageEntryHash?: EntryHash;
-  status?: "loading" | "loaded" | "pending" | "error"; // Pending = not yet sent to holochain, loading = loading from holochain, loaded = loaded from holochain, error = failed to load
+  thumbnailStorageEntryHash?: EntryHash;
+  width?: number;
+  height?: number;
+  // The downloaded thumbnail of an image, shown until the original is loaded. Never sent.
+  thumbnailDataURL?: string;
+  durationMs?: number;
+  waveform?: number[];
+  transferId?: string; // The id of the upload of a file that is being sent
+  status?: "loading" | "loaded" | "pending" | "preview" | "error"; // Pending = not yet sent to holochain, loading = loading from holochain, loaded = loaded from holochain, preview = only the thumbnail is loaded, error = failed to load
+}
+
+// Holochain Type
+export interface MentionStruct {
+  agent: AgentPubKey;
+  start: number; // In characters
+  end: number;
+  membership?: ActionHash | null; // Added by the zome
+}
+
+// An @name span of a message's content
+export interface Mention {
+  agent: AgentPubKeyB64;
+  start: number;
+  end: number;
+}
+
+// Holochain Type
+export interface LinkPreviewStruct {
+  url: string;
+  title: string;
+  description: string;
+  image: FileStruct | null;
+}
+
+export interface LinkPreview {
+  url: string;
+  title: string;
+  description: string;
+  image?: FileStruct;
+  imageDataURL?: string; // Set once the image is loaded
 }
 
 export interface Message {
@@ -140,11 +277,14 @@ export interface Message {
   avatar?: string; // Used in the UI to display the author's avatar
   content: string;
   header?: string; // an optional header to display above this message in the conversation UI
-  images: Image[];
+  attachments: Attachment[];
   hideDetails?: boolean; // Used in the UI to toggle the display of the message details
   status?: "pending" | "confirmed" | "delivered" | "read"; // status of the message
   timestamp: Date;
   bucket: number;
+  forwardedFrom?: AgentPubKeyB64; // The author of the original message, if this one was forwarded
+  mentions?: Mention[];
+  linkPreview?: LinkPreview;
 }
 
 export type BucketInput = {